@@ -20,6 +20,10 @@ impl<T, U> Rect<T, U> {
         Self(euclid::Box2D::from_origin_and_size(origin, extent))
     }
 
+    pub fn from_points(top_left: Point<T, U>, bottom_right: Point<T, U>) -> Self {
+        Self(euclid::Box2D::new(top_left, bottom_right))
+    }
+
     pub fn left(&self) -> T
     where
         T: Copy,
@@ -96,6 +100,23 @@ impl<T, U> Rect<T, U> {
     {
         self.0.intersection(&rhs.0).map(|r| Rect(r))
     }
+
+    /// Whether any point is inside both rects. Rects that only share an edge
+    /// don't intersect.
+    pub fn intersects(&self, rhs: &Rect<T, U>) -> bool
+    where
+        T: Copy + PartialOrd,
+    {
+        self.0.intersects(&rhs.0)
+    }
+
+    /// The smallest rect containing both rects. Empty rects are ignored.
+    pub fn union(&self, rhs: &Rect<T, U>) -> Rect<T, U>
+    where
+        T: Copy + PartialOrd,
+    {
+        Rect(self.0.union(&rhs.0))
+    }
 }
 
 impl<T, U> Add<Offset<T, U>> for Rect<T, U>
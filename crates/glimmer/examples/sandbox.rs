@@ -1,99 +1,25 @@
-use std::{cell::RefCell, collections::HashMap, rc::Rc};
+use std::{
+    rc::Rc,
+    time::{Duration, Instant},
+};
 
 use geometry::{Extent, Point, Rect, ScreenPx};
 use plinth::{
     graphics::{
-        BottomLeft, BottomRight, Color, DrawRect, GraphicsConfig, GraphicsContext, Image, Left,
-        PixelBuffer, RenderGraph, RenderGraphNodeId, Right, Surface, TopLeft, TopRight,
+        BottomLeft, BottomRight, Color, DrawRect, GraphicsConfig, GraphicsContext, ImageLoader,
+        ImageLoaderConfig, Left, RenderGraph, RenderGraphNodeId, Right, Surface, TopLeft, TopRight,
     },
     input::{ButtonState, MouseButton, VirtualKeyCode},
     window::{Window, WindowDesc, WindowFlags, WindowHandler, WindowSpawner},
 };
 
-#[derive(Clone)]
-struct IoRequester {
-    sender: crossbeam::channel::Sender<String>,
-    receiver: crossbeam::channel::Receiver<(String, Option<PixelBuffer>)>,
-}
-
-struct ImageCache {
-    cache: RefCell<HashMap<String, ImageState>>,
-    // default_image: Image,
-    io_requester: IoRequester,
-}
-
-#[derive(Clone, Copy)]
-enum ImageState {
-    Pending,
-    Ready(Image),
-}
-
-impl ImageCache {
-    fn new(io_requester: IoRequester) -> Self {
-        Self {
-            cache: RefCell::new(HashMap::new()),
-            // default_image: Image::new(),
-            io_requester,
-        }
-    }
-
-    fn get(&self, graphics: &GraphicsContext, path: &str) -> Option<Image> {
-        let image = self.cache.borrow().get(path).cloned();
-
-        match image {
-            Some(ImageState::Ready(image)) => Some(image),
-            Some(ImageState::Pending) => {
-                let mut found = None;
-
-                while let Ok((p, Some(buf))) = self.io_requester.receiver.try_recv() {
-                    let image = graphics.upload_image(buf.as_ref());
-
-                    if p == path {
-                        found = Some(image);
-                    }
-
-                    self.cache.borrow_mut().insert(p, ImageState::Ready(image));
-                }
-
-                found
-            }
-            None => {
-                self.cache
-                    .borrow_mut()
-                    .insert(path.to_string(), ImageState::Pending);
-                self.io_requester.sender.send(path.to_string()).unwrap();
-                None
-            }
-        }
-    }
-}
-
-fn spawn_loader_thread() -> IoRequester {
-    let (request_sender, request_receiver) = crossbeam::channel::unbounded();
-    let (result_sender, result_receiver) = crossbeam::channel::unbounded();
-
-    // Thread will be dropped automatically when the request sender is dropped.
-    let _thread = std::thread::spawn(move || {
-        while let Ok(request) = request_receiver.recv() {
-            let buf = PixelBuffer::from_file(&std::fs::read(&request).unwrap());
-            let Ok(_) = result_sender.send((request, Some(buf))) else { break; };
-        }
-    });
-
-    IoRequester {
-        sender: request_sender,
-        receiver: result_receiver,
-    }
-}
-
 fn main() {
     let graphics = Rc::new(GraphicsContext::new(&GraphicsConfig {
         debug_mode: true,
         ..Default::default()
     }));
 
-    let io_requester = spawn_loader_thread();
-    let image_cache = Rc::new(ImageCache::new(io_requester));
+    let image_loader = Rc::new(ImageLoader::new(&ImageLoaderConfig::default()));
 
     let main_window = WindowDesc {
         title: "Sandbox",
@@ -104,7 +30,7 @@ fn main() {
         flags: WindowFlags::VISIBLE | WindowFlags::RESIZABLE,
         handler: &mut |window| {
             let surface = graphics.create_surface(&window);
-            AppWindow::new(window, surface, graphics.clone(), image_cache.clone())
+            AppWindow::new(window, surface, graphics.clone(), image_loader.clone())
         },
     };
 
@@ -115,7 +41,7 @@ struct AppWindow {
     window: Window,
     surface: Surface,
     graphics: Rc<GraphicsContext>,
-    image_cache: Rc<ImageCache>,
+    image_loader: Rc<ImageLoader>,
 }
 
 impl AppWindow {
@@ -123,13 +49,16 @@ impl AppWindow {
         window: Window,
         surface: Surface,
         graphics: Rc<GraphicsContext>,
-        image_cache: Rc<ImageCache>,
+        image_loader: Rc<ImageLoader>,
     ) -> Self {
+        // Images load in the background, so keep drawing.
+        window.request_animation_frame();
+
         Self {
             window,
             surface,
             graphics,
-            image_cache,
+            image_loader,
         }
     }
 }
@@ -189,7 +118,7 @@ impl WindowHandler for AppWindow {
                                 window,
                                 surface,
                                 self.graphics.clone(),
-                                self.image_cache.clone(),
+                                self.image_loader.clone(),
                             )
                         },
                     });
@@ -222,7 +151,16 @@ impl WindowHandler for AppWindow {
 
     fn on_idle(&mut self, _spawner: &mut dyn WindowSpawner<Self>) {
         // no-op
+    }
+
+    fn on_animation_frame(
+        &mut self,
+        _spawner: &mut dyn WindowSpawner<Self>,
+        _timestamp: Instant,
+        _frame_interval: Duration,
+    ) {
         self.window.request_redraw();
+        self.window.request_animation_frame();
     }
 
     fn on_redraw(&mut self, _control: &mut dyn WindowSpawner<Self>) {
@@ -230,7 +168,19 @@ impl WindowHandler for AppWindow {
 
         let mut render_graph = RenderGraph::new();
 
-        let _i = self.image_cache.get(&self.graphics, "D:/test.png");
+        self.image_loader.poll(&self.graphics);
+
+        if let Some(image) = self.image_loader.image("D:/test.png") {
+            render_graph.draw_rect(
+                RenderGraphNodeId::root(),
+                &DrawRect::new(Rect::new(
+                    Point::new(100.0, 100.0),
+                    Extent::new(256.0, 256.0),
+                ))
+                .with_color(Color::WHITE)
+                .with_image(image),
+            );
+        }
 
         render_graph.draw_rect(
             RenderGraphNodeId::root(),
@@ -262,7 +212,9 @@ impl WindowHandler for AppWindow {
             ]),
         );
 
-        self.graphics.draw(&image, &render_graph);
+        self.graphics
+            .draw(&image, &render_graph)
+            .expect("failed to draw frame");
 
         self.graphics.present(&mut self.surface);
     }
@@ -1,3 +1,5 @@
+use std::time::{Duration, Instant};
+
 use geometry::{Extent, Point, ScreenPx};
 use plinth::{
     input::{ButtonState, MouseButton, VirtualKeyCode},
@@ -126,4 +128,13 @@ impl WindowHandler for AppWindow {
     fn on_redraw(&mut self, _control: &mut dyn WindowSpawner<Self>) {
         // no-op
     }
+
+    fn on_animation_frame(
+        &mut self,
+        _control: &mut dyn WindowSpawner<Self>,
+        _timestamp: Instant,
+        _frame_interval: Duration,
+    ) {
+        // no-op
+    }
 }
@@ -21,6 +21,12 @@ fn compile_shaders() {
         s!("vertex_main"),
         "rect_vs.cso",
     );
+    compile(
+        w!("shaders/rounded_rect.hlsl"),
+        ShaderKind::Vertex,
+        s!("rect_instance_main"),
+        "rect_instance_vs.cso",
+    );
     compile(
         w!("shaders/rounded_rect.hlsl"),
         ShaderKind::Pixel,
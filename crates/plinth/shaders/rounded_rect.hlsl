@@ -1,14 +1,36 @@
 #define RS "RootFlags(ALLOW_INPUT_ASSEMBLER_INPUT_LAYOUT), \
-                       RootConstants(num32BitConstants = 2, b0)"
+                       RootConstants(num32BitConstants = 4, b0), \
+                       DescriptorTable(SRV(t0), visibility = SHADER_VISIBILITY_PIXEL), \
+                       RootConstants(num32BitConstants = 4, b1, visibility = SHADER_VISIBILITY_VERTEX), \
+                       StaticSampler(s0, \
+                                     filter = FILTER_MIN_MAG_MIP_LINEAR, \
+                                     addressU = TEXTURE_ADDRESS_CLAMP, \
+                                     addressV = TEXTURE_ADDRESS_CLAMP, \
+                                     visibility = SHADER_VISIBILITY_PIXEL)"
 
 struct DrawConstants
 {
     uint screen_width;
     uint screen_height;
+    // Moves everything drawn, for drawing display lists.
+    float2 offset;
+};
+
+struct ImageConstants
+{
+    // Maps texture coordinates onto the image's region of the texture, for
+    // images that share a texture (atlas).
+    float2 uv_offset;
+    float2 uv_scale;
 };
 
 // Constants set by the root signature
 ConstantBuffer<DrawConstants> draw_constants : register(b0);
+ConstantBuffer<ImageConstants> image_constants : register(b1);
+
+// Images are stored with premultiplied alpha.
+Texture2D<float4> image : register(t0);
+SamplerState image_sampler : register(s0);
 
 struct VsInput
 {
@@ -18,6 +40,7 @@ struct VsInput
     float4 outer_radius : OUTER_RADIUS;
     float4 inner_radius : INNER_RADIUS;
     float4 color : COLOR;
+    float2 uv : TEXCOORD;
 };
 
 struct VsOutput
@@ -28,23 +51,58 @@ struct VsOutput
     float4 outer_radius : OUTER_RADIUS;
     float4 inner_radius : INNER_RADIUS;
     float4 color : COLOR;
+    float2 uv : TEXCOORD;
 };
 
 [RootSignature(RS)]
 VsOutput vertex_main(VsInput input)
 {
+    float2 position = input.position + draw_constants.offset;
+
     VsOutput output;
-    output.position = float4((input.position.x / draw_constants.screen_width) * 2.0f - 1.0f,
-                             ((draw_constants.screen_height - input.position.y) / draw_constants.screen_height) * 2.0f - 1.0f,
+    output.position = float4((position.x / draw_constants.screen_width) * 2.0f - 1.0f,
+                             ((draw_constants.screen_height - position.y) / draw_constants.screen_height) * 2.0f - 1.0f,
                              0.0f, 1.0f);
     output.rect_size = input.rect_size;
-    output.rect_center = input.rect_center;
+    output.rect_center = input.rect_center + draw_constants.offset;
     output.outer_radius = input.outer_radius;
     output.inner_radius = input.inner_radius;
     output.color = input.color;
+    output.uv = input.uv * image_constants.uv_scale + image_constants.uv_offset;
     return output;
 }
 
+struct RectInstance
+{
+    // left, top, right, bottom
+    float4 rect : RECT;
+    float4 outer_radius : OUTER_RADIUS;
+    float4 inner_radius : INNER_RADIUS;
+    // top-left, top-right, bottom-right, bottom-left
+    float4 colors[4] : COLOR;
+    float2 uvs[4] : TEXCOORD;
+};
+
+// The corner of the rect for each vertex of its two triangles.
+static const uint rect_corners[6] = { 0, 1, 2, 0, 2, 3 };
+
+[RootSignature(RS)]
+VsOutput rect_instance_main(RectInstance input, uint vertex_id : SV_VertexID)
+{
+    uint corner = rect_corners[vertex_id];
+
+    VsInput vertex;
+    vertex.position = float2(corner == 1 || corner == 2 ? input.rect.z : input.rect.x,
+                             corner >= 2 ? input.rect.w : input.rect.y);
+    vertex.rect_size = input.rect.zw - input.rect.xy;
+    vertex.rect_center = (input.rect.xy + input.rect.zw) / 2;
+    vertex.outer_radius = input.outer_radius;
+    vertex.inner_radius = input.inner_radius;
+    vertex.color = input.colors[corner];
+    vertex.uv = input.uvs[corner];
+    return vertex_main(vertex);
+}
+
 float4 pixel_main(VsOutput input) : SV_TARGET
 {
     // Compute the position of the pixel relative to the center of the rect.
@@ -71,7 +129,10 @@ float4 pixel_main(VsOutput input) : SV_TARGET
     float w = 0.5 * fwidth(distance);
     w *= 1.1f;
 
-    float4 color = lerp(float4(0.0, 0.0, 0.0, 0.0), input.color, smoothstep(w, -w, distance));
+    // Vertex colors have straight alpha, so premultiply them before blending
+    // with the (premultiplied) image.
+    float4 color = float4(input.color.rgb * input.color.a, input.color.a);
+    color *= image.Sample(image_sampler, input.uv);
 
-    return color;
+    return color * smoothstep(w, -w, distance);
 }
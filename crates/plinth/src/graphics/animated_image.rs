@@ -0,0 +1,510 @@
+use std::time::Duration;
+
+use geometry::{Extent, Point, Px, Rect};
+
+use super::{
+    image_format::{self, DecodeError},
+    Color, ColorSpace, PixelBuffer, PixelBufferRef, PixelFormat,
+};
+
+/// Frame delays at or below this are replaced with [`DEFAULT_FRAME_DELAY`].
+/// Browsers do the same, and many GIFs in the wild rely on it.
+const MIN_FRAME_DELAY: Duration = Duration::from_millis(10);
+const DEFAULT_FRAME_DELAY: Duration = Duration::from_millis(100);
+
+/// How many times an animation plays before stopping on its last frame.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Repeat {
+    Infinite,
+    Finite(u32),
+}
+
+/// A fully composited frame of an animation.
+#[derive(Clone)]
+pub struct AnimationFrame {
+    pixels: PixelBuffer,
+    delay: Duration,
+}
+
+impl AnimationFrame {
+    /// The contents of the whole canvas while this frame is shown, as straight
+    /// alpha RGBA8.
+    #[must_use]
+    pub fn pixels(&self) -> PixelBufferRef {
+        self.pixels.as_ref()
+    }
+
+    /// How long this frame is shown for.
+    #[must_use]
+    pub fn delay(&self) -> Duration {
+        self.delay
+    }
+}
+
+/// A decoded APNG or GIF animation.
+///
+/// Frames are composited ahead of time (applying each frame's blend and
+/// disposal operations), so that any frame can be uploaded and drawn as an
+/// ordinary [`Image`](super::Image) without reference to the ones before it.
+#[derive(Clone)]
+pub struct AnimatedImage {
+    width: u32,
+    height: u32,
+    frames: Vec<AnimationFrame>,
+    repeat: Repeat,
+    duration: Duration,
+}
+
+impl AnimatedImage {
+    /// Decodes an APNG or GIF file.
+    ///
+    /// Panics if the file cannot be decoded. See [`Self::decode`] for a
+    /// fallible version.
+    #[must_use]
+    pub fn from_file(bytes: &[u8]) -> Self {
+        Self::decode(bytes).expect("failed to decode animation")
+    }
+
+    /// Decodes an APNG or GIF file, detected by its signature. Non-animated
+    /// PNGs produce a single frame.
+    pub fn decode(bytes: &[u8]) -> Result<Self, DecodeError> {
+        if bytes.starts_with(image_format::PNG_SIGNATURE) {
+            decode_png(bytes)
+        } else if bytes.starts_with(b"GIF87a") || bytes.starts_with(b"GIF89a") {
+            decode_gif(bytes)
+        } else {
+            Err(DecodeError::UnknownFormat)
+        }
+    }
+
+    #[inline]
+    #[must_use]
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    #[inline]
+    #[must_use]
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    #[inline]
+    #[must_use]
+    pub fn frames(&self) -> &[AnimationFrame] {
+        &self.frames
+    }
+
+    #[inline]
+    #[must_use]
+    pub fn repeat(&self) -> Repeat {
+        self.repeat
+    }
+
+    /// The time it takes to play every frame once.
+    #[inline]
+    #[must_use]
+    pub fn duration(&self) -> Duration {
+        self.duration
+    }
+
+    /// The index of the frame to show `time` after the animation started.
+    /// Finite animations stop on their last frame.
+    #[must_use]
+    pub fn frame_index_at(&self, time: Duration) -> usize {
+        let last = self.frames.len() - 1;
+
+        if last == 0 {
+            return 0;
+        }
+
+        let duration = self.duration.as_nanos();
+        let plays = time.as_nanos() / duration;
+
+        if let Repeat::Finite(count) = self.repeat {
+            if plays >= u128::from(count) {
+                return last;
+            }
+        }
+
+        let mut remaining = time.as_nanos() % duration;
+        for (index, frame) in self.frames.iter().enumerate() {
+            let delay = frame.delay.as_nanos();
+            if remaining < delay {
+                return index;
+            }
+            remaining -= delay;
+        }
+
+        last
+    }
+
+    /// The frame to show `time` after the animation started.
+    #[must_use]
+    pub fn frame_at(&self, time: Duration) -> &AnimationFrame {
+        &self.frames[self.frame_index_at(time)]
+    }
+}
+
+/// What happens to a frame's region of the canvas once the frame is done.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Disposal {
+    /// Leave it as-is.
+    None,
+    /// Clear it to transparent black.
+    Background,
+    /// Restore it to what it was before the frame was drawn.
+    Previous,
+}
+
+/// How a frame is drawn onto the canvas.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Blend {
+    /// Replace the canvas with the frame.
+    Source,
+    /// Alpha-blend the frame over the canvas.
+    Over,
+}
+
+/// A (possibly partial) frame, as stored in the file.
+struct Subframe<'a> {
+    pixels: PixelBufferRef<'a>,
+    position: Point<u32, Px>,
+    delay: Duration,
+    disposal: Disposal,
+    blend: Blend,
+}
+
+struct Compositor {
+    canvas: PixelBuffer,
+    frames: Vec<AnimationFrame>,
+}
+
+impl Compositor {
+    fn new(width: u32, height: u32) -> Self {
+        Self {
+            canvas: PixelBuffer::new(width, height, PixelFormat::Rgba8, ColorSpace::Srgb),
+            frames: Vec::new(),
+        }
+    }
+
+    fn push(&mut self, subframe: &Subframe) {
+        let canvas_rect = Rect::new(
+            Point::zero(),
+            Extent::new(self.canvas.width(), self.canvas.height()),
+        );
+        let region = Rect::new(
+            subframe.position,
+            Extent::new(subframe.pixels.width(), subframe.pixels.height()),
+        )
+        .intersection(&canvas_rect);
+
+        let previous = match (subframe.disposal, region) {
+            (Disposal::Previous, Some(region)) => Some(self.canvas.crop(region)),
+            _ => None,
+        };
+
+        if let Some(region) = region {
+            let src = subframe
+                .pixels
+                .sub_view(Rect::new(Point::zero(), region.extent()));
+            let mut dst = self.canvas.sub_view_mut(region);
+
+            match subframe.blend {
+                Blend::Source => dst.copy_from(src),
+                Blend::Over => {
+                    for (dst_row, src_row) in dst.rows_mut().zip(src.rows()) {
+                        for (dst, src) in dst_row.chunks_exact_mut(4).zip(src_row.chunks_exact(4)) {
+                            let color = blend_over(
+                                PixelFormat::Rgba8.read_color(src),
+                                PixelFormat::Rgba8.read_color(dst),
+                            );
+                            let (_, bytes) = PixelFormat::Rgba8.write_color(color);
+                            dst.copy_from_slice(&bytes[..4]);
+                        }
+                    }
+                }
+            }
+        }
+
+        let delay = if subframe.delay <= MIN_FRAME_DELAY {
+            DEFAULT_FRAME_DELAY
+        } else {
+            subframe.delay
+        };
+
+        self.frames.push(AnimationFrame {
+            pixels: self.canvas.clone(),
+            delay,
+        });
+
+        if let Some(region) = region {
+            match subframe.disposal {
+                Disposal::None => {}
+                Disposal::Background => self
+                    .canvas
+                    .sub_view_mut(region)
+                    .fill(Color::new(0.0, 0.0, 0.0, 0.0)),
+                Disposal::Previous => {
+                    let previous = previous.expect("previous region was saved");
+                    self.canvas
+                        .sub_view_mut(region)
+                        .copy_from(previous.as_ref());
+                }
+            }
+        }
+    }
+
+    fn finish(self, repeat: Repeat) -> Result<AnimatedImage, DecodeError> {
+        if self.frames.is_empty() {
+            return Err(DecodeError::Malformed("animation has no frames".into()));
+        }
+
+        Ok(AnimatedImage {
+            width: self.canvas.width(),
+            height: self.canvas.height(),
+            duration: self.frames.iter().map(|frame| frame.delay).sum(),
+            frames: self.frames,
+            repeat,
+        })
+    }
+}
+
+/// Porter-Duff 'over' for straight alpha colors.
+fn blend_over(src: Color, dst: Color) -> Color {
+    let src = src.premultiplied();
+    let dst = dst.premultiplied();
+    let inv_alpha = 1.0 - src.a;
+
+    Color::new(
+        src.r + dst.r * inv_alpha,
+        src.g + dst.g * inv_alpha,
+        src.b + dst.b * inv_alpha,
+        src.a + dst.a * inv_alpha,
+    )
+    .unpremultiplied()
+}
+
+fn decode_png(bytes: &[u8]) -> Result<AnimatedImage, DecodeError> {
+    let mut decoder = png::Decoder::new(bytes);
+    decoder.set_transformations(png::Transformations::normalize_to_color8());
+    let mut reader = decoder.read_info()?;
+
+    let (width, height) = reader.info().size();
+    let (color_type, _) = reader.output_color_type();
+    let animation = reader.info().animation_control().copied();
+
+    let mut buffer = vec![0; reader.output_buffer_size()];
+    let mut compositor = Compositor::new(width, height);
+
+    // The default image is only part of the animation if it has a frame
+    // control chunk. Otherwise, the animation starts with the first subframe.
+    let num_frames = animation.map_or(1, |animation| animation.num_frames);
+    let mut decoded = 0;
+    let mut is_first = true;
+
+    while decoded < num_frames {
+        let output = reader.next_frame(&mut buffer)?;
+        let frame_control = reader.info().frame_control().copied();
+
+        if std::mem::take(&mut is_first) && animation.is_some() && frame_control.is_none() {
+            continue;
+        }
+
+        let frame_control = frame_control.unwrap_or_default();
+        let pixels = image_format::rgba8_from_png(
+            &buffer[..output.line_size * output.height as usize],
+            output.width,
+            color_type,
+        );
+
+        compositor.push(&Subframe {
+            pixels: pixels.as_ref(),
+            position: Point::new(frame_control.x_offset, frame_control.y_offset),
+            delay: match frame_control.delay_den {
+                // A denominator of 0 means hundredths of a second.
+                0 => Duration::from_millis(u64::from(frame_control.delay_num) * 10),
+                den => Duration::from_secs_f64(f64::from(frame_control.delay_num) / f64::from(den)),
+            },
+            disposal: match frame_control.dispose_op {
+                png::DisposeOp::None => Disposal::None,
+                png::DisposeOp::Background => Disposal::Background,
+                png::DisposeOp::Previous => Disposal::Previous,
+            },
+            blend: match frame_control.blend_op {
+                png::BlendOp::Source => Blend::Source,
+                png::BlendOp::Over => Blend::Over,
+            },
+        });
+
+        decoded += 1;
+    }
+
+    let repeat = match animation {
+        Some(png::AnimationControl { num_plays, .. }) if num_plays > 0 => Repeat::Finite(num_plays),
+        _ => Repeat::Infinite,
+    };
+
+    compositor.finish(repeat)
+}
+
+fn decode_gif(bytes: &[u8]) -> Result<AnimatedImage, DecodeError> {
+    let mut options = gif::DecodeOptions::new();
+    options.set_color_output(gif::ColorOutput::RGBA);
+    let mut decoder = options.read_info(bytes)?;
+
+    let mut compositor = Compositor::new(decoder.width().into(), decoder.height().into());
+
+    while let Some(frame) = decoder.read_next_frame()? {
+        compositor.push(&Subframe {
+            pixels: PixelBufferRef::from_bytes(
+                &frame.buffer,
+                frame.width.into(),
+                PixelFormat::Rgba8,
+                ColorSpace::Srgb,
+            ),
+            position: Point::new(frame.left.into(), frame.top.into()),
+            // GIF delays are in hundredths of a second.
+            delay: Duration::from_millis(u64::from(frame.delay) * 10),
+            disposal: match frame.dispose {
+                gif::DisposalMethod::Any | gif::DisposalMethod::Keep => Disposal::None,
+                gif::DisposalMethod::Background => Disposal::Background,
+                gif::DisposalMethod::Previous => Disposal::Previous,
+            },
+            // Transparent pixels leave the canvas untouched, which is the same
+            // as blending with an alpha of 0.
+            blend: Blend::Over,
+        });
+    }
+
+    // The decoder doesn't expose the NETSCAPE2.0 loop count, and in practice
+    // nearly every animated GIF loops forever.
+    compositor.finish(Repeat::Infinite)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::borrow::Cow;
+
+    use super::*;
+
+    #[test]
+    fn apng_compositing() {
+        let mut out = Vec::new();
+
+        {
+            let mut encoder = png::Encoder::new(&mut out, 2, 2);
+            encoder.set_color(png::ColorType::Rgba);
+            encoder.set_depth(png::BitDepth::Eight);
+            encoder.set_animated(3, 2).unwrap();
+            let mut writer = encoder.write_header().unwrap();
+
+            writer.set_frame_delay(1, 10).unwrap();
+            writer
+                .write_image_data(&[255, 0, 0, 255].repeat(4))
+                .unwrap();
+
+            writer.set_frame_dimension(1, 1).unwrap();
+            writer.set_frame_position(1, 1).unwrap();
+            writer.set_frame_delay(2, 10).unwrap();
+            writer.set_blend_op(png::BlendOp::Over).unwrap();
+            writer.set_dispose_op(png::DisposeOp::Previous).unwrap();
+            writer.write_image_data(&[0, 0, 255, 128]).unwrap();
+
+            writer.set_frame_position(0, 0).unwrap();
+            writer.set_frame_delay(1, 10).unwrap();
+            writer.set_blend_op(png::BlendOp::Source).unwrap();
+            writer.set_dispose_op(png::DisposeOp::None).unwrap();
+            writer.write_image_data(&[0, 255, 0, 255]).unwrap();
+
+            writer.finish().unwrap();
+        }
+
+        let image = AnimatedImage::from_file(&out);
+        assert_eq!(image.frames().len(), 3);
+        assert_eq!(image.repeat(), Repeat::Finite(2));
+        assert_eq!(image.duration(), Duration::from_millis(400));
+
+        let red = [255, 0, 0, 255];
+        assert_eq!(image.frames()[0].pixels().bytes(), red.repeat(4));
+        assert_eq!(
+            image.frames()[1].pixels().bytes(),
+            [red, red, red, [127, 0, 128, 255]].concat()
+        );
+        // The second frame's region is restored before the third is drawn.
+        assert_eq!(
+            image.frames()[2].pixels().bytes(),
+            [[0, 255, 0, 255], red, red, red].concat()
+        );
+
+        assert_eq!(image.frame_index_at(Duration::ZERO), 0);
+        assert_eq!(image.frame_index_at(Duration::from_millis(150)), 1);
+        assert_eq!(image.frame_index_at(Duration::from_millis(350)), 2);
+        assert_eq!(image.frame_index_at(Duration::from_millis(450)), 0);
+        assert_eq!(image.frame_index_at(Duration::from_secs(10)), 2);
+    }
+
+    #[test]
+    fn gif_compositing() {
+        let palette = [255, 0, 0, 0, 255, 0, 0, 0, 0];
+        let mut out = Vec::new();
+
+        {
+            let mut encoder = gif::Encoder::new(&mut out, 2, 1, &palette).unwrap();
+
+            let frames = [
+                (0, 2, [0, 0].as_slice(), gif::DisposalMethod::Keep),
+                (0, 2, [2, 1].as_slice(), gif::DisposalMethod::Background),
+                (1, 1, [1].as_slice(), gif::DisposalMethod::Keep),
+            ];
+
+            for (left, width, indices, dispose) in frames {
+                encoder
+                    .write_frame(&gif::Frame {
+                        delay: 5,
+                        dispose,
+                        transparent: Some(2),
+                        left,
+                        width,
+                        height: 1,
+                        buffer: Cow::Borrowed(indices),
+                        ..gif::Frame::default()
+                    })
+                    .unwrap();
+            }
+        }
+
+        let image = AnimatedImage::from_file(&out);
+        assert_eq!(image.frames().len(), 3);
+        assert_eq!(image.repeat(), Repeat::Infinite);
+
+        let red = [255, 0, 0, 255];
+        let green = [0, 255, 0, 255];
+        let clear = [0, 0, 0, 0];
+        assert_eq!(image.frames()[0].pixels().bytes(), [red, red].concat());
+        // Transparent pixels show the previous frame.
+        assert_eq!(image.frames()[1].pixels().bytes(), [red, green].concat());
+        assert_eq!(image.frames()[2].pixels().bytes(), [clear, green].concat());
+
+        assert_eq!(image.frames()[0].delay(), Duration::from_millis(50));
+        assert_eq!(image.frame_index_at(Duration::from_millis(120)), 2);
+        assert_eq!(image.frame_index_at(Duration::from_millis(170)), 0);
+    }
+
+    #[test]
+    fn short_delays_are_clamped() {
+        let mut compositor = Compositor::new(1, 1);
+        let pixels = PixelBuffer::new(1, 1, PixelFormat::Rgba8, ColorSpace::Srgb);
+
+        compositor.push(&Subframe {
+            pixels: pixels.as_ref(),
+            position: Point::zero(),
+            delay: Duration::ZERO,
+            disposal: Disposal::None,
+            blend: Blend::Source,
+        });
+
+        let image = compositor.finish(Repeat::Infinite).unwrap();
+        assert_eq!(image.frames()[0].delay(), DEFAULT_FRAME_DELAY);
+    }
+}
@@ -0,0 +1,599 @@
+use std::cell::Cell;
+
+use geometry::{Extent, Point, Px, Rect};
+use structures::generational_pool::{GenerationalPool, Handle};
+
+use super::{AlphaMode, PixelBuffer, PixelBufferRef, PixelFormat};
+
+/// Options for the texture atlas that small images are packed into.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AtlasConfig {
+    /// The width and height of each atlas page.
+    pub page_size: u32,
+    /// Pixels of padding around each image. Edge pixels are repeated into the
+    /// padding so that filtering doesn't pick up neighbouring images.
+    pub padding: u32,
+    /// The number of pages that can be created before the least recently used
+    /// page is evicted to make room for new images.
+    pub max_pages: usize,
+}
+
+impl Default for AtlasConfig {
+    fn default() -> Self {
+        Self {
+            page_size: 2048,
+            padding: 1,
+            max_pages: 4,
+        }
+    }
+}
+
+pub(crate) struct AtlasEntry {
+    page: usize,
+    /// The area allocated to the entry, including padding.
+    rect: Rect<u32, Px>,
+    /// How the image's alpha is interpreted, which may differ from other
+    /// images in the same page.
+    alpha_mode: AlphaMode,
+}
+
+/// A successful atlas allocation. The caller is responsible for copying the
+/// (padded) image into `rect` of the page's texture.
+pub(crate) struct AtlasAllocation<'a, T> {
+    pub handle: Handle<AtlasEntry>,
+    pub texture: &'a T,
+    pub rect: Rect<u32, Px>,
+}
+
+struct Page<T> {
+    texture: T,
+    packer: ShelfPacker,
+    entries: Vec<Handle<AtlasEntry>>,
+    last_use: Cell<u64>,
+}
+
+/// Bookkeeping for packing images into a set of shared textures ('pages') of
+/// type `T`.
+///
+/// Pages are evicted whole when the atlas is full, which invalidates every
+/// image in them. Pages that have been used since the last call to
+/// [`Self::end_frame`] are never evicted.
+pub(crate) struct Atlas<T> {
+    config: AtlasConfig,
+    pages: Vec<Page<T>>,
+    entries: GenerationalPool<AtlasEntry>,
+    frame: u64,
+}
+
+impl<T> Atlas<T> {
+    pub fn new(config: AtlasConfig) -> Self {
+        Self {
+            config,
+            pages: Vec::new(),
+            entries: GenerationalPool::new(),
+            frame: 0,
+        }
+    }
+
+    pub fn config(&self) -> &AtlasConfig {
+        &self.config
+    }
+
+    /// Reserves space for an image of the given size and alpha mode, calling
+    /// `new_page` with the page size if a new page needs to be created.
+    ///
+    /// Returns `None` if the image is too big for a page, or if the atlas is
+    /// full and every page is in use this frame.
+    pub fn allocate(
+        &mut self,
+        extent: Extent<u32, Px>,
+        alpha_mode: AlphaMode,
+        new_page: impl FnOnce(u32) -> T,
+    ) -> Option<AtlasAllocation<T>> {
+        let padding = self.config.padding * 2;
+        let padded = Extent::new(extent.width + padding, extent.height + padding);
+
+        if padded.width > self.config.page_size || padded.height > self.config.page_size {
+            return None;
+        }
+
+        let mut found = None;
+        for (index, page) in self.pages.iter_mut().enumerate() {
+            if let Some(rect) = page.packer.allocate(padded) {
+                found = Some((index, rect));
+                break;
+            }
+        }
+
+        let (page_index, rect) = if let Some(found) = found {
+            found
+        } else if self.pages.len() < self.config.max_pages {
+            let size = self.config.page_size;
+            self.pages.push(Page {
+                texture: new_page(size),
+                packer: ShelfPacker::new(Extent::new(size, size)),
+                entries: Vec::new(),
+                last_use: Cell::new(self.frame),
+            });
+
+            let index = self.pages.len() - 1;
+            (index, self.pages[index].packer.allocate(padded)?)
+        } else {
+            let index = self.evict()?;
+            (index, self.pages[index].packer.allocate(padded)?)
+        };
+
+        let handle = self.entries.insert(AtlasEntry {
+            page: page_index,
+            rect,
+            alpha_mode,
+        });
+
+        let page = &mut self.pages[page_index];
+        page.entries.push(handle);
+        page.last_use.set(self.frame);
+
+        Some(AtlasAllocation {
+            handle,
+            texture: &page.texture,
+            rect,
+        })
+    }
+
+    /// Frees the space used by an image. Returns `false` if the entry had
+    /// already been evicted.
+    pub fn deallocate(&mut self, handle: Handle<AtlasEntry>) -> bool {
+        let Some(entry) = self.entries.remove(handle) else {
+            return false;
+        };
+
+        let page = &mut self.pages[entry.page];
+        page.packer.deallocate(entry.rect);
+        page.entries.retain(|e| *e != handle);
+        true
+    }
+
+    /// Looks up the page texture for an entry, along with the normalized
+    /// texture coordinates of the image within it. Marks the page as used this
+    /// frame.
+    pub fn get(&self, handle: Handle<AtlasEntry>) -> Option<(&T, Rect<f32, Px>)> {
+        let entry = self.entries.get(handle)?;
+        let page = &self.pages[entry.page];
+        page.last_use.set(self.frame);
+
+        #[allow(clippy::cast_precision_loss)]
+        let scale = 1.0 / self.config.page_size as f32;
+        #[allow(clippy::cast_precision_loss)]
+        let uv_rect = Rect::new(
+            Point::new(
+                (entry.rect.left() + self.config.padding) as f32 * scale,
+                (entry.rect.top() + self.config.padding) as f32 * scale,
+            ),
+            Extent::new(
+                (entry.rect.extent().width - self.config.padding * 2) as f32 * scale,
+                (entry.rect.extent().height - self.config.padding * 2) as f32 * scale,
+            ),
+        );
+
+        Some((&page.texture, uv_rect))
+    }
+
+    /// Looks up the page texture for an entry, along with the area of the page
+    /// it occupies in pixels, including padding. Unlike [`Self::get`], this
+    /// does not mark the page as used.
+    pub fn allocation(&self, handle: Handle<AtlasEntry>) -> Option<(&T, Rect<u32, Px>)> {
+        let entry = self.entries.get(handle)?;
+        Some((&self.pages[entry.page].texture, entry.rect))
+    }
+
+    /// The alpha mode the image was allocated with.
+    pub fn alpha_mode(&self, handle: Handle<AtlasEntry>) -> Option<AlphaMode> {
+        Some(self.entries.get(handle)?.alpha_mode)
+    }
+
+    pub fn contains(&self, handle: Handle<AtlasEntry>) -> bool {
+        self.entries.get(handle).is_some()
+    }
+
+    pub fn end_frame(&mut self) {
+        self.frame += 1;
+    }
+
+    /// Empties the least recently used page that hasn't been used this frame.
+    fn evict(&mut self) -> Option<usize> {
+        let (index, page) = self
+            .pages
+            .iter_mut()
+            .enumerate()
+            .filter(|(_, page)| page.last_use.get() < self.frame)
+            .min_by_key(|(_, page)| page.last_use.get())?;
+
+        for handle in page.entries.drain(..) {
+            self.entries.remove(handle);
+        }
+
+        page.packer.clear();
+        Some(index)
+    }
+}
+
+/// Copies `pixels` into a new RGBA8 buffer with `padding` pixels on each side,
+/// filled by repeating the outermost pixels of the image.
+pub(crate) fn pad(pixels: PixelBufferRef, padding: u32) -> PixelBuffer {
+    let width = pixels.width() + padding * 2;
+    let height = pixels.height() + padding * 2;
+
+    let mut padded = PixelBuffer::new(width, height, PixelFormat::Rgba8, pixels.color_space())
+        .with_alpha_mode(pixels.alpha_mode());
+    padded.as_mut().blit(pixels, Point::new(padding, padding));
+
+    if padding == 0 {
+        return padded;
+    }
+
+    let bpp = PixelFormat::Rgba8.bytes_per_pixel();
+    let pad_bytes = padding as usize * bpp;
+    let image_rows = padding as usize..(padding + pixels.height()) as usize;
+
+    for row in padded
+        .rows_mut()
+        .skip(image_rows.start)
+        .take(image_rows.len())
+    {
+        let (left, rest) = row.split_at_mut(pad_bytes);
+        let (middle, right) = rest.split_at_mut(rest.len() - pad_bytes);
+
+        let first = &middle[..bpp];
+        let last = &middle[middle.len() - bpp..];
+
+        for pixel in left.chunks_exact_mut(bpp) {
+            pixel.copy_from_slice(first);
+        }
+
+        for pixel in right.chunks_exact_mut(bpp) {
+            pixel.copy_from_slice(last);
+        }
+    }
+
+    let row_pitch = padded.row_pitch();
+    let bytes = padded.bytes_mut();
+    let first_row = image_rows.start * row_pitch;
+    let last_row = (image_rows.end - 1) * row_pitch;
+
+    for row in 0..image_rows.start {
+        bytes.copy_within(first_row..first_row + row_pitch, row * row_pitch);
+    }
+
+    for row in image_rows.end..height as usize {
+        bytes.copy_within(last_row..last_row + row_pitch, row * row_pitch);
+    }
+
+    padded
+}
+
+/// Pads the new contents of `region` of an atlas image so that the padding
+/// around the image is kept up to date on any side the region touches.
+///
+/// Returns the pixels to copy and where to copy them to, relative to the top
+/// left corner of the image's allocation (including its padding).
+pub(crate) fn pad_region(
+    pixels: PixelBufferRef,
+    region: Rect<u32, Px>,
+    image: Extent<u32, Px>,
+    padding: u32,
+) -> (PixelBuffer, Point<u32, Px>) {
+    let padded = pad(pixels, padding);
+
+    let left = if region.left() == 0 { 0 } else { padding };
+    let top = if region.top() == 0 { 0 } else { padding };
+    let right = if region.right() == image.width {
+        padded.width()
+    } else {
+        padded.width() - padding
+    };
+    let bottom = if region.bottom() == image.height {
+        padded.height()
+    } else {
+        padded.height() - padding
+    };
+
+    let cropped = padded.crop(Rect::new(
+        Point::new(left, top),
+        Extent::new(right - left, bottom - top),
+    ));
+
+    (
+        cropped,
+        Point::new(region.left() + left, region.top() + top),
+    )
+}
+
+/// Packs rectangles into horizontal shelves, each as tall as the tallest
+/// rectangle placed on it.
+///
+/// Freed space is reused by rectangles of similar height, and shelves that
+/// become empty are returned to the pool.
+pub(crate) struct ShelfPacker {
+    extent: Extent<u32, Px>,
+    shelves: Vec<Shelf>,
+}
+
+struct Shelf {
+    top: u32,
+    height: u32,
+    /// The start of unallocated space at the end of the shelf.
+    cursor: u32,
+    /// Freed spans of the shelf before the cursor, as `(left, width)`.
+    free: Vec<(u32, u32)>,
+    num_allocations: u32,
+}
+
+impl Shelf {
+    fn find_span(&self, width: u32, shelf_width: u32) -> Option<Span> {
+        self.free
+            .iter()
+            .position(|(_, w)| *w >= width)
+            .map(Span::Free)
+            .or_else(|| (shelf_width - self.cursor >= width).then_some(Span::End))
+    }
+}
+
+enum Span {
+    Free(usize),
+    End,
+}
+
+impl ShelfPacker {
+    pub fn new(extent: Extent<u32, Px>) -> Self {
+        Self {
+            extent,
+            shelves: Vec::new(),
+        }
+    }
+
+    pub fn allocate(&mut self, extent: Extent<u32, Px>) -> Option<Rect<u32, Px>> {
+        if extent.width > self.extent.width || extent.height > self.extent.height {
+            return None;
+        }
+
+        // Pick the shelf that wastes the least height, ignoring shelves that
+        // are much taller than the rectangle.
+        let best = self
+            .shelves
+            .iter()
+            .enumerate()
+            .filter(|(_, shelf)| {
+                shelf.height >= extent.height
+                    && (shelf.height <= extent.height * 2 || shelf.num_allocations == 0)
+            })
+            .filter_map(|(index, shelf)| {
+                let span = shelf.find_span(extent.width, self.extent.width)?;
+                Some((shelf.height - extent.height, index, span))
+            })
+            .min_by_key(|(waste, _, _)| *waste);
+
+        let (index, span) = if let Some((_, index, span)) = best {
+            (index, span)
+        } else {
+            let top = self.shelves.last().map_or(0, |s| s.top + s.height);
+            if self.extent.height - top < extent.height {
+                return None;
+            }
+
+            self.shelves.push(Shelf {
+                top,
+                height: extent.height,
+                cursor: 0,
+                free: Vec::new(),
+                num_allocations: 0,
+            });
+
+            (self.shelves.len() - 1, Span::End)
+        };
+
+        let shelf = &mut self.shelves[index];
+        let left = match span {
+            Span::Free(i) => {
+                let (left, width) = shelf.free[i];
+                if width == extent.width {
+                    shelf.free.swap_remove(i);
+                } else {
+                    shelf.free[i] = (left + extent.width, width - extent.width);
+                }
+                left
+            }
+            Span::End => {
+                let left = shelf.cursor;
+                shelf.cursor += extent.width;
+                left
+            }
+        };
+
+        shelf.num_allocations += 1;
+        Some(Rect::new(Point::new(left, shelf.top), extent))
+    }
+
+    pub fn deallocate(&mut self, rect: Rect<u32, Px>) {
+        let index = self
+            .shelves
+            .iter()
+            .position(|shelf| shelf.top == rect.top())
+            .expect("rect was not allocated from this packer");
+
+        let shelf = &mut self.shelves[index];
+        shelf.num_allocations -= 1;
+
+        if shelf.num_allocations == 0 {
+            shelf.cursor = 0;
+            shelf.free.clear();
+
+            // Return empty shelves at the bottom to the pool so that their
+            // height can be reused.
+            while matches!(self.shelves.last(), Some(shelf) if shelf.num_allocations == 0) {
+                self.shelves.pop();
+            }
+        } else if rect.right() == shelf.cursor {
+            shelf.cursor = rect.left();
+        } else {
+            shelf.free.push((rect.left(), rect.extent().width));
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.shelves.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::graphics::{Color, ColorSpace};
+
+    fn corners(rect: Rect<u32, Px>) -> (u32, u32, u32, u32) {
+        (rect.left(), rect.top(), rect.right(), rect.bottom())
+    }
+
+    #[test]
+    fn shelf_packing() {
+        let mut packer = ShelfPacker::new(Extent::new(100, 100));
+
+        let a = packer.allocate(Extent::new(40, 20)).unwrap();
+        let b = packer.allocate(Extent::new(40, 18)).unwrap();
+        let c = packer.allocate(Extent::new(40, 20)).unwrap();
+        let d = packer.allocate(Extent::new(10, 60)).unwrap();
+
+        assert_eq!(corners(a), (0, 0, 40, 20));
+        assert_eq!(corners(b), (40, 0, 80, 18));
+        assert_eq!(corners(c), (0, 20, 40, 40));
+        assert_eq!(corners(d), (0, 40, 10, 100));
+        assert!(packer.allocate(Extent::new(10, 10)).is_some());
+        assert!(packer.allocate(Extent::new(101, 1)).is_none());
+
+        // Freed space in the middle of a shelf is reused.
+        packer.deallocate(a);
+        let e = packer.allocate(Extent::new(30, 20)).unwrap();
+        assert_eq!(corners(e), (0, 0, 30, 20));
+
+        // Empty shelves at the end are reclaimed.
+        packer.deallocate(d);
+        let f = packer.allocate(Extent::new(100, 60)).unwrap();
+        assert_eq!(corners(f), (0, 40, 100, 100));
+    }
+
+    #[test]
+    fn atlas_eviction() {
+        let mut atlas = Atlas::new(AtlasConfig {
+            page_size: 10,
+            padding: 1,
+            max_pages: 2,
+        });
+
+        let mut next_page = 0;
+        let mut new_page = |_| {
+            next_page += 1;
+            next_page
+        };
+
+        let a = atlas
+            .allocate(Extent::new(8, 8), AlphaMode::Premultiplied, &mut new_page)
+            .unwrap();
+        assert_eq!((*a.texture, corners(a.rect)), (1, (0, 0, 10, 10)));
+        let a = a.handle;
+
+        // Too big once padded.
+        assert!(atlas
+            .allocate(Extent::new(9, 1), AlphaMode::Premultiplied, &mut new_page)
+            .is_none());
+
+        let b = atlas
+            .allocate(Extent::new(8, 8), AlphaMode::Premultiplied, &mut new_page)
+            .unwrap();
+        assert_eq!(*b.texture, 2);
+        let b = b.handle;
+
+        // Both pages are in use this frame, so nothing can be evicted.
+        assert!(atlas
+            .allocate(Extent::new(8, 8), AlphaMode::Premultiplied, &mut new_page)
+            .is_none());
+
+        atlas.end_frame();
+        atlas.get(b).unwrap();
+
+        // The first page is the least recently used.
+        let c = atlas
+            .allocate(Extent::new(4, 4), AlphaMode::Opaque, &mut new_page)
+            .unwrap();
+        assert_eq!(*c.texture, 1);
+        let c = c.handle;
+        assert_eq!(atlas.alpha_mode(c), Some(AlphaMode::Opaque));
+
+        assert!(!atlas.contains(a));
+        assert!(atlas.contains(b));
+        assert!(!atlas.deallocate(a));
+
+        let (_, uv_rect) = atlas.get(c).unwrap();
+        assert_eq!(
+            (
+                uv_rect.left(),
+                uv_rect.top(),
+                uv_rect.right(),
+                uv_rect.bottom()
+            ),
+            (0.1, 0.1, 0.5, 0.5)
+        );
+
+        assert!(atlas.deallocate(c));
+        assert!(atlas
+            .allocate(Extent::new(8, 8), AlphaMode::Premultiplied, &mut new_page)
+            .is_some());
+    }
+
+    #[test]
+    fn padding_repeats_edges() {
+        let colors = [Color::RED, Color::GREEN, Color::BLUE, Color::WHITE];
+        let pixels = PixelBuffer::from_colors(&colors, 2, PixelFormat::Bgra8, ColorSpace::Srgb);
+
+        let padded = pad(pixels.as_ref(), 1);
+        assert_eq!((padded.width(), padded.height()), (4, 4));
+        assert_eq!(padded.format(), PixelFormat::Rgba8);
+
+        let r = [255, 0, 0, 255];
+        let g = [0, 255, 0, 255];
+        let b = [0, 0, 255, 255];
+        let w = [255, 255, 255, 255];
+
+        #[rustfmt::skip]
+        let expected = [
+            r, r, g, g,
+            r, r, g, g,
+            b, b, w, w,
+            b, b, w, w,
+        ].concat();
+
+        assert_eq!(padded.bytes(), expected);
+    }
+
+    #[test]
+    fn region_padding() {
+        let colors = [Color::RED, Color::GREEN];
+        let pixels = PixelBuffer::from_colors(&colors, 2, PixelFormat::Rgba8, ColorSpace::Srgb);
+        let image = Extent::new(4, 4);
+
+        // Touching the top and right edges of the image.
+        let region = Rect::new(Point::new(2, 0), Extent::new(2, 1));
+        let (padded, at) = pad_region(pixels.as_ref(), region, image, 1);
+        assert_eq!((padded.width(), padded.height()), (3, 2));
+        assert_eq!(at, Point::new(3, 0));
+
+        let r = [255, 0, 0, 255];
+        let g = [0, 255, 0, 255];
+        assert_eq!(padded.bytes(), [r, g, g, r, g, g].concat());
+
+        // Entirely inside the image, so the padding is left alone.
+        let region = Rect::new(Point::new(1, 1), Extent::new(2, 1));
+        let (padded, at) = pad_region(pixels.as_ref(), region, image, 1);
+        assert_eq!((padded.width(), padded.height()), (2, 1));
+        assert_eq!(at, Point::new(2, 2));
+        assert_eq!(padded.bytes(), [r, g].concat());
+    }
+}
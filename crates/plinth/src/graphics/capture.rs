@@ -0,0 +1,634 @@
+use std::{fmt, io, path::Path};
+
+use geometry::{Extent, Point, Px};
+
+use super::{
+    software, AlphaMode, Color, ColorSpace, DebugView, GraphicsContext, Image, PixelBuffer,
+    PixelFormat, RectInstance, RenderGraph, RoundedRectVertex,
+};
+
+const MAGIC: [u8; 4] = *b"GLRC";
+/// Version 2 widened indices and node links to 32 bits, and version 3 added
+/// rect instances.
+const VERSION: u32 = 3;
+
+#[derive(Debug)]
+pub enum CaptureError {
+    Io(io::Error),
+    /// The capture was written by a newer (or much older) version of the
+    /// library.
+    UnsupportedVersion(u32),
+    Malformed(String),
+}
+
+impl fmt::Display for CaptureError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(error) => write!(f, "failed to access frame capture: {error}"),
+            Self::UnsupportedVersion(version) => {
+                write!(f, "unsupported frame capture version {version}")
+            }
+            Self::Malformed(reason) => write!(f, "malformed frame capture: {reason}"),
+        }
+    }
+}
+
+impl std::error::Error for CaptureError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for CaptureError {
+    fn from(error: io::Error) -> Self {
+        Self::Io(error)
+    }
+}
+
+/// Counts of what a captured frame draws.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CaptureStats {
+    pub draws: usize,
+    pub triangles: usize,
+    pub images: usize,
+    /// The total size of the captured images.
+    pub image_bytes: usize,
+}
+
+/// A complete frame, including the contents of every image it draws, that can
+/// be saved to a file and replayed later (see
+/// [`GraphicsContext::capture_frame`]).
+///
+/// The file format is versioned binary. Captures from older versions of the
+/// library may stop loading when the format changes.
+pub struct FrameCapture {
+    graph: RenderGraph<u32>,
+    images: Vec<PixelBuffer>,
+}
+
+impl FrameCapture {
+    pub(crate) fn new(graph: RenderGraph<u32>, images: Vec<PixelBuffer>) -> Self {
+        Self { graph, images }
+    }
+
+    /// The captured render graph, which refers to images by their index in
+    /// [`Self::images`].
+    #[must_use]
+    pub fn graph(&self) -> &RenderGraph<u32> {
+        &self.graph
+    }
+
+    #[must_use]
+    pub fn images(&self) -> &[PixelBuffer] {
+        &self.images
+    }
+
+    #[must_use]
+    pub fn stats(&self) -> CaptureStats {
+        CaptureStats {
+            draws: self.graph.num_nodes() - 1,
+            triangles: self.graph.imm_indices.len() / 3 + self.graph.imm_rect_instances.len() * 2,
+            images: self.images.len(),
+            image_bytes: self.images.iter().map(|image| image.bytes().len()).sum(),
+        }
+    }
+
+    /// The smallest extent that contains everything the frame draws, starting
+    /// from the origin.
+    #[must_use]
+    pub fn content_extent(&self) -> Extent<u32, Px> {
+        let corners = self
+            .graph
+            .imm_rect_instances
+            .iter()
+            .map(|instance| instance.bounds().bottom_right());
+
+        let (width, height) = self
+            .graph
+            .imm_rect_vertices
+            .iter()
+            .map(|vertex| vertex.position)
+            .chain(corners)
+            .fold((0.0f32, 0.0f32), |(width, height), position| {
+                (width.max(position.x), height.max(position.y))
+            });
+
+        #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+        Extent::new(width.ceil() as u32, height.ceil() as u32)
+    }
+
+    /// Draws the frame on the CPU, without a GPU or window. The result is
+    /// close to, but not exactly the same as, what the GPU would draw.
+    #[must_use]
+    pub fn render(&self, extent: Extent<u32, Px>) -> PixelBuffer {
+        self.render_debug(extent, DebugView::Off)
+    }
+
+    /// Like [`Self::render`], but visualized as `view` describes.
+    #[must_use]
+    pub fn render_debug(&self, extent: Extent<u32, Px>, view: DebugView) -> PixelBuffer {
+        software::render(&self.graph, &self.images, extent, view)
+    }
+
+    /// Uploads the captured images, and returns a render graph that draws the
+    /// captured frame with them. The images are returned as well so that they
+    /// can be destroyed once the frame is no longer needed.
+    pub fn upload(&self, graphics: &GraphicsContext) -> (RenderGraph, Vec<Image>) {
+        let images = self
+            .images
+            .iter()
+            .map(|pixels| graphics.upload_image(pixels.as_ref()))
+            .collect::<Vec<_>>();
+
+        let graph = self.graph.map_images(|index| images[*index as usize]);
+        (graph, images)
+    }
+
+    #[must_use]
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut w = Writer::default();
+        w.bytes(&MAGIC);
+        w.u32(VERSION);
+
+        w.u32(self.images.len() as u32);
+        for image in &self.images {
+            w.u32(image.width());
+            w.u32(image.height());
+            w.u8(match image.format() {
+                PixelFormat::Rgba8 => 0,
+                PixelFormat::Bgra8 => 1,
+            });
+            w.u8(match image.color_space() {
+                ColorSpace::Srgb => 0,
+            });
+            w.u8(match image.alpha_mode() {
+                AlphaMode::Straight => 0,
+                AlphaMode::Premultiplied => 1,
+                AlphaMode::Opaque => 2,
+            });
+
+            for row in image.rows() {
+                w.bytes(row);
+            }
+        }
+
+        self.graph.write(&mut w);
+        w.finish()
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, CaptureError> {
+        let mut r = Reader::new(bytes);
+
+        if r.bytes(MAGIC.len())? != MAGIC {
+            return Err(CaptureError::Malformed("not a frame capture".into()));
+        }
+
+        let version = r.u32()?;
+        if !(1..=VERSION).contains(&version) {
+            return Err(CaptureError::UnsupportedVersion(version));
+        }
+
+        let num_images = r.u32()?;
+        let mut images = Vec::new();
+        for _ in 0..num_images {
+            let width = r.u32()?;
+            let height = r.u32()?;
+
+            let format = match r.u8()? {
+                0 => PixelFormat::Rgba8,
+                1 => PixelFormat::Bgra8,
+                _ => return Err(CaptureError::Malformed("invalid pixel format".into())),
+            };
+
+            let color_space = match r.u8()? {
+                0 => ColorSpace::Srgb,
+                _ => return Err(CaptureError::Malformed("invalid color space".into())),
+            };
+
+            let alpha_mode = match r.u8()? {
+                0 => AlphaMode::Straight,
+                1 => AlphaMode::Premultiplied,
+                2 => AlphaMode::Opaque,
+                _ => return Err(CaptureError::Malformed("invalid alpha mode".into())),
+            };
+
+            if width == 0 || height == 0 {
+                return Err(CaptureError::Malformed("empty image".into()));
+            }
+
+            let size = (width as usize)
+                .checked_mul(height as usize)
+                .and_then(|pixels| pixels.checked_mul(format.bytes_per_pixel()))
+                .ok_or_else(|| CaptureError::Malformed("image too large".into()))?;
+
+            let bytes = r.bytes(size)?;
+            images.push(
+                PixelBuffer::from_bytes(bytes, width, format, color_space)
+                    .with_alpha_mode(alpha_mode),
+            );
+        }
+
+        let graph = RenderGraph::read(&mut r, version, images.len())?;
+
+        if !r.is_empty() {
+            return Err(CaptureError::Malformed("trailing data".into()));
+        }
+
+        Ok(Self { graph, images })
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), CaptureError> {
+        std::fs::write(path, self.to_bytes())?;
+        Ok(())
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, CaptureError> {
+        Self::from_bytes(&std::fs::read(path)?)
+    }
+}
+
+/// Little-endian encoding for frame captures.
+#[derive(Default)]
+pub(crate) struct Writer {
+    bytes: Vec<u8>,
+}
+
+impl Writer {
+    pub fn u8(&mut self, value: u8) {
+        self.bytes.push(value);
+    }
+
+    pub fn u32(&mut self, value: u32) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn u16(&mut self, value: u16) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn f32(&mut self, value: f32) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn bytes(&mut self, bytes: &[u8]) {
+        self.bytes.extend_from_slice(bytes);
+    }
+
+    pub fn vertex(&mut self, vertex: &RoundedRectVertex) {
+        let RoundedRectVertex {
+            position,
+            rect_size,
+            rect_center,
+            outer_radii,
+            inner_radii,
+            color,
+            uv,
+        } = vertex;
+
+        for value in [
+            position.x,
+            position.y,
+            rect_size.width,
+            rect_size.height,
+            rect_center.x,
+            rect_center.y,
+        ]
+        .iter()
+        .chain(outer_radii)
+        .chain(inner_radii)
+        .chain(&[color.r, color.g, color.b, color.a, uv.x, uv.y])
+        {
+            self.f32(*value);
+        }
+    }
+
+    pub fn instance(&mut self, instance: &RectInstance) {
+        let RectInstance {
+            rect,
+            outer_radii,
+            inner_radii,
+            colors,
+            uvs,
+        } = instance;
+
+        for value in rect.iter().chain(outer_radii).chain(inner_radii) {
+            self.f32(*value);
+        }
+
+        for value in colors.iter().flatten().chain(uvs.iter().flatten()) {
+            self.u16(*value);
+        }
+    }
+
+    pub fn finish(self) -> Vec<u8> {
+        self.bytes
+    }
+}
+
+/// Reads values written by [`Writer`], failing if there aren't enough bytes.
+pub(crate) struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+
+    pub fn bytes(&mut self, len: usize) -> Result<&'a [u8], CaptureError> {
+        if len > self.bytes.len() {
+            return Err(CaptureError::Malformed("unexpected end of data".into()));
+        }
+
+        let (bytes, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Ok(bytes)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], CaptureError> {
+        let mut array = [0; N];
+        array.copy_from_slice(self.bytes(N)?);
+        Ok(array)
+    }
+
+    pub fn u8(&mut self) -> Result<u8, CaptureError> {
+        Ok(self.array::<1>()?[0])
+    }
+
+    pub fn u16(&mut self) -> Result<u16, CaptureError> {
+        Ok(u16::from_le_bytes(self.array()?))
+    }
+
+    pub fn u32(&mut self) -> Result<u32, CaptureError> {
+        Ok(u32::from_le_bytes(self.array()?))
+    }
+
+    pub fn f32(&mut self) -> Result<f32, CaptureError> {
+        Ok(f32::from_le_bytes(self.array()?))
+    }
+
+    pub fn vertex(&mut self) -> Result<RoundedRectVertex, CaptureError> {
+        let mut values = [0.0; 20];
+        for value in &mut values {
+            *value = self.f32()?;
+        }
+
+        Ok(RoundedRectVertex {
+            position: Point::new(values[0], values[1]),
+            rect_size: Extent::new(values[2], values[3]),
+            rect_center: Point::new(values[4], values[5]),
+            outer_radii: [values[6], values[7], values[8], values[9]],
+            inner_radii: [values[10], values[11], values[12], values[13]],
+            color: Color {
+                r: values[14],
+                g: values[15],
+                b: values[16],
+                a: values[17],
+            },
+            uv: Point::new(values[18], values[19]),
+        })
+    }
+
+    pub fn instance(&mut self) -> Result<RectInstance, CaptureError> {
+        let mut instance = RectInstance::default();
+
+        for value in instance
+            .rect
+            .iter_mut()
+            .chain(&mut instance.outer_radii)
+            .chain(&mut instance.inner_radii)
+        {
+            *value = self.f32()?;
+        }
+
+        for value in instance
+            .colors
+            .iter_mut()
+            .flatten()
+            .chain(instance.uvs.iter_mut().flatten())
+        {
+            *value = self.u16()?;
+        }
+
+        Ok(instance)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use geometry::Rect;
+
+    use super::*;
+    use crate::graphics::{DrawRect, RenderGraphCommand, RenderGraphNodeId};
+
+    fn capture() -> FrameCapture {
+        let mut graph = RenderGraph::new();
+        graph.draw_rect(
+            RenderGraphNodeId::root(),
+            &DrawRect::new(Rect::new(Point::new(1.0, 2.0), Extent::new(3.0, 4.0)))
+                .with_color(Color::RED)
+                .with_radius(2.0),
+        );
+
+        let parent = graph
+            .iter_children(RenderGraphNodeId::root())
+            .next()
+            .unwrap();
+        graph.draw_rect(
+            parent,
+            &DrawRect::new(Rect::new(Point::new(5.0, 6.0), Extent::new(7.0, 8.0))),
+        );
+
+        let image = PixelBuffer::from_colors(
+            &[Color::GREEN, Color::BLUE],
+            2,
+            PixelFormat::Bgra8,
+            ColorSpace::Srgb,
+        )
+        .with_alpha_mode(AlphaMode::Premultiplied);
+
+        FrameCapture::new(graph.map_images(|_| unreachable!()), vec![image])
+    }
+
+    #[test]
+    fn round_trip() {
+        let bytes = capture().to_bytes();
+        let loaded = FrameCapture::from_bytes(&bytes).unwrap();
+        assert_eq!(loaded.to_bytes(), bytes);
+
+        let image = &loaded.images()[0];
+        assert_eq!((image.width(), image.height()), (2, 1));
+        assert_eq!(image.format(), PixelFormat::Bgra8);
+        assert_eq!(image.alpha_mode(), AlphaMode::Premultiplied);
+
+        let graph = loaded.graph();
+        let rect = graph
+            .iter_children(RenderGraphNodeId::root())
+            .next()
+            .unwrap();
+        assert!(matches!(
+            graph.get(rect),
+            RenderGraphCommand::DrawRect {
+                first_instance: 0,
+                num_instances: 1,
+                image: None,
+            }
+        ));
+        assert_eq!(graph.iter_children(rect).count(), 1);
+        let color = Color::from_f16(graph.imm_rect_instances[0].colors[0]);
+        assert_eq!([color.r, color.g, color.b, color.a], [1.0, 0.0, 0.0, 1.0]);
+        assert_eq!(graph.imm_rect_instances[0].outer_radii, [2.0; 4]);
+    }
+
+    #[test]
+    fn version_2_rects() {
+        let rect = DrawRect::new(Rect::new(Point::new(1.0, 2.0), Extent::new(3.0, 4.0)))
+            .with_color(Color::RED)
+            .to_instance();
+
+        // Rects used to be drawn with four vertices and six indices.
+        let mut w = Writer::default();
+        w.bytes(&MAGIC);
+        w.u32(2);
+        w.u32(0);
+
+        w.u32(4);
+        for vertex in &rect.to_vertices() {
+            w.vertex(vertex);
+        }
+
+        w.u32(6);
+        for index in [0, 1, 2, 0, 2, 3] {
+            w.u32(index);
+        }
+
+        w.u32(2);
+        for value in [0, 1, 1] {
+            w.u32(value);
+        }
+        w.u8(0);
+        for value in [0, 0, 0] {
+            w.u32(value);
+        }
+        w.u8(1);
+        for value in [0, 6, u32::MAX] {
+            w.u32(value);
+        }
+
+        let loaded = FrameCapture::from_bytes(&w.finish()).unwrap();
+        let graph = loaded.graph();
+        let node = graph
+            .iter_children(RenderGraphNodeId::root())
+            .next()
+            .unwrap();
+        assert!(matches!(
+            graph.get(node),
+            RenderGraphCommand::DrawRect {
+                first_instance: 0,
+                num_instances: 1,
+                image: None,
+            }
+        ));
+        assert_eq!(graph.imm_rect_instances, [rect]);
+    }
+
+    #[test]
+    fn malformed_captures() {
+        let bytes = capture().to_bytes();
+
+        for len in [0, 4, 8, bytes.len() - 1] {
+            assert!(matches!(
+                FrameCapture::from_bytes(&bytes[..len]),
+                Err(CaptureError::Malformed(_))
+            ));
+        }
+
+        let mut future = bytes.clone();
+        future[4] = 4;
+        assert!(matches!(
+            FrameCapture::from_bytes(&future),
+            Err(CaptureError::UnsupportedVersion(4))
+        ));
+
+        // Make the first rect its own child.
+        let mut cyclic = bytes;
+        let first_rect = cyclic.len() - 2 * 25;
+        cyclic[first_rect + 4] = 1;
+        assert!(matches!(
+            FrameCapture::from_bytes(&cyclic),
+            Err(CaptureError::Malformed(_))
+        ));
+    }
+
+    /// A capture without images or shapes, whose nodes have the given links
+    /// (next, first child, and last child). Every node but the root draws an
+    /// empty rect.
+    fn capture_with_links(links: &[[u32; 3]]) -> Vec<u8> {
+        let mut w = Writer::default();
+        w.bytes(&MAGIC);
+        w.u32(VERSION);
+        w.u32(0); // images
+        w.u32(0); // vertices
+        w.u32(0); // indices
+        w.u32(0); // instances
+
+        w.u32(links.len() as u32);
+        for (i, node) in links.iter().enumerate() {
+            for link in node {
+                w.u32(*link);
+            }
+
+            if i == 0 {
+                w.u8(0);
+            } else {
+                w.u8(1);
+                for value in [0, 0, u32::MAX] {
+                    w.u32(value);
+                }
+            }
+        }
+
+        w.finish()
+    }
+
+    #[test]
+    fn huge_node_count() {
+        let mut bytes = capture_with_links(&[[0, 0, 0]]);
+        let num_nodes = bytes.len() - 13 - 4;
+        bytes[num_nodes..num_nodes + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+
+        assert!(matches!(
+            FrameCapture::from_bytes(&bytes),
+            Err(CaptureError::Malformed(_))
+        ));
+    }
+
+    #[test]
+    fn malformed_trees() {
+        let malformed = |links: &[[u32; 3]]| {
+            matches!(
+                FrameCapture::from_bytes(&capture_with_links(links)),
+                Err(CaptureError::Malformed(_))
+            )
+        };
+
+        assert!(!malformed(&[[0, 1, 2], [2, 0, 0], [0, 0, 0]]));
+
+        // Every node is both the next sibling and first child of the one
+        // before it, which would be visited exponentially many times.
+        assert!(malformed(&[[0, 1, 1], [2, 2, 2], [3, 3, 3], [0, 0, 0]]));
+        // Not linked to from anywhere.
+        assert!(malformed(&[[0, 1, 1], [0, 0, 0], [0, 0, 0]]));
+        // The last child isn't the end of the chain.
+        assert!(malformed(&[[0, 1, 1], [2, 0, 0], [0, 0, 0]]));
+        // A sibling of the root.
+        assert!(malformed(&[[1, 0, 0], [0, 0, 0]]));
+    }
+}
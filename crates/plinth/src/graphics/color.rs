@@ -46,4 +46,144 @@ impl Color {
     pub fn new(r: f32, g: f32, b: f32, a: f32) -> Self {
         Self { r, g, b, a }
     }
+
+    /// Multiplies the color channels by alpha.
+    #[must_use]
+    pub fn premultiplied(self) -> Self {
+        Self {
+            r: self.r * self.a,
+            g: self.g * self.a,
+            b: self.b * self.a,
+            a: self.a,
+        }
+    }
+
+    /// Converts the color to half-precision floats, as stored in GPU buffers.
+    pub(crate) fn to_f16(self) -> [u16; 4] {
+        [self.r, self.g, self.b, self.a].map(f32_to_f16)
+    }
+
+    pub(crate) fn from_f16(half: [u16; 4]) -> Self {
+        let [r, g, b, a] = half.map(f16_to_f32);
+        Self { r, g, b, a }
+    }
+
+    /// Divides the color channels by alpha, undoing [`Self::premultiplied`].
+    /// Fully transparent colors become transparent black.
+    #[must_use]
+    pub fn unpremultiplied(self) -> Self {
+        if self.a == 0.0 {
+            return Self::new(0.0, 0.0, 0.0, 0.0);
+        }
+
+        Self {
+            r: self.r / self.a,
+            g: self.g / self.a,
+            b: self.b / self.a,
+            a: self.a,
+        }
+    }
+}
+
+/// Rounds to the nearest half-precision float, with ties to even.
+#[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+fn f32_to_f16(value: f32) -> u16 {
+    let bits = value.to_bits();
+    let sign = ((bits >> 16) & 0x8000) as u16;
+    let exponent = ((bits >> 23) & 0xff) as i32;
+    let mantissa = bits & 0x007f_ffff;
+
+    if exponent == 0xff {
+        // Infinity, or NaN (which must keep a non-zero mantissa).
+        return sign | 0x7c00 | if mantissa == 0 { 0 } else { 0x0200 };
+    }
+
+    let exponent = exponent - 127 + 15;
+    if exponent >= 0x1f {
+        return sign | 0x7c00;
+    }
+
+    // Subnormal halves have an implicit leading zero, so shift the leading one
+    // into the mantissa.
+    let (half, shift) = if exponent <= 0 {
+        if exponent < -10 {
+            return sign;
+        }
+        (0, (14 - exponent) as u32)
+    } else {
+        ((exponent as u32) << 10, 13)
+    };
+
+    let mantissa = if exponent <= 0 {
+        mantissa | 0x0080_0000
+    } else {
+        mantissa
+    };
+
+    let half = half | (mantissa >> shift);
+    let remainder = mantissa & ((1 << shift) - 1);
+    let halfway = 1 << (shift - 1);
+
+    // Rounding up may carry into the exponent, which is still correct.
+    if remainder > halfway || (remainder == halfway && half & 1 == 1) {
+        sign | (half + 1) as u16
+    } else {
+        sign | half as u16
+    }
+}
+
+fn f16_to_f32(half: u16) -> f32 {
+    let sign = if half & 0x8000 == 0 { 1.0 } else { -1.0 };
+    let exponent = i32::from((half >> 10) & 0x1f);
+    let mantissa = f32::from(half & 0x03ff);
+
+    sign * match exponent {
+        0 => mantissa * 2.0f32.powi(-24),
+        0x1f if mantissa == 0.0 => f32::INFINITY,
+        0x1f => f32::NAN,
+        _ => (1.0 + mantissa / 1024.0) * 2.0f32.powi(exponent - 15),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn half_precision() {
+        for (value, half) in [
+            (0.0, 0x0000),
+            (-0.0, 0x8000),
+            (1.0, 0x3c00),
+            (-2.0, 0xc000),
+            (0.5, 0x3800),
+            (65504.0, 0x7bff),
+            (f32::INFINITY, 0x7c00),
+            // Smallest normal and subnormal halves.
+            (6.103_515_6e-5, 0x0400),
+            (5.960_464_5e-8, 0x0001),
+        ] {
+            assert_eq!(f32_to_f16(value), half, "{value}");
+            assert_eq!(f16_to_f32(half), value, "{half:#x}");
+        }
+
+        // Too large or too small.
+        assert_eq!(f32_to_f16(1.0e6), 0x7c00);
+        assert_eq!(f32_to_f16(1.0e-9), 0x0000);
+        assert!(f16_to_f32(f32_to_f16(f32::NAN)).is_nan());
+
+        // 1 + 2^-11 is halfway between two halves, and rounds to the even one.
+        assert_eq!(f32_to_f16(1.000_488_3), 0x3c00);
+        assert_eq!(f32_to_f16(1.001_464_8), 0x3c02);
+
+        // Every channel of a color survives the round trip to within the
+        // precision of a half.
+        let color = Color::from_f16(Color::new(0.1, 0.25, 0.7, 1.0).to_f16());
+        for (a, b) in [color.r, color.g, color.b, color.a]
+            .iter()
+            .zip([0.1, 0.25, 0.7, 1.0])
+        {
+            assert!((a - b).abs() < 0.001);
+        }
+    }
 }
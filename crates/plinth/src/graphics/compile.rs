@@ -0,0 +1,345 @@
+//! Turns a render graph into a flat list of batches for drawing.
+//!
+//! Only the parts of the graph inside the viewport are drawn, which the
+//! graph's [`SpatialIndex`](super::spatial_index::SpatialIndex) finds quickly.
+//!
+//! Draws are merged into an earlier batch with the same pipeline and image as
+//! long as they don't overlap anything drawn in between, so the result looks
+//! the same as drawing the graph in painting order. Display lists are already
+//! compiled, so they are drawn as they are.
+
+use std::borrow::Cow;
+
+use geometry::{Offset, Px, Rect};
+
+use super::{
+    render_graph::to_u32, DisplayList, RectInstance, RenderGraph, RenderGraphCommand,
+    RoundedRectVertex,
+};
+
+/// How far back to look for a batch to merge a draw into. Keeps compiling
+/// linear in the number of draws.
+const SEARCH_DEPTH: usize = 16;
+
+/// How well the draws in a frame were batched together.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct BatchStats {
+    /// The number of draw commands in the viewport.
+    pub draws: usize,
+    /// The number of draw commands skipped because they were outside the
+    /// viewport.
+    pub culled: usize,
+    /// The number of draw calls they were batched into.
+    pub batches: usize,
+}
+
+impl BatchStats {
+    /// The number of draws that were merged into another draw's batch.
+    #[must_use]
+    pub fn merged(&self) -> usize {
+        self.draws - self.batches
+    }
+}
+
+/// One draw call.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Batch<I> {
+    Rects {
+        first_instance: u32,
+        num_instances: u32,
+        image: Option<I>,
+    },
+    /// Meshes only go without an image when drawn by debug views.
+    Mesh {
+        first_index: u32,
+        num_indices: u32,
+        image: Option<I>,
+    },
+    /// Draws the display list at `index` in the compiled graph's display
+    /// lists. Its images are not included in [`Batch::image`].
+    DisplayList { index: u32 },
+}
+
+impl<I: Copy> Batch<I> {
+    pub fn image(&self) -> Option<I> {
+        match *self {
+            Batch::Rects { image, .. } => image,
+            Batch::Mesh { image, .. } => image,
+            Batch::DisplayList { .. } => None,
+        }
+    }
+}
+
+/// A render graph flattened into batches, with the rect instances and mesh
+/// indices rearranged so that each batch's are contiguous.
+pub(crate) struct CompiledGraph<'a, I> {
+    /// Mesh vertices, shared with the render graph unless the compiled graph
+    /// has to outlive it.
+    pub vertices: Cow<'a, [RoundedRectVertex]>,
+    pub indices: Vec<u32>,
+    pub instances: Vec<RectInstance>,
+    pub display_lists: Vec<(DisplayList, Offset<f32, Px>)>,
+    pub batches: Vec<Batch<I>>,
+    pub stats: BatchStats,
+}
+
+impl<I> CompiledGraph<'_, I> {
+    pub fn into_owned(self) -> CompiledGraph<'static, I> {
+        CompiledGraph {
+            vertices: Cow::Owned(self.vertices.into_owned()),
+            indices: self.indices,
+            instances: self.instances,
+            display_lists: self.display_lists,
+            batches: self.batches,
+            stats: self.stats,
+        }
+    }
+}
+
+/// The state that draws in the same batch must share.
+#[derive(Clone, Copy, PartialEq)]
+enum Key<I> {
+    Rects(Option<I>),
+    Mesh(I),
+    /// Display lists are never batched together, so the key is the list's
+    /// index in the render graph, which is different for every draw.
+    DisplayList(u32),
+}
+
+struct Pending<'a, I> {
+    key: Key<I>,
+    bounds: Rect<f32, Px>,
+    /// The indices or rects of every draw in the batch, depending on `key`.
+    indices: Vec<&'a [u32]>,
+    rects: Vec<&'a [RectInstance]>,
+}
+
+impl<I: Copy + PartialEq> RenderGraph<I> {
+    /// Batches the draws that overlap `viewport`. Draws with nothing in them
+    /// are never visible, so they are culled too.
+    pub(crate) fn compile(&self, viewport: &Rect<f32, Px>) -> CompiledGraph<I> {
+        let visible = self.spatial_index().visible(self, viewport);
+        let mut pending: Vec<Pending<I>> = Vec::new();
+
+        for &node in &visible {
+            let bounds = self.bounds(node).expect("visible nodes have bounds");
+
+            let (key, indices, rects) = match self.get(node) {
+                RenderGraphCommand::Root => unreachable!("the root draws nothing"),
+                RenderGraphCommand::DrawRect {
+                    first_instance,
+                    num_instances,
+                    image,
+                } => {
+                    let first = *first_instance as usize;
+                    let rects = &self.imm_rect_instances[first..first + *num_instances as usize];
+                    (Key::Rects(*image), &[][..], rects)
+                }
+                RenderGraphCommand::DrawNineSlice {
+                    first_index,
+                    num_indices,
+                    image,
+                } => {
+                    let first = *first_index as usize;
+                    let indices = &self.imm_indices[first..first + *num_indices as usize];
+                    (Key::Mesh(*image), indices, &[][..])
+                }
+                RenderGraphCommand::DrawDisplayList { index } => {
+                    (Key::DisplayList(*index), &[][..], &[][..])
+                }
+            };
+
+            let mut target = None;
+            for (i, batch) in pending.iter().enumerate().rev().take(SEARCH_DEPTH) {
+                if batch.key == key {
+                    target = Some(i);
+                    break;
+                }
+
+                // Moving the draw before this batch would change the result.
+                if batch.bounds.intersects(&bounds) {
+                    break;
+                }
+            }
+
+            if let Some(batch) = target.map(|i| &mut pending[i]) {
+                batch.bounds = batch.bounds.union(&bounds);
+                batch.indices.push(indices);
+                batch.rects.push(rects);
+            } else {
+                pending.push(Pending {
+                    key,
+                    bounds,
+                    indices: vec![indices],
+                    rects: vec![rects],
+                });
+            }
+        }
+
+        let mut compiled = CompiledGraph {
+            vertices: Cow::Borrowed(&self.imm_rect_vertices),
+            indices: Vec::new(),
+            instances: Vec::new(),
+            display_lists: Vec::new(),
+            batches: Vec::with_capacity(pending.len()),
+            stats: BatchStats {
+                draws: visible.len(),
+                // Not counting the root.
+                culled: self.num_nodes() - 1 - visible.len(),
+                batches: pending.len(),
+            },
+        };
+
+        for batch in pending {
+            compiled.batches.push(match batch.key {
+                Key::Rects(image) => {
+                    let first_instance = compiled.instances.len();
+                    for rects in batch.rects {
+                        compiled.instances.extend_from_slice(rects);
+                    }
+
+                    Batch::Rects {
+                        first_instance: to_u32(first_instance),
+                        num_instances: to_u32(compiled.instances.len() - first_instance),
+                        image,
+                    }
+                }
+                Key::Mesh(image) => {
+                    let first_index = compiled.indices.len();
+                    for indices in batch.indices {
+                        compiled.indices.extend_from_slice(indices);
+                    }
+
+                    Batch::Mesh {
+                        first_index: to_u32(first_index),
+                        num_indices: to_u32(compiled.indices.len() - first_index),
+                        image: Some(image),
+                    }
+                }
+                Key::DisplayList(index) => {
+                    let list = self.display_lists[index as usize].clone();
+                    compiled.display_lists.push(list);
+
+                    Batch::DisplayList {
+                        index: to_u32(compiled.display_lists.len() - 1),
+                    }
+                }
+            });
+        }
+
+        compiled
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use geometry::{Extent, Point};
+
+    use crate::graphics::{DrawRect, RenderGraphNodeId};
+
+    use super::*;
+
+    fn rect(x: f32, y: f32) -> RectInstance {
+        DrawRect::new(Rect::new(Point::new(x, y), Extent::new(10.0, 10.0))).to_instance()
+    }
+
+    /// Draws rects with images given by index, at the given positions.
+    fn graph(draws: &[(Option<u32>, f32, f32)]) -> RenderGraph<u32> {
+        let mut graph = RenderGraph::<u32>::default();
+        for (image, x, y) in draws {
+            graph.imm_rect_instances.push(rect(*x, *y));
+            graph.push_node(
+                RenderGraphNodeId::root(),
+                RenderGraphCommand::DrawRect {
+                    first_instance: to_u32(graph.imm_rect_instances.len() - 1),
+                    num_instances: 1,
+                    image: *image,
+                },
+            );
+        }
+        graph
+    }
+
+    fn viewport() -> Rect<f32, Px> {
+        Rect::new(Point::new(0.0, 0.0), Extent::new(100.0, 100.0))
+    }
+
+    fn images(compiled: &CompiledGraph<u32>) -> Vec<(Option<u32>, u32)> {
+        compiled
+            .batches
+            .iter()
+            .map(|batch| match batch {
+                Batch::Rects {
+                    num_instances,
+                    image,
+                    ..
+                } => (*image, *num_instances),
+                Batch::Mesh { .. } | Batch::DisplayList { .. } => unreachable!(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn merges_adjacent_draws() {
+        let graph = graph(&[(None, 0.0, 0.0), (None, 20.0, 0.0), (Some(0), 40.0, 0.0)]);
+        let compiled = graph.compile(&viewport());
+
+        assert_eq!(images(&compiled), [(None, 2), (Some(0), 1)]);
+        assert_eq!(compiled.stats.merged(), 1);
+        assert_eq!(
+            compiled.instances,
+            [rect(0.0, 0.0), rect(20.0, 0.0), rect(40.0, 0.0)]
+        );
+    }
+
+    #[test]
+    fn reorders_disjoint_draws() {
+        // Alternating images, side by side, like icons next to labels.
+        let graph = graph(&[
+            (Some(0), 0.0, 0.0),
+            (None, 10.0, 0.0),
+            (Some(0), 0.0, 20.0),
+            (None, 10.0, 20.0),
+        ]);
+        let compiled = graph.compile(&viewport());
+
+        assert_eq!(images(&compiled), [(Some(0), 2), (None, 2)]);
+        assert_eq!(
+            compiled.instances,
+            [
+                rect(0.0, 0.0),
+                rect(0.0, 20.0),
+                rect(10.0, 0.0),
+                rect(10.0, 20.0)
+            ]
+        );
+        assert_eq!(
+            compiled.stats,
+            BatchStats {
+                draws: 4,
+                culled: 0,
+                batches: 2
+            }
+        );
+    }
+
+    #[test]
+    fn preserves_painting_order() {
+        // The third draw covers the second, so it can't move before it.
+        let graph = graph(&[(Some(0), 0.0, 0.0), (None, 20.0, 0.0), (Some(0), 25.0, 5.0)]);
+        let compiled = graph.compile(&viewport());
+
+        assert_eq!(images(&compiled), [(Some(0), 1), (None, 1), (Some(0), 1)]);
+        assert_eq!(compiled.stats.merged(), 0);
+    }
+
+    #[test]
+    fn culls_draws_outside_viewport() {
+        let graph = graph(&[(None, 0.0, 0.0), (Some(0), 200.0, 0.0), (None, 95.0, 95.0)]);
+        let compiled = graph.compile(&viewport());
+
+        assert_eq!(images(&compiled), [(None, 2)]);
+        assert_eq!(compiled.stats.culled, 1);
+        assert_eq!(compiled.instances, [rect(0.0, 0.0), rect(95.0, 95.0)]);
+    }
+}
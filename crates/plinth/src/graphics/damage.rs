@@ -0,0 +1,294 @@
+use std::mem::discriminant;
+
+use geometry::{Offset, Point, Px, Rect};
+
+use super::{DisplayList, Image, RectInstance, RenderGraph, RenderGraphCommand, RoundedRectVertex};
+
+/// One draw command, as seen by damage tracking.
+struct Draw<'a, I> {
+    command: &'a RenderGraphCommand<I>,
+    image: Option<&'a I>,
+    shapes: Shapes<'a>,
+}
+
+enum Shapes<'a> {
+    Mesh {
+        indices: &'a [u32],
+        vertices: &'a [RoundedRectVertex],
+    },
+    Rects(&'a [RectInstance]),
+    DisplayList(&'a DisplayList, Offset<f32, Px>),
+}
+
+impl<'a, I: PartialEq> Draw<'a, I> {
+    /// Two draws are the same if they draw the same shapes with the same
+    /// image, regardless of where their vertices are stored.
+    fn same_as(&self, other: &Draw<I>) -> bool {
+        let same_shapes = match (&self.shapes, &other.shapes) {
+            (Shapes::Mesh { indices: a, .. }, Shapes::Mesh { indices: b, .. }) => {
+                a.len() == b.len()
+                    && self
+                        .mesh_vertices()
+                        .zip(other.mesh_vertices())
+                        .all(|(a, b)| same_vertex(a, b))
+            }
+            (Shapes::Rects(a), Shapes::Rects(b)) => a == b,
+            (Shapes::DisplayList(a, a_offset), Shapes::DisplayList(b, b_offset)) => {
+                a.ptr_eq(b) && a_offset == b_offset
+            }
+            _ => false,
+        };
+
+        discriminant(self.command) == discriminant(other.command)
+            && self.image == other.image
+            && same_shapes
+    }
+
+    fn mesh_vertices(&self) -> impl Iterator<Item = &'a RoundedRectVertex> + '_ {
+        let (indices, vertices) = match self.shapes {
+            Shapes::Mesh { indices, vertices } => (indices, vertices),
+            Shapes::Rects(_) | Shapes::DisplayList(..) => (&[][..], &[][..]),
+        };
+
+        indices.iter().map(|i| &vertices[*i as usize])
+    }
+
+    fn bounds(&self) -> Option<Rect<f32, Px>> {
+        match self.shapes {
+            Shapes::Rects(rects) => {
+                return rects
+                    .iter()
+                    .map(RectInstance::bounds)
+                    .reduce(|a, b| a.union(&b));
+            }
+            Shapes::DisplayList(list, offset) => return list.bounds().map(|b| b + offset),
+            Shapes::Mesh { .. } => {}
+        }
+
+        let mut vertices = self.mesh_vertices();
+        let first = vertices.next()?.position;
+
+        let (min, max) = vertices.fold((first, first), |(min, max), vertex| {
+            (min.min(vertex.position), max.max(vertex.position))
+        });
+
+        Some(Rect::from_points(min, max))
+    }
+}
+
+impl<I: PartialEq> RenderGraph<I> {
+    /// Finds the region that changed since `previous` was drawn, or `None` if
+    /// both graphs draw the same thing.
+    ///
+    /// Draws are compared in painting order, so inserting or removing a draw
+    /// damages every draw after it.
+    ///
+    /// Only the graphs are compared, so draws of images whose contents were
+    /// updated aren't damaged unless something else about them changed. Use
+    /// [`GraphicsContext::damage`](super::GraphicsContext::damage) to include
+    /// them.
+    pub fn damage(&self, previous: &RenderGraph<I>) -> Option<Rect<f32, Px>> {
+        self.damage_where(previous, |_| false)
+    }
+
+    /// Like [`Self::damage`], but also damaging every current draw for which
+    /// `changed` returns true.
+    fn damage_where(
+        &self,
+        previous: &RenderGraph<I>,
+        changed: impl Fn(&Draw<I>) -> bool,
+    ) -> Option<Rect<f32, Px>> {
+        let current = self.draws();
+        let previous = previous.draws();
+
+        let mut damage: Option<Rect<f32, Px>> = None;
+        let mut add = |draw: &Draw<I>| {
+            if let Some(bounds) = draw.bounds() {
+                damage = Some(damage.map_or(bounds, |damage| damage.union(&bounds)));
+            }
+        };
+
+        for (a, b) in current.iter().zip(&previous) {
+            if !a.same_as(b) {
+                add(a);
+                add(b);
+            } else if changed(a) {
+                add(a);
+            }
+        }
+
+        let common = current.len().min(previous.len());
+        for draw in current[common..].iter().chain(&previous[common..]) {
+            add(draw);
+        }
+
+        damage
+    }
+
+    /// Every draw in the graph, in painting order.
+    fn draws(&self) -> Vec<Draw<'_, I>> {
+        self.paint_order()
+            .into_iter()
+            .filter_map(|command| {
+                let (image, shapes) = match command {
+                    RenderGraphCommand::Root => return None,
+                    RenderGraphCommand::DrawRect {
+                        first_instance,
+                        num_instances,
+                        image,
+                    } => {
+                        let first = *first_instance as usize;
+                        let rects =
+                            &self.imm_rect_instances[first..first + *num_instances as usize];
+                        (image.as_ref(), Shapes::Rects(rects))
+                    }
+                    RenderGraphCommand::DrawNineSlice {
+                        first_index,
+                        num_indices,
+                        image,
+                    } => {
+                        let first = *first_index as usize;
+                        let indices = &self.imm_indices[first..first + *num_indices as usize];
+                        let shapes = Shapes::Mesh {
+                            indices,
+                            vertices: &self.imm_rect_vertices,
+                        };
+                        (Some(image), shapes)
+                    }
+                    RenderGraphCommand::DrawDisplayList { index } => {
+                        let (list, offset) = &self.display_lists[*index as usize];
+                        (None, Shapes::DisplayList(list, *offset))
+                    }
+                };
+
+                Some(Draw {
+                    command,
+                    image,
+                    shapes,
+                })
+            })
+            .collect()
+    }
+}
+
+impl RenderGraph {
+    /// Like [`Self::damage`], but also damaging draws of the `updated` images,
+    /// including those inside display lists.
+    pub(crate) fn damage_with_updated(
+        &self,
+        previous: &RenderGraph,
+        updated: &[Image],
+    ) -> Option<Rect<f32, Px>> {
+        self.damage_where(previous, |draw| match draw.shapes {
+            Shapes::DisplayList(list, _) => list.data().images.iter().any(|i| updated.contains(i)),
+            Shapes::Mesh { .. } | Shapes::Rects(_) => {
+                draw.image.is_some_and(|i| updated.contains(i))
+            }
+        })
+    }
+}
+
+fn same_vertex(a: &RoundedRectVertex, b: &RoundedRectVertex) -> bool {
+    let same_point = |a: Point<f32, Px>, b: Point<f32, Px>| a.x == b.x && a.y == b.y;
+
+    same_point(a.position, b.position)
+        && a.rect_size.width == b.rect_size.width
+        && a.rect_size.height == b.rect_size.height
+        && same_point(a.rect_center, b.rect_center)
+        && a.outer_radii == b.outer_radii
+        && a.inner_radii == b.inner_radii
+        && [a.color.r, a.color.g, a.color.b, a.color.a]
+            == [b.color.r, b.color.g, b.color.b, b.color.a]
+        && same_point(a.uv, b.uv)
+}
+
+#[cfg(test)]
+mod tests {
+    use geometry::{Extent, Point};
+
+    use crate::graphics::{Color, DrawRect, RenderGraphNodeId};
+
+    use super::*;
+
+    fn rect(x: f32, y: f32, width: f32, height: f32) -> DrawRect {
+        DrawRect::new(Rect::new(Point::new(x, y), Extent::new(width, height)))
+    }
+
+    fn graph(rects: &[DrawRect]) -> RenderGraph {
+        let mut graph = RenderGraph::new();
+        for rect in rects {
+            graph.draw_rect(RenderGraphNodeId::root(), rect);
+        }
+        graph
+    }
+
+    fn edges(rect: Option<Rect<f32, Px>>) -> Option<[f32; 4]> {
+        rect.map(|r| [r.left(), r.top(), r.right(), r.bottom()])
+    }
+
+    #[test]
+    fn identical_graphs() {
+        let rects = [rect(0.0, 0.0, 10.0, 10.0), rect(20.0, 0.0, 10.0, 10.0)];
+        assert!(graph(&rects).damage(&graph(&rects)).is_none());
+        assert!(RenderGraph::new().damage(&RenderGraph::new()).is_none());
+    }
+
+    #[test]
+    fn changed_draw() {
+        let before = graph(&[rect(0.0, 0.0, 10.0, 10.0), rect(20.0, 0.0, 10.0, 10.0)]);
+
+        // Only the color changes.
+        let after = graph(&[
+            rect(0.0, 0.0, 10.0, 10.0),
+            rect(20.0, 0.0, 10.0, 10.0).with_color(Color::RED),
+        ]);
+        assert_eq!(edges(after.damage(&before)), Some([20.0, 0.0, 30.0, 10.0]));
+
+        // Moving a draw damages both where it was and where it is.
+        let after = graph(&[rect(0.0, 0.0, 10.0, 10.0), rect(20.0, 30.0, 10.0, 10.0)]);
+        assert_eq!(edges(after.damage(&before)), Some([20.0, 0.0, 30.0, 40.0]));
+    }
+
+    #[test]
+    fn changed_contents() {
+        // The graphs are the same, but the second draw's image was updated.
+        let rects = [rect(0.0, 0.0, 10.0, 10.0), rect(20.0, 0.0, 10.0, 10.0)];
+        let updated = |draw: &Draw<_>| draw.bounds().is_some_and(|b| b.left() == 20.0);
+
+        assert_eq!(
+            edges(graph(&rects).damage_where(&graph(&rects), updated)),
+            Some([20.0, 0.0, 30.0, 10.0])
+        );
+    }
+
+    #[test]
+    fn added_and_removed_draws() {
+        let without_caret = graph(&[rect(0.0, 0.0, 100.0, 20.0)]);
+        let with_caret = graph(&[rect(0.0, 0.0, 100.0, 20.0), rect(50.0, 2.0, 1.0, 16.0)]);
+
+        assert_eq!(
+            edges(with_caret.damage(&without_caret)),
+            Some([50.0, 2.0, 51.0, 18.0])
+        );
+        assert_eq!(
+            edges(without_caret.damage(&with_caret)),
+            Some([50.0, 2.0, 51.0, 18.0])
+        );
+    }
+
+    #[test]
+    fn structure_does_not_matter() {
+        // The same draws, nested differently, paint the same thing.
+        let flat = graph(&[rect(0.0, 0.0, 10.0, 10.0), rect(20.0, 0.0, 10.0, 10.0)]);
+
+        let mut nested = RenderGraph::new();
+        nested.draw_rect(RenderGraphNodeId::root(), &rect(0.0, 0.0, 10.0, 10.0));
+        let parent = nested
+            .iter_children(RenderGraphNodeId::root())
+            .next()
+            .unwrap();
+        nested.draw_rect(parent, &rect(20.0, 0.0, 10.0, 10.0));
+
+        assert!(nested.damage(&flat).is_none());
+    }
+}
@@ -0,0 +1,282 @@
+//! Visualizations of what the renderer draws, for debugging layouts.
+//!
+//! They are applied to the compiled graph, so the GPU and software renderers
+//! draw them the same way.
+
+use geometry::{Extent, Offset, Point, Px, Rect};
+
+use super::{
+    compile::{Batch, CompiledGraph},
+    render_graph::to_u32,
+    Color, DrawRect, RenderGraph, RoundedRectVertex,
+};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DebugView {
+    /// Draws normally.
+    #[default]
+    Off,
+    /// Outlines the bounds of every node that was drawn.
+    Bounds,
+    /// Draws every shape in the same translucent color, without images, so
+    /// pixels get redder the more times they are drawn over.
+    Overdraw,
+    /// Outlines every triangle on top of the frame.
+    Wireframe,
+}
+
+const BOUNDS_COLOR: Color = Color {
+    r: 1.0,
+    g: 0.0,
+    b: 1.0,
+    a: 1.0,
+};
+
+const OVERDRAW_COLOR: Color = Color {
+    r: 1.0,
+    g: 0.0,
+    b: 0.0,
+    a: 0.25,
+};
+
+const WIREFRAME_COLOR: Color = Color {
+    r: 0.0,
+    g: 0.8,
+    b: 0.0,
+    a: 1.0,
+};
+
+impl<I: Copy + PartialEq> RenderGraph<I> {
+    /// Like [`Self::compile`], but visualized as `view` describes. The contents
+    /// of display lists are only visualized if they have been inlined.
+    pub(crate) fn compile_debug(
+        &self,
+        viewport: &Rect<f32, Px>,
+        view: DebugView,
+    ) -> CompiledGraph<I> {
+        let mut compiled = self.compile(viewport);
+
+        match view {
+            DebugView::Off => {}
+            DebugView::Bounds => {
+                let first_instance = compiled.instances.len();
+
+                for node in self.spatial_index().visible(self, viewport) {
+                    let bounds = self.bounds(node).expect("visible nodes have bounds");
+                    for edge in outline(&bounds) {
+                        compiled
+                            .instances
+                            .push(DrawRect::new(edge).with_color(BOUNDS_COLOR).to_instance());
+                    }
+                }
+
+                compiled.batches.push(Batch::Rects {
+                    first_instance: to_u32(first_instance),
+                    num_instances: to_u32(compiled.instances.len() - first_instance),
+                    image: None,
+                });
+            }
+            DebugView::Overdraw => {
+                let color = OVERDRAW_COLOR.to_f16();
+                for instance in &mut compiled.instances {
+                    instance.colors = [color; 4];
+                }
+
+                for vertex in compiled.vertices.to_mut() {
+                    vertex.color = OVERDRAW_COLOR;
+                }
+
+                for batch in &mut compiled.batches {
+                    match batch {
+                        Batch::Rects { image, .. } => *image = None,
+                        Batch::Mesh { image, .. } => *image = None,
+                        Batch::DisplayList { .. } => {}
+                    }
+                }
+            }
+            DebugView::Wireframe => {
+                let mut triangles = Vec::new();
+                for batch in &compiled.batches {
+                    match *batch {
+                        Batch::Rects {
+                            first_instance,
+                            num_instances,
+                            ..
+                        } => {
+                            let first = first_instance as usize;
+                            for rect in &compiled.instances[first..first + num_instances as usize] {
+                                let [a, b, c, d] = rect.to_vertices().map(|v| v.position);
+                                triangles.push([a, b, c]);
+                                triangles.push([a, c, d]);
+                            }
+                        }
+                        Batch::Mesh {
+                            first_index,
+                            num_indices,
+                            ..
+                        } => {
+                            let first = first_index as usize;
+                            for triangle in compiled.indices[first..first + num_indices as usize]
+                                .chunks_exact(3)
+                            {
+                                triangles.push(
+                                    [0, 1, 2]
+                                        .map(|i| compiled.vertices[triangle[i] as usize].position),
+                                );
+                            }
+                        }
+                        Batch::DisplayList { .. } => {}
+                    }
+                }
+
+                let first_index = compiled.indices.len();
+                for [a, b, c] in triangles {
+                    for (from, to) in [(a, b), (b, c), (c, a)] {
+                        push_line(&mut compiled, from, to);
+                    }
+                }
+
+                compiled.batches.push(Batch::Mesh {
+                    first_index: to_u32(first_index),
+                    num_indices: to_u32(compiled.indices.len() - first_index),
+                    image: None,
+                });
+            }
+        }
+
+        compiled
+    }
+}
+
+/// One pixel wide rects along the inside of each edge of `bounds`.
+fn outline(bounds: &Rect<f32, Px>) -> [Rect<f32, Px>; 4] {
+    let (left, top) = (bounds.left(), bounds.top());
+    let Extent { width, height, .. } = bounds.extent();
+    let thickness = 1.0_f32.min(width / 2.0).min(height / 2.0);
+
+    [
+        Rect::new(Point::new(left, top), Extent::new(width, thickness)),
+        Rect::new(
+            Point::new(left, bounds.bottom() - thickness),
+            Extent::new(width, thickness),
+        ),
+        Rect::new(Point::new(left, top), Extent::new(thickness, height)),
+        Rect::new(
+            Point::new(bounds.right() - thickness, top),
+            Extent::new(thickness, height),
+        ),
+    ]
+}
+
+/// Adds a one pixel wide line to the compiled graph's mesh.
+fn push_line<I>(compiled: &mut CompiledGraph<I>, from: Point<f32, Px>, to: Point<f32, Px>) {
+    let direction = to - from;
+    let length = direction.length();
+    if length == 0.0 {
+        return;
+    }
+
+    let normal = Offset::new(-direction.y, direction.x) * (0.5 / length);
+
+    let vertex = |position| RoundedRectVertex {
+        position,
+        // A rect far larger than the line, so that all of it is drawn.
+        rect_size: Extent::new(1.0e4, 1.0e4),
+        rect_center: from.lerp(to, 0.5),
+        outer_radii: [0.0; 4],
+        inner_radii: [0.0; 4],
+        color: WIREFRAME_COLOR,
+        uv: Point::zero(),
+    };
+
+    let vertices = compiled.vertices.to_mut();
+    let first = to_u32(vertices.len());
+    vertices.extend([from + normal, to + normal, to - normal, from - normal].map(vertex));
+
+    compiled
+        .indices
+        .extend([0, 1, 2, 0, 2, 3].map(|i| first + i));
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::graphics::{software, RenderGraphNodeId};
+
+    use super::*;
+
+    fn graph() -> RenderGraph<u32> {
+        let rect = DrawRect::new(Rect::new(Point::new(10.0, 10.0), Extent::new(20.0, 20.0)));
+
+        let mut graph = RenderGraph::new();
+        graph.draw_rect(RenderGraphNodeId::root(), &rect);
+        graph.draw_rect(RenderGraphNodeId::root(), &rect);
+        graph.map_images(|_| unreachable!())
+    }
+
+    fn viewport() -> Rect<f32, Px> {
+        Rect::new(Point::new(0.0, 0.0), Extent::new(100.0, 100.0))
+    }
+
+    #[test]
+    fn off_changes_nothing() {
+        let graph = graph();
+        let compiled = graph.compile_debug(&viewport(), DebugView::Off);
+        assert_eq!(compiled.batches, graph.compile(&viewport()).batches);
+    }
+
+    #[test]
+    fn bounds() {
+        let graph = graph();
+        let compiled = graph.compile_debug(&viewport(), DebugView::Bounds);
+
+        // Four edges for each rect, drawn after everything else.
+        assert_eq!(compiled.instances.len(), 2 + 8);
+        assert_eq!(
+            compiled.batches.last(),
+            Some(&Batch::Rects {
+                first_instance: 2,
+                num_instances: 8,
+                image: None
+            })
+        );
+        assert_eq!(
+            compiled.instances[2].bounds().extent(),
+            Extent::new(20.0, 1.0)
+        );
+    }
+
+    #[test]
+    fn overdraw() {
+        let frame = software::render(&graph(), &[], Extent::new(40, 40), DebugView::Overdraw);
+        let pixel = |x: usize, y: usize| {
+            let offset = (y * 40 + x) * 4;
+            frame.bytes()[offset..offset + 4].to_vec()
+        };
+
+        // White where nothing was drawn, and less green and blue with each
+        // rect drawn over the same pixel.
+        assert_eq!(pixel(5, 5), [255, 255, 255, 255]);
+        let [r, g, ..] = pixel(20, 20)[..] else {
+            unreachable!()
+        };
+        assert_eq!(r, 255);
+        assert!(g < 200 && g > 100);
+    }
+
+    #[test]
+    fn wireframe() {
+        let graph = graph();
+        let compiled = graph.compile_debug(&viewport(), DebugView::Wireframe);
+
+        // Two triangles per rect, three lines per triangle, and a quad per line.
+        assert_eq!(compiled.vertices.len(), 2 * 2 * 3 * 4);
+        assert_eq!(
+            compiled.batches.last(),
+            Some(&Batch::Mesh {
+                first_index: 0,
+                num_indices: 2 * 2 * 3 * 6,
+                image: None
+            })
+        );
+    }
+}
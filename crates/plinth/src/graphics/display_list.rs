@@ -0,0 +1,216 @@
+//! Render graphs that are built once and drawn in many frames.
+
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc, Weak,
+};
+
+use geometry::{Point, Px, Rect};
+
+use super::{compile::CompiledGraph, Image, RenderGraph, RenderGraphNodeId};
+
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+/// A render graph that has been frozen so that it can be drawn into other
+/// render graphs with [`RenderGraph::draw_display_list`], at any offset.
+///
+/// Display lists are compiled once, and the GPU keeps their vertex data until
+/// the last clone of the list is dropped, so drawing one costs about as much as
+/// a single draw no matter how much is in it. This suits parts of the UI that
+/// rarely change, like toolbars and sidebars.
+///
+/// Cloning a display list is cheap, since the clones share the same data, and
+/// like render graphs, display lists can be built on other threads.
+#[derive(Clone)]
+pub struct DisplayList {
+    inner: Arc<DisplayListData>,
+}
+
+pub(crate) struct DisplayListData {
+    /// Identifies the list's vertex data on the GPU.
+    pub id: u64,
+    pub graph: RenderGraph,
+    pub compiled: CompiledGraph<'static, Image>,
+    /// Every image drawn by the list, including by display lists inside it.
+    pub images: Vec<Image>,
+}
+
+impl DisplayList {
+    #[must_use]
+    pub fn new(graph: RenderGraph) -> Self {
+        // Display lists are culled as a whole, so compile everything.
+        let everything = Rect::from_points(Point::splat(f32::MIN), Point::splat(f32::MAX));
+        let compiled = graph.compile(&everything).into_owned();
+
+        let mut images = Vec::new();
+        for image in graph.images() {
+            if !images.contains(&image) {
+                images.push(image);
+            }
+        }
+
+        Self {
+            inner: Arc::new(DisplayListData {
+                id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+                graph,
+                compiled,
+                images,
+            }),
+        }
+    }
+
+    /// The bounds of everything in the list, or `None` if it is empty.
+    #[must_use]
+    pub fn bounds(&self) -> Option<Rect<f32, Px>> {
+        self.inner
+            .graph
+            .spatial_index()
+            .bounds(RenderGraphNodeId::root())
+    }
+
+    pub(crate) fn data(&self) -> &DisplayListData {
+        &self.inner
+    }
+
+    /// Lets the platform find out when the list has been dropped, so that it
+    /// can free the list's vertex data.
+    pub(crate) fn downgrade(&self) -> Weak<DisplayListData> {
+        Arc::downgrade(&self.inner)
+    }
+
+    /// Whether both lists are clones of the same list.
+    pub(crate) fn ptr_eq(&self, other: &DisplayList) -> bool {
+        Arc::ptr_eq(&self.inner, &other.inner)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use geometry::{Extent, Offset};
+
+    use crate::graphics::{compile::Batch, DrawRect, RenderGraphCommand};
+
+    use super::*;
+
+    fn rect(x: f32, y: f32) -> DrawRect {
+        DrawRect::new(Rect::new(Point::new(x, y), Extent::new(10.0, 10.0)))
+    }
+
+    fn toolbar() -> DisplayList {
+        let mut graph = RenderGraph::new();
+        graph.draw_rect(RenderGraphNodeId::root(), &rect(0.0, 0.0));
+        graph.draw_rect(RenderGraphNodeId::root(), &rect(20.0, 0.0));
+        DisplayList::new(graph)
+    }
+
+    fn edges(rect: Option<Rect<f32, Px>>) -> Option<[f32; 4]> {
+        rect.map(|r| [r.left(), r.top(), r.right(), r.bottom()])
+    }
+
+    #[test]
+    fn drawn_as_one_batch() {
+        let toolbar = toolbar();
+        assert_eq!(edges(toolbar.bounds()), Some([0.0, 0.0, 30.0, 10.0]));
+
+        let mut graph = RenderGraph::new();
+        graph.draw_display_list(RenderGraphNodeId::root(), &toolbar, Offset::new(0.0, 100.0));
+        graph.draw_rect(RenderGraphNodeId::root(), &rect(0.0, 0.0));
+
+        let viewport = Rect::new(Point::new(0.0, 0.0), Extent::new(200.0, 200.0));
+        let compiled = graph.compile(&viewport);
+        assert!(matches!(
+            compiled.batches[..],
+            [Batch::DisplayList { index: 0 }, Batch::Rects { .. }]
+        ));
+        assert!(compiled.display_lists[0].0.ptr_eq(&toolbar));
+
+        // Culled as a whole, using the list's bounds at its offset.
+        let viewport = Rect::new(Point::new(0.0, 0.0), Extent::new(200.0, 50.0));
+        let compiled = graph.compile(&viewport);
+        assert_eq!(compiled.stats.culled, 1);
+        assert!(compiled.display_lists.is_empty());
+    }
+
+    #[test]
+    fn inlined_at_offset() {
+        let mut nested = RenderGraph::new();
+        nested.draw_display_list(RenderGraphNodeId::root(), &toolbar(), Offset::new(5.0, 0.0));
+        let nested = DisplayList::new(nested);
+
+        let mut graph = RenderGraph::new();
+        graph.draw_rect(RenderGraphNodeId::root(), &rect(0.0, 0.0));
+        graph.draw_display_list(RenderGraphNodeId::root(), &nested, Offset::new(0.0, 50.0));
+
+        let inlined = graph.inline_display_lists();
+        assert!(inlined.display_lists.is_empty());
+
+        let lefts_and_tops = inlined
+            .paint_order()
+            .into_iter()
+            .filter_map(|command| match command {
+                RenderGraphCommand::DrawRect {
+                    first_instance,
+                    num_instances: 1,
+                    ..
+                } => {
+                    let bounds = inlined.imm_rect_instances[*first_instance as usize].bounds();
+                    Some((bounds.left(), bounds.top()))
+                }
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(lefts_and_tops, [(0.0, 0.0), (5.0, 50.0), (25.0, 50.0)]);
+    }
+
+    #[test]
+    fn children_are_inlined_over_the_list() {
+        let mut graph = RenderGraph::new();
+        let node =
+            graph.draw_display_list(RenderGraphNodeId::root(), &toolbar(), Offset::new(0.0, 0.0));
+        graph.draw_rect(node, &rect(100.0, 100.0));
+
+        let inlined = graph.inline_display_lists();
+        let lefts = inlined
+            .paint_order()
+            .into_iter()
+            .filter_map(|command| match command {
+                RenderGraphCommand::DrawRect {
+                    first_instance,
+                    num_instances: 1,
+                    ..
+                } => Some(
+                    inlined.imm_rect_instances[*first_instance as usize]
+                        .bounds()
+                        .left(),
+                ),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+
+        // The child is painted after everything in the list, as on the GPU.
+        assert_eq!(lefts, [0.0, 20.0, 100.0]);
+    }
+
+    #[test]
+    fn damage() {
+        let list = toolbar();
+        let draw = |offset: Offset<f32, Px>| {
+            let mut graph = RenderGraph::new();
+            graph.draw_display_list(RenderGraphNodeId::root(), &list, offset);
+            graph
+        };
+
+        let before = draw(Offset::new(0.0, 0.0));
+        assert!(draw(Offset::new(0.0, 0.0)).damage(&before).is_none());
+        assert_eq!(
+            edges(draw(Offset::new(0.0, 20.0)).damage(&before)),
+            Some([0.0, 0.0, 30.0, 30.0])
+        );
+
+        // An identical list is still a different list.
+        let mut other = RenderGraph::new();
+        other.draw_display_list(RenderGraphNodeId::root(), &toolbar(), Offset::zero());
+        assert!(other.damage(&before).is_some());
+    }
+}
@@ -0,0 +1,140 @@
+//! What it cost to draw each frame, for finding heavy frames and showing in
+//! performance overlays.
+
+use std::{collections::VecDeque, time::Duration};
+
+use super::BatchStats;
+
+/// How many frames [`FrameHistory`] keeps by default, about two seconds at
+/// 60 Hz.
+const DEFAULT_HISTORY_LEN: usize = 120;
+
+/// The work done to draw one frame.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct FrameStats {
+    /// How the graph's draws were culled and batched.
+    pub batches: BatchStats,
+    /// The number of draw calls recorded, including those inside display
+    /// lists.
+    pub draw_calls: usize,
+    /// The number of mesh vertices uploaded for the frame. Display lists keep
+    /// theirs on the GPU, so they aren't counted.
+    pub vertices_uploaded: usize,
+    /// The number of mesh indices uploaded for the frame.
+    pub indices_uploaded: usize,
+    /// The number of rect instances uploaded for the frame.
+    pub instances_uploaded: usize,
+    /// The number of bytes the frame took from the upload buffer, including
+    /// alignment padding.
+    pub upload_bytes: u64,
+    /// The number of times a texture was bound for a draw call.
+    pub textures_bound: usize,
+    /// The CPU time spent compiling the graph and recording its commands, not
+    /// including time spent waiting for the GPU.
+    pub record_time: Duration,
+}
+
+/// The stats of the most recent frames, oldest first.
+#[derive(Clone, Debug)]
+pub struct FrameHistory {
+    frames: VecDeque<FrameStats>,
+    capacity: usize,
+}
+
+impl FrameHistory {
+    /// Keeps the stats of the last `capacity` frames.
+    #[must_use]
+    pub fn new(capacity: usize) -> Self {
+        assert!(capacity > 0, "the history must keep at least one frame");
+        Self {
+            frames: VecDeque::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    pub fn push(&mut self, stats: FrameStats) {
+        if self.frames.len() == self.capacity {
+            self.frames.pop_front();
+        }
+        self.frames.push_back(stats);
+    }
+
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    /// The most recent frame's stats.
+    #[must_use]
+    pub fn latest(&self) -> Option<&FrameStats> {
+        self.frames.back()
+    }
+
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = &FrameStats> + ExactSizeIterator {
+        self.frames.iter()
+    }
+
+    /// The average time spent recording each frame, or zero if there are no
+    /// frames.
+    #[must_use]
+    pub fn average_record_time(&self) -> Duration {
+        let total = self
+            .frames
+            .iter()
+            .map(|frame| frame.record_time)
+            .sum::<Duration>();
+        let len = u32::try_from(self.frames.len()).unwrap_or(u32::MAX);
+        total.checked_div(len).unwrap_or_default()
+    }
+
+    /// The frame that took the longest to record.
+    #[must_use]
+    pub fn slowest(&self) -> Option<&FrameStats> {
+        self.frames.iter().max_by_key(|frame| frame.record_time)
+    }
+}
+
+impl Default for FrameHistory {
+    fn default() -> Self {
+        Self::new(DEFAULT_HISTORY_LEN)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(millis: u64) -> FrameStats {
+        FrameStats {
+            record_time: Duration::from_millis(millis),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn keeps_the_most_recent_frames() {
+        let mut history = FrameHistory::new(3);
+        assert!(history.latest().is_none());
+        assert_eq!(history.average_record_time(), Duration::ZERO);
+
+        for millis in 1..=5 {
+            history.push(frame(millis));
+        }
+
+        assert_eq!(history.len(), 3);
+        let times = history
+            .iter()
+            .map(|frame| frame.record_time.as_millis())
+            .collect::<Vec<_>>();
+        assert_eq!(times, [3, 4, 5]);
+
+        assert_eq!(history.latest(), Some(&frame(5)));
+        assert_eq!(history.slowest(), Some(&frame(5)));
+        assert_eq!(history.average_record_time(), Duration::from_millis(4));
+    }
+}
@@ -0,0 +1,162 @@
+use geometry::{Point, Px};
+
+use super::{RectInstance, RenderGraph, RenderGraphCommand, RenderGraphNodeId};
+
+impl<I> RenderGraph<I> {
+    /// Finds the nodes that draw over `point`, front to back.
+    ///
+    /// Rects are hit where they would be drawn, so points in their rounded
+    /// corners miss, and nine-slices are hit inside any of their triangles. A
+    /// display list is a single node, which is hit if anything in it is.
+    pub fn hit_test(&self, point: Point<f32, Px>) -> impl Iterator<Item = RenderGraphNodeId> + '_ {
+        let candidates = self.spatial_index().at_point(self, point);
+
+        candidates
+            .into_iter()
+            .rev()
+            .filter(move |node| self.hits(*node, point))
+    }
+
+    fn hits(&self, node: RenderGraphNodeId, point: Point<f32, Px>) -> bool {
+        match self.get(node) {
+            RenderGraphCommand::Root => false,
+            RenderGraphCommand::DrawRect {
+                first_instance,
+                num_instances,
+                ..
+            } => {
+                let first = *first_instance as usize;
+                self.imm_rect_instances[first..first + *num_instances as usize]
+                    .iter()
+                    .any(|rect| rect_contains(rect, point))
+            }
+            RenderGraphCommand::DrawNineSlice {
+                first_index,
+                num_indices,
+                ..
+            } => {
+                let first = *first_index as usize;
+                self.imm_indices[first..first + *num_indices as usize]
+                    .chunks_exact(3)
+                    .any(|triangle| {
+                        let [a, b, c] = [0, 1, 2]
+                            .map(|i| self.imm_rect_vertices[triangle[i] as usize].position);
+                        triangle_contains([a, b, c], point)
+                    })
+            }
+            RenderGraphCommand::DrawDisplayList { index } => {
+                let (list, offset) = &self.display_lists[*index as usize];
+                list.data().graph.hit_test(point - *offset).next().is_some()
+            }
+        }
+    }
+}
+
+/// The same shape as the pixel shader draws, without antialiasing.
+fn rect_contains(rect: &RectInstance, point: Point<f32, Px>) -> bool {
+    let bounds = rect.bounds();
+    let center = bounds.center();
+    let x = point.x - center.x;
+    let y = point.y - center.y;
+
+    // Bottom-right, top-right, bottom-left, top-left.
+    let [r0, r1, r2, r3] = rect.outer_radii;
+    let (a, b) = if x > 0.0 { (r0, r1) } else { (r2, r3) };
+    let radius = if y > 0.0 { a } else { b };
+
+    let half_width = bounds.extent().width / 2.0;
+    let half_height = bounds.extent().height / 2.0;
+    let radius = radius.clamp(0.0, half_width.min(half_height));
+
+    let dx = x.abs() - half_width + radius;
+    let dy = y.abs() - half_height + radius;
+    let outside = dx.max(0.0).hypot(dy.max(0.0));
+    let inside = dx.max(dy).min(0.0);
+
+    inside + outside - radius <= 0.0
+}
+
+/// Works for triangles wound either way.
+fn triangle_contains([a, b, c]: [Point<f32, Px>; 3], p: Point<f32, Px>) -> bool {
+    let edge = |a: Point<f32, Px>, b: Point<f32, Px>| (b - a).cross(p - a);
+    let (ab, bc, ca) = (edge(a, b), edge(b, c), edge(c, a));
+
+    (ab >= 0.0 && bc >= 0.0 && ca >= 0.0) || (ab <= 0.0 && bc <= 0.0 && ca <= 0.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use geometry::{Extent, Offset, Rect};
+
+    use crate::graphics::{DisplayList, DrawRect};
+
+    use super::*;
+
+    fn rect(x: f32, y: f32, width: f32, height: f32) -> DrawRect {
+        DrawRect::new(Rect::new(Point::new(x, y), Extent::new(width, height)))
+    }
+
+    fn tags(graph: &RenderGraph, x: f32, y: f32) -> Vec<Option<u64>> {
+        graph
+            .hit_test(Point::new(x, y))
+            .map(|node| graph.tag(node))
+            .collect()
+    }
+
+    #[test]
+    fn front_to_back() {
+        let mut graph = RenderGraph::new();
+        let panel = graph.draw_rect(RenderGraphNodeId::root(), &rect(0.0, 0.0, 100.0, 100.0));
+        graph.set_tag(panel, 1);
+        let button = graph.draw_rect(panel, &rect(10.0, 10.0, 20.0, 20.0));
+        graph.set_tag(button, 2);
+        let overlay = graph.draw_rect(RenderGraphNodeId::root(), &rect(50.0, 50.0, 100.0, 100.0));
+        graph.set_tag(overlay, 3);
+
+        assert_eq!(tags(&graph, 15.0, 15.0), [Some(2), Some(1)]);
+        assert_eq!(tags(&graph, 75.0, 75.0), [Some(3), Some(1)]);
+        assert_eq!(tags(&graph, 125.0, 125.0), [Some(3)]);
+        assert!(tags(&graph, 200.0, 200.0).is_empty());
+    }
+
+    #[test]
+    fn edges() {
+        let mut graph = RenderGraph::new();
+        graph.draw_rect(RenderGraphNodeId::root(), &rect(10.0, 10.0, 20.0, 20.0));
+
+        assert_eq!(tags(&graph, 10.0, 10.0), [None]);
+        assert_eq!(tags(&graph, 10.0, 20.0), [None]);
+        assert_eq!(tags(&graph, 30.0, 30.0), [None]);
+        assert!(tags(&graph, 9.9, 20.0).is_empty());
+    }
+
+    #[test]
+    fn rounded_corners() {
+        let mut graph = RenderGraph::new();
+        graph.draw_rect(
+            RenderGraphNodeId::root(),
+            &rect(0.0, 0.0, 100.0, 100.0).with_radius(20.0),
+        );
+
+        assert!(tags(&graph, 2.0, 2.0).is_empty());
+        assert_eq!(tags(&graph, 10.0, 10.0), [None]);
+        assert_eq!(tags(&graph, 2.0, 50.0), [None]);
+    }
+
+    #[test]
+    fn display_lists() {
+        let mut contents = RenderGraph::new();
+        contents.draw_rect(RenderGraphNodeId::root(), &rect(0.0, 0.0, 10.0, 10.0));
+        contents.draw_rect(RenderGraphNodeId::root(), &rect(20.0, 0.0, 10.0, 10.0));
+        let list = DisplayList::new(contents);
+
+        let mut graph = RenderGraph::new();
+        let node =
+            graph.draw_display_list(RenderGraphNodeId::root(), &list, Offset::new(0.0, 50.0));
+        graph.set_tag(node, 7);
+
+        assert_eq!(tags(&graph, 25.0, 55.0), [Some(7)]);
+        // Inside the list's bounds, but between its rects.
+        assert!(tags(&graph, 15.0, 55.0).is_empty());
+    }
+}
@@ -0,0 +1,440 @@
+use std::{fmt, path::Path};
+
+use super::{AlphaMode, ColorSpace, PixelBuffer, PixelBufferRef, PixelFormat};
+
+pub(crate) const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+
+/// File formats that a pixel buffer can be encoded into.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ImageFormat {
+    Png,
+    /// The "Quite OK Image" format. Lossless, and much faster to encode than
+    /// PNG at the cost of larger files.
+    Qoi,
+    /// 32-bit Windows bitmap with an alpha channel. Bitmaps can't hold more
+    /// than 4 GiB of pixels, which is about a gigapixel.
+    Bmp,
+}
+
+impl ImageFormat {
+    /// Guesses the image format from a file extension (case-insensitive).
+    #[must_use]
+    pub fn from_extension(extension: &str) -> Option<Self> {
+        match extension.to_ascii_lowercase().as_str() {
+            "png" => Some(Self::Png),
+            "qoi" => Some(Self::Qoi),
+            "bmp" => Some(Self::Bmp),
+            _ => None,
+        }
+    }
+
+    /// Guesses the image format from the extension of a path.
+    #[must_use]
+    pub fn from_path(path: &Path) -> Option<Self> {
+        path.extension()
+            .and_then(|ext| ext.to_str())
+            .and_then(Self::from_extension)
+    }
+}
+
+/// The reasons an image file can fail to decode.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DecodeError {
+    /// The file is not in a format that can be decoded.
+    UnknownFormat,
+    /// The file uses a feature of its format that isn't supported.
+    Unsupported(String),
+    /// The file is corrupt or truncated.
+    Malformed(String),
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnknownFormat => write!(f, "unknown image format"),
+            Self::Unsupported(reason) => write!(f, "unsupported image: {reason}"),
+            Self::Malformed(reason) => write!(f, "malformed image: {reason}"),
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+impl From<png::DecodingError> for DecodeError {
+    fn from(error: png::DecodingError) -> Self {
+        match error {
+            png::DecodingError::IoError(_) | png::DecodingError::Format(_) => {
+                Self::Malformed(error.to_string())
+            }
+            png::DecodingError::Parameter(_) | png::DecodingError::LimitsExceeded => {
+                Self::Unsupported(error.to_string())
+            }
+        }
+    }
+}
+
+impl From<gif::DecodingError> for DecodeError {
+    fn from(error: gif::DecodingError) -> Self {
+        Self::Malformed(error.to_string())
+    }
+}
+
+/// Decodes the first (or only) frame of a PNG file.
+pub(crate) fn decode_png(bytes: &[u8]) -> Result<PixelBuffer, DecodeError> {
+    let mut decoder = png::Decoder::new(bytes);
+    decoder.set_transformations(png::Transformations::normalize_to_color8());
+    let mut reader = decoder.read_info()?;
+
+    let mut buffer = vec![0; reader.output_buffer_size()];
+    let output = reader.next_frame(&mut buffer)?;
+    let (color_type, _) = reader.output_color_type();
+
+    // Untagged images are assumed to be sRGB, which is what every viewer does
+    // in practice.
+    Ok(rgba8_from_png(
+        &buffer[..output.line_size * output.height as usize],
+        output.width,
+        color_type,
+    ))
+}
+
+/// Expands the 8-bit output of the PNG decoder to RGBA8.
+pub(crate) fn rgba8_from_png(bytes: &[u8], width: u32, color_type: png::ColorType) -> PixelBuffer {
+    let expanded: Vec<u8> = match color_type {
+        png::ColorType::Rgba => bytes.to_vec(),
+        png::ColorType::Rgb => bytes
+            .chunks_exact(3)
+            .flat_map(|px| [px[0], px[1], px[2], 255])
+            .collect(),
+        png::ColorType::GrayscaleAlpha => bytes
+            .chunks_exact(2)
+            .flat_map(|px| [px[0], px[0], px[0], px[1]])
+            .collect(),
+        png::ColorType::Grayscale => bytes.iter().flat_map(|&v| [v, v, v, 255]).collect(),
+        png::ColorType::Indexed => unreachable!("palettes are expanded by the decoder"),
+    };
+
+    PixelBuffer::from_bytes(&expanded, width, PixelFormat::Rgba8, ColorSpace::Srgb)
+}
+
+pub(crate) fn encode(pixels: PixelBufferRef, format: ImageFormat) -> Vec<u8> {
+    // All of the encoders below expect RGBA8 pixels with straight alpha.
+    if pixels.format() != PixelFormat::Rgba8 || pixels.alpha_mode() == AlphaMode::Premultiplied {
+        let mut converted = PixelBuffer::new(
+            pixels.width(),
+            pixels.height(),
+            PixelFormat::Rgba8,
+            pixels.color_space(),
+        );
+        converted.as_mut().copy_from(pixels);
+        return encode(converted.as_ref(), format);
+    }
+
+    match format {
+        ImageFormat::Png => encode_png(pixels),
+        ImageFormat::Qoi => encode_qoi(pixels),
+        ImageFormat::Bmp => encode_bmp(pixels),
+    }
+}
+
+fn encode_png(pixels: PixelBufferRef) -> Vec<u8> {
+    use std::io::Write;
+
+    let mut out = Vec::new();
+
+    {
+        let mut encoder = png::Encoder::new(&mut out, pixels.width(), pixels.height());
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+
+        match pixels.color_space() {
+            // The PNG spec recommends writing gAMA and cHRM alongside sRGB for
+            // the benefit of decoders that don't understand the sRGB chunk.
+            ColorSpace::Srgb => {
+                encoder.set_source_srgb(png::SrgbRenderingIntent::Perceptual);
+                encoder.set_source_gamma(png::ScaledFloat::from_scaled(45455));
+                encoder.set_source_chromaticities(png::SourceChromaticities::new(
+                    (0.3127, 0.3290),
+                    (0.6400, 0.3300),
+                    (0.3000, 0.6000),
+                    (0.1500, 0.0600),
+                ));
+            }
+        }
+
+        // Writing to a Vec can only fail if we've mis-described the image, so
+        // treat errors here as bugs.
+        let mut writer = encoder.write_header().expect("invalid PNG header");
+        let mut stream = writer.stream_writer().expect("invalid PNG stream");
+        for row in pixels.rows() {
+            stream.write_all(row).expect("PNG row write failed");
+        }
+        stream.finish().expect("PNG stream finalization failed");
+        writer.finish().expect("PNG finalization failed");
+    }
+
+    out
+}
+
+fn encode_qoi(pixels: PixelBufferRef) -> Vec<u8> {
+    const OP_INDEX: u8 = 0x00;
+    const OP_DIFF: u8 = 0x40;
+    const OP_LUMA: u8 = 0x80;
+    const OP_RUN: u8 = 0xc0;
+    const OP_RGB: u8 = 0xfe;
+    const OP_RGBA: u8 = 0xff;
+    const END_MARKER: [u8; 8] = [0, 0, 0, 0, 0, 0, 0, 1];
+
+    fn hash([r, g, b, a]: [u8; 4]) -> usize {
+        (usize::from(r) * 3 + usize::from(g) * 5 + usize::from(b) * 7 + usize::from(a) * 11) % 64
+    }
+
+    let num_pixels = pixels.width() as usize * pixels.height() as usize;
+
+    let mut out = Vec::with_capacity(14 + num_pixels * 5 + END_MARKER.len());
+    out.extend_from_slice(b"qoif");
+    out.extend_from_slice(&pixels.width().to_be_bytes());
+    out.extend_from_slice(&pixels.height().to_be_bytes());
+    out.push(4); // channels
+    out.push(match pixels.color_space() {
+        ColorSpace::Srgb => 0,
+    });
+
+    let mut index = [[0u8; 4]; 64];
+    let mut prev = [0, 0, 0, 255];
+    let mut run = 0u8;
+
+    let mut remaining = num_pixels;
+    for px in pixels.rows().flat_map(|row| row.chunks_exact(4)) {
+        let px = [px[0], px[1], px[2], px[3]];
+        remaining -= 1;
+
+        if px == prev {
+            run += 1;
+            if run == 62 || remaining == 0 {
+                out.push(OP_RUN | (run - 1));
+                run = 0;
+            }
+            continue;
+        }
+
+        if run > 0 {
+            out.push(OP_RUN | (run - 1));
+            run = 0;
+        }
+
+        let slot = hash(px);
+        if index[slot] == px {
+            #[allow(clippy::cast_possible_truncation)]
+            out.push(OP_INDEX | slot as u8);
+        } else {
+            index[slot] = px;
+
+            if px[3] == prev[3] {
+                #[allow(clippy::cast_possible_wrap)]
+                let [dr, dg, db] = [
+                    px[0].wrapping_sub(prev[0]) as i8,
+                    px[1].wrapping_sub(prev[1]) as i8,
+                    px[2].wrapping_sub(prev[2]) as i8,
+                ];
+                let dr_dg = dr.wrapping_sub(dg);
+                let db_dg = db.wrapping_sub(dg);
+
+                #[allow(clippy::cast_sign_loss)]
+                if (-2..=1).contains(&dr) && (-2..=1).contains(&dg) && (-2..=1).contains(&db) {
+                    out.push(
+                        OP_DIFF | ((dr + 2) as u8) << 4 | ((dg + 2) as u8) << 2 | (db + 2) as u8,
+                    );
+                } else if (-32..=31).contains(&dg)
+                    && (-8..=7).contains(&dr_dg)
+                    && (-8..=7).contains(&db_dg)
+                {
+                    out.push(OP_LUMA | (dg + 32) as u8);
+                    out.push(((dr_dg + 8) as u8) << 4 | (db_dg + 8) as u8);
+                } else {
+                    out.extend_from_slice(&[OP_RGB, px[0], px[1], px[2]]);
+                }
+            } else {
+                out.extend_from_slice(&[OP_RGBA, px[0], px[1], px[2], px[3]]);
+            }
+        }
+
+        prev = px;
+    }
+
+    out.extend_from_slice(&END_MARKER);
+    out
+}
+
+fn encode_bmp(pixels: PixelBufferRef) -> Vec<u8> {
+    const FILE_HEADER_SIZE: u32 = 14;
+    const INFO_HEADER_SIZE: u32 = 108; // BITMAPV4HEADER
+    const BI_BITFIELDS: u32 = 3;
+    const LCS_SRGB: u32 = 0x7352_4742;
+    // 72 DPI
+    const PIXELS_PER_METER: i32 = 2835;
+
+    let width = pixels.width();
+    let height = pixels.height();
+    let pixel_offset = FILE_HEADER_SIZE + INFO_HEADER_SIZE;
+    let image_size = width.checked_mul(height).and_then(|n| n.checked_mul(4));
+    let (image_size, file_size) = image_size
+        .and_then(|size| Some((size, size.checked_add(pixel_offset)?)))
+        .expect("image too large for a bitmap");
+
+    let mut out = Vec::with_capacity(file_size as usize);
+
+    // BITMAPFILEHEADER
+    out.extend_from_slice(b"BM");
+    out.extend_from_slice(&file_size.to_le_bytes());
+    out.extend_from_slice(&0u32.to_le_bytes());
+    out.extend_from_slice(&pixel_offset.to_le_bytes());
+
+    // BITMAPV4HEADER
+    out.extend_from_slice(&INFO_HEADER_SIZE.to_le_bytes());
+    out.extend_from_slice(&i32::try_from(width).expect("image too wide").to_le_bytes());
+    // A negative height indicates that rows are stored top-down.
+    out.extend_from_slice(&(-i32::try_from(height).expect("image too tall")).to_le_bytes());
+    out.extend_from_slice(&1u16.to_le_bytes()); // planes
+    out.extend_from_slice(&32u16.to_le_bytes()); // bits per pixel
+    out.extend_from_slice(&BI_BITFIELDS.to_le_bytes());
+    out.extend_from_slice(&image_size.to_le_bytes());
+    out.extend_from_slice(&PIXELS_PER_METER.to_le_bytes());
+    out.extend_from_slice(&PIXELS_PER_METER.to_le_bytes());
+    out.extend_from_slice(&0u32.to_le_bytes()); // colors used
+    out.extend_from_slice(&0u32.to_le_bytes()); // colors important
+
+    // Channel masks over each pixel read as a little-endian u32, matching the
+    // RGBA byte order of the pixel buffer.
+    out.extend_from_slice(&0x0000_00FFu32.to_le_bytes());
+    out.extend_from_slice(&0x0000_FF00u32.to_le_bytes());
+    out.extend_from_slice(&0x00FF_0000u32.to_le_bytes());
+    out.extend_from_slice(&0xFF00_0000u32.to_le_bytes());
+
+    out.extend_from_slice(
+        &match pixels.color_space() {
+            ColorSpace::Srgb => LCS_SRGB,
+        }
+        .to_le_bytes(),
+    );
+    // Endpoints and gamma are ignored for LCS_sRGB.
+    out.extend_from_slice(&[0; 36 + 12]);
+
+    for row in pixels.rows() {
+        out.extend_from_slice(row);
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::graphics::Color;
+
+    fn test_image() -> PixelBuffer {
+        let colors = [
+            Color::RED,
+            Color::GREEN,
+            Color::BLUE,
+            Color::WHITE,
+            Color::WHITE,
+            Color::WHITE,
+            Color::new(0.5, 0.5, 0.5, 0.5),
+            Color::BLACK,
+        ];
+        PixelBuffer::from_colors(&colors, 4, PixelFormat::Rgba8, ColorSpace::Srgb)
+    }
+
+    #[test]
+    fn png_round_trip() {
+        let image = test_image();
+        let encoded = image.encode(ImageFormat::Png);
+        let decoded = PixelBuffer::from_file(&encoded);
+
+        assert_eq!(decoded.width(), image.width());
+        assert_eq!(decoded.height(), image.height());
+        assert_eq!(decoded.format(), image.format());
+        assert_eq!(decoded.color_space(), image.color_space());
+        assert_eq!(decoded.bytes(), image.bytes());
+    }
+
+    #[test]
+    fn premultiplied_pixels_are_encoded_straight() {
+        let image = test_image();
+        let mut premultiplied = image.clone();
+        premultiplied.premultiply();
+
+        let decoded = PixelBuffer::from_file(&premultiplied.encode(ImageFormat::Png));
+        assert_eq!(decoded.bytes(), image.bytes());
+    }
+
+    #[test]
+    fn qoi_encoding() {
+        let encoded = test_image().encode(ImageFormat::Qoi);
+
+        assert_eq!(&encoded[0..4], b"qoif");
+        assert_eq!(&encoded[4..8], &4u32.to_be_bytes());
+        assert_eq!(&encoded[8..12], &2u32.to_be_bytes());
+        assert_eq!(&encoded[encoded.len() - 8..], &[0, 0, 0, 0, 0, 0, 0, 1]);
+
+        // Red is a small (wrapping) difference from the implicit opaque black
+        // start pixel, and the two whites after the first collapse into a run.
+        assert_eq!(encoded[14], 0x40 | 1 << 4 | 2 << 2 | 2);
+        assert!(encoded.contains(&(0xc0 | 1)));
+    }
+
+    #[test]
+    fn bmp_encoding() {
+        let image = test_image();
+        let encoded = image.encode(ImageFormat::Bmp);
+
+        assert_eq!(&encoded[0..2], b"BM");
+        assert_eq!(encoded.len(), 14 + 108 + image.bytes().len());
+        assert_eq!(&encoded[2..6], &(encoded.len() as u32).to_le_bytes());
+        assert_eq!(&encoded[14 + 108..], image.bytes());
+    }
+
+    #[test]
+    fn decode_errors() {
+        assert!(matches!(
+            PixelBuffer::decode(b"not an image"),
+            Err(DecodeError::UnknownFormat)
+        ));
+
+        let truncated = &test_image().encode(ImageFormat::Png)[..40];
+        assert!(matches!(
+            PixelBuffer::decode(truncated),
+            Err(DecodeError::Malformed(_))
+        ));
+    }
+
+    #[test]
+    fn decode_rgb_png() {
+        let mut out = Vec::new();
+        {
+            let mut encoder = png::Encoder::new(&mut out, 2, 1);
+            encoder.set_color(png::ColorType::Rgb);
+            encoder.set_depth(png::BitDepth::Eight);
+            let mut writer = encoder.write_header().unwrap();
+            writer.write_image_data(&[255, 0, 0, 0, 0, 255]).unwrap();
+        }
+
+        let decoded = PixelBuffer::decode(&out).unwrap();
+        assert_eq!(decoded.bytes(), [255, 0, 0, 255, 0, 0, 255, 255]);
+    }
+
+    #[test]
+    fn format_from_path() {
+        assert_eq!(
+            ImageFormat::from_path(Path::new("a/b.PNG")),
+            Some(ImageFormat::Png)
+        );
+        assert_eq!(
+            ImageFormat::from_path(Path::new("frame.qoi")),
+            Some(ImageFormat::Qoi)
+        );
+        assert_eq!(ImageFormat::from_path(Path::new("b.jpg")), None);
+        assert_eq!(ImageFormat::from_path(Path::new("b")), None);
+    }
+}
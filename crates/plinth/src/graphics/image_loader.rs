@@ -0,0 +1,467 @@
+use std::{
+    cell::{Cell, RefCell},
+    cmp::Ordering,
+    collections::{BinaryHeap, HashMap},
+    fmt, io,
+    path::{Path, PathBuf},
+    sync::{mpsc, Arc, Condvar, Mutex},
+    thread::JoinHandle,
+};
+
+use super::{DecodeError, GraphicsContext, Image, PixelBuffer};
+
+/// Queued images are loaded highest priority first, and in the order they
+/// were requested within a priority.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LoadPriority {
+    Low,
+    #[default]
+    Normal,
+    High,
+}
+
+#[derive(Debug, Clone)]
+pub enum LoadError {
+    Io(Arc<io::Error>),
+    Decode(DecodeError),
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(error) => write!(f, "failed to read image: {error}"),
+            Self::Decode(error) => write!(f, "failed to decode image: {error}"),
+        }
+    }
+}
+
+impl std::error::Error for LoadError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io(error) => Some(error.as_ref()),
+            Self::Decode(error) => Some(error),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum ImageStatus {
+    /// The image is waiting to be, or is being, read and decoded.
+    Pending,
+    Ready(Image),
+    Failed(LoadError),
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct ImageLoaderConfig {
+    /// The number of threads that read and decode images.
+    pub num_workers: usize,
+    /// Shown by [`ImageLoader::image`] while an image is loading.
+    pub placeholder: Option<Image>,
+    /// Shown by [`ImageLoader::image`] when an image failed to load.
+    pub error_image: Option<Image>,
+}
+
+impl Default for ImageLoaderConfig {
+    fn default() -> Self {
+        Self {
+            num_workers: std::thread::available_parallelism().map_or(1, |n| n.get().min(4)),
+            placeholder: None,
+            error_image: None,
+        }
+    }
+}
+
+/// Loads images from disk on a pool of worker threads.
+///
+/// Images are identified by their path, and each path is only loaded once no
+/// matter how many times it is requested. Decoded images are uploaded to the
+/// GPU by [`Self::poll`], which must be called from the thread that owns the
+/// graphics context.
+pub struct ImageLoader {
+    pool: WorkerPool,
+    entries: RefCell<HashMap<PathBuf, Entry>>,
+    /// Requests that have been sent to the worker pool and haven't come back.
+    pending: RefCell<HashMap<u64, PathBuf>>,
+    next_id: Cell<u64>,
+    placeholder: Option<Image>,
+    error_image: Option<Image>,
+}
+
+struct Entry {
+    id: u64,
+    priority: LoadPriority,
+    status: ImageStatus,
+}
+
+impl ImageLoader {
+    #[must_use]
+    pub fn new(config: &ImageLoaderConfig) -> Self {
+        Self {
+            pool: WorkerPool::new(config.num_workers),
+            entries: RefCell::default(),
+            pending: RefCell::default(),
+            next_id: Cell::new(0),
+            placeholder: config.placeholder,
+            error_image: config.error_image,
+        }
+    }
+
+    /// Requests that an image be loaded, and returns its current status.
+    ///
+    /// Requesting an image that is still queued with a higher priority moves
+    /// it up the queue.
+    pub fn load(&self, path: impl AsRef<Path>, priority: LoadPriority) -> ImageStatus {
+        let path = path.as_ref();
+        let mut entries = self.entries.borrow_mut();
+
+        if let Some(entry) = entries.get_mut(path) {
+            if matches!(entry.status, ImageStatus::Pending) && priority > entry.priority {
+                entry.priority = priority;
+                self.pool.reprioritize(entry.id, priority);
+            }
+
+            return entry.status.clone();
+        }
+
+        let id = self.next_id.get();
+        self.next_id.set(id + 1);
+
+        self.pool.submit(id, path.to_owned(), priority);
+        self.pending.borrow_mut().insert(id, path.to_owned());
+        entries.insert(
+            path.to_owned(),
+            Entry {
+                id,
+                priority,
+                status: ImageStatus::Pending,
+            },
+        );
+
+        ImageStatus::Pending
+    }
+
+    /// Gets the image to draw for a path: the image itself once it has loaded,
+    /// and the placeholder or error image otherwise. Requests the image with
+    /// normal priority if it hasn't been requested yet.
+    pub fn image(&self, path: impl AsRef<Path>) -> Option<Image> {
+        match self.load(path, LoadPriority::Normal) {
+            ImageStatus::Pending => self.placeholder,
+            ImageStatus::Ready(image) => Some(image),
+            ImageStatus::Failed(_) => self.error_image,
+        }
+    }
+
+    /// The status of an image, or `None` if it hasn't been requested.
+    #[must_use]
+    pub fn status(&self, path: impl AsRef<Path>) -> Option<ImageStatus> {
+        self.entries
+            .borrow()
+            .get(path.as_ref())
+            .map(|entry| entry.status.clone())
+    }
+
+    /// Cancels a pending load. Images that are already being decoded finish
+    /// decoding, but are then discarded. Returns `false` if the image wasn't
+    /// pending.
+    pub fn cancel(&self, path: impl AsRef<Path>) -> bool {
+        let mut entries = self.entries.borrow_mut();
+        let path = path.as_ref();
+
+        match entries.get(path) {
+            Some(Entry {
+                id,
+                status: ImageStatus::Pending,
+                ..
+            }) => {
+                self.pool.cancel(*id);
+                self.pending.borrow_mut().remove(id);
+                entries.remove(path);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Forgets about an image, destroying it if it has been loaded or
+    /// cancelling it if it is pending. The next request for the path will load
+    /// it again.
+    pub fn unload(&self, graphics: &GraphicsContext, path: impl AsRef<Path>) {
+        let path = path.as_ref();
+
+        if self.cancel(path) {
+            return;
+        }
+
+        if let Some(entry) = self.entries.borrow_mut().remove(path) {
+            if let ImageStatus::Ready(mut image) = entry.status {
+                graphics.destroy_image(&mut image);
+            }
+        }
+    }
+
+    /// Uploads images that have finished decoding. Returns `true` if any image
+    /// finished loading, whether or not it succeeded.
+    ///
+    /// Loaded images are reloadable, so they may be evicted when the graphics
+    /// context's image memory budget is exceeded. Evicted images are forgotten
+    /// here, and are loaded again the next time they are requested.
+    pub fn poll(&self, graphics: &GraphicsContext) -> bool {
+        let mut any_completed = false;
+
+        while let Some((id, result)) = self.pool.try_recv() {
+            // Cancelled requests are no longer pending.
+            let Some(path) = self.pending.borrow_mut().remove(&id) else {
+                continue;
+            };
+
+            let status = match result {
+                Ok(pixels) => {
+                    let image = graphics.upload_image(pixels.as_ref());
+                    graphics.set_image_reloadable(&image, true);
+                    ImageStatus::Ready(image)
+                }
+                Err(error) => ImageStatus::Failed(error),
+            };
+
+            if let Some(entry) = self.entries.borrow_mut().get_mut(&path) {
+                entry.status = status;
+            }
+
+            any_completed = true;
+        }
+
+        self.entries
+            .borrow_mut()
+            .retain(|_, entry| match entry.status {
+                ImageStatus::Ready(image) => graphics.is_image_valid(&image),
+                _ => true,
+            });
+
+        any_completed
+    }
+}
+
+type LoadResult = (u64, Result<PixelBuffer, LoadError>);
+
+struct WorkerPool {
+    shared: Arc<Shared>,
+    results: mpsc::Receiver<LoadResult>,
+    workers: Vec<JoinHandle<()>>,
+}
+
+struct Shared {
+    queue: Mutex<Queue>,
+    available: Condvar,
+}
+
+impl WorkerPool {
+    fn new(num_workers: usize) -> Self {
+        let shared = Arc::new(Shared {
+            queue: Mutex::default(),
+            available: Condvar::new(),
+        });
+
+        let (sender, results) = mpsc::channel();
+
+        let workers = (0..num_workers.max(1))
+            .map(|i| {
+                let shared = shared.clone();
+                let sender = sender.clone();
+                std::thread::Builder::new()
+                    .name(format!("Image Loader {i}"))
+                    .spawn(move || worker(&shared, &sender))
+                    .expect("failed to spawn image loader thread")
+            })
+            .collect();
+
+        Self {
+            shared,
+            results,
+            workers,
+        }
+    }
+
+    fn submit(&self, id: u64, path: PathBuf, priority: LoadPriority) {
+        self.shared.queue.lock().unwrap().push(id, path, priority);
+        self.shared.available.notify_one();
+    }
+
+    fn reprioritize(&self, id: u64, priority: LoadPriority) {
+        self.shared.queue.lock().unwrap().reprioritize(id, priority);
+    }
+
+    fn cancel(&self, id: u64) {
+        self.shared.queue.lock().unwrap().cancel(id);
+    }
+
+    fn try_recv(&self) -> Option<LoadResult> {
+        self.results.try_recv().ok()
+    }
+}
+
+impl Drop for WorkerPool {
+    fn drop(&mut self) {
+        self.shared.queue.lock().unwrap().shutdown = true;
+        self.shared.available.notify_all();
+
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+fn worker(shared: &Shared, results: &mpsc::Sender<LoadResult>) {
+    loop {
+        let (id, path) = {
+            let mut queue = shared.queue.lock().unwrap();
+            loop {
+                if queue.shutdown {
+                    return;
+                }
+
+                if let Some(job) = queue.pop() {
+                    break job;
+                }
+
+                queue = shared.available.wait(queue).unwrap();
+            }
+        };
+
+        let result = std::fs::read(path)
+            .map_err(|error| LoadError::Io(Arc::new(error)))
+            .and_then(|bytes| PixelBuffer::decode(&bytes).map_err(LoadError::Decode));
+
+        if results.send((id, result)).is_err() {
+            return;
+        }
+    }
+}
+
+#[derive(Default)]
+struct Queue {
+    jobs: BinaryHeap<Job>,
+    /// The sequence number of the live job for each queued request. Jobs that
+    /// don't match are stale (cancelled or reprioritized), and are skipped.
+    queued: HashMap<u64, (u64, PathBuf)>,
+    next_seq: u64,
+    shutdown: bool,
+}
+
+impl Queue {
+    fn push(&mut self, id: u64, path: PathBuf, priority: LoadPriority) {
+        let seq = self.next_seq;
+        self.next_seq += 1;
+
+        self.jobs.push(Job { priority, seq, id });
+        self.queued.insert(id, (seq, path));
+    }
+
+    /// Moves a request that is still queued to a new priority. Does nothing if
+    /// the request has already been taken by a worker.
+    fn reprioritize(&mut self, id: u64, priority: LoadPriority) {
+        if let Some((_, path)) = self.queued.remove(&id) {
+            self.push(id, path, priority);
+        }
+    }
+
+    fn cancel(&mut self, id: u64) {
+        self.queued.remove(&id);
+    }
+
+    fn pop(&mut self) -> Option<(u64, PathBuf)> {
+        while let Some(job) = self.jobs.pop() {
+            if matches!(self.queued.get(&job.id), Some((seq, _)) if *seq == job.seq) {
+                let (_, path) = self.queued.remove(&job.id).unwrap();
+                return Some((job.id, path));
+            }
+        }
+
+        None
+    }
+}
+
+#[derive(PartialEq, Eq)]
+struct Job {
+    priority: LoadPriority,
+    seq: u64,
+    id: u64,
+}
+
+impl Ord for Job {
+    fn cmp(&self, other: &Self) -> Ordering {
+        // BinaryHeap is a max-heap, so earlier requests must compare greater.
+        self.priority
+            .cmp(&other.priority)
+            .then_with(|| other.seq.cmp(&self.seq))
+    }
+}
+
+impl PartialOrd for Job {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::graphics::{Color, ColorSpace, PixelFormat};
+
+    #[test]
+    fn queue_order() {
+        let mut queue = Queue::default();
+        queue.push(0, "a".into(), LoadPriority::Low);
+        queue.push(1, "b".into(), LoadPriority::Normal);
+        queue.push(2, "c".into(), LoadPriority::High);
+        queue.push(3, "d".into(), LoadPriority::Normal);
+        queue.push(4, "e".into(), LoadPriority::Low);
+
+        queue.reprioritize(0, LoadPriority::High);
+        queue.cancel(3);
+
+        let order: Vec<_> = std::iter::from_fn(|| queue.pop())
+            .map(|(id, _)| id)
+            .collect();
+        assert_eq!(order, [2, 0, 1, 4]);
+    }
+
+    #[test]
+    fn worker_pool() {
+        let dir = std::env::temp_dir().join(format!("plinth-image-loader-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let valid = dir.join("valid.png");
+        let invalid = dir.join("invalid.png");
+        let missing = dir.join("missing.png");
+
+        PixelBuffer::from_colors(&[Color::RED], 1, PixelFormat::Rgba8, ColorSpace::Srgb)
+            .save(&valid)
+            .unwrap();
+        std::fs::write(&invalid, b"not an image").unwrap();
+
+        let pool = WorkerPool::new(2);
+        pool.submit(0, valid, LoadPriority::Normal);
+        pool.submit(1, invalid, LoadPriority::Normal);
+        pool.submit(2, missing, LoadPriority::Normal);
+
+        let mut results: Vec<_> = (0..3)
+            .map(|_| pool.results.recv_timeout(Duration::from_secs(10)).unwrap())
+            .collect();
+        results.sort_by_key(|(id, _)| *id);
+
+        assert_eq!(results[0].1.as_ref().unwrap().bytes(), [255, 0, 0, 255]);
+        assert!(matches!(
+            results[1].1,
+            Err(LoadError::Decode(DecodeError::UnknownFormat))
+        ));
+        assert!(
+            matches!(&results[2].1, Err(LoadError::Io(error)) if error.kind() == io::ErrorKind::NotFound)
+        );
+
+        drop(pool);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
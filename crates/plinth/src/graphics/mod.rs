@@ -1,22 +1,61 @@
+pub mod animated_image;
+pub mod atlas;
+pub mod capture;
 pub mod color;
+pub mod compile;
+pub mod debug;
+pub mod display_list;
+pub mod frame_stats;
+pub mod image_format;
+pub mod image_loader;
+pub mod nine_slice;
 pub mod pixel_buffer;
 pub mod render_graph;
-
-use std::cell::RefCell;
+pub mod resample;
+
+mod damage;
+mod hit_test;
+mod residency;
+mod software;
+mod spatial_index;
+
+use std::{
+    cell::{Cell, RefCell},
+    fmt,
+    time::Instant,
+};
 
 use raw_window_handle::HasRawWindowHandle;
 
-use geometry::{Extent, Point, Px, Rect};
+use geometry::{Extent, Offset, Point, Px, Rect};
 use structures::generational_pool::{GenerationalPool, Handle};
 
 pub use self::{
+    animated_image::{AnimatedImage, AnimationFrame, Repeat},
+    atlas::AtlasConfig,
+    capture::{CaptureError, CaptureStats, FrameCapture},
     color::Color,
-    pixel_buffer::{ColorSpace, PixelBuffer, PixelBufferRef, PixelFormat},
+    compile::BatchStats,
+    debug::DebugView,
+    display_list::DisplayList,
+    frame_stats::{FrameHistory, FrameStats},
+    image_format::{DecodeError, ImageFormat},
+    image_loader::{ImageLoader, ImageLoaderConfig, ImageStatus, LoadError, LoadPriority},
+    nine_slice::{DrawNineSlice, Insets, SliceMode},
+    pixel_buffer::{
+        AlphaMode, ColorSpace, PixelBuffer, PixelBufferMut, PixelBufferRef, PixelFormat, Rotation,
+    },
     render_graph::{RenderGraph, RenderGraphCommand, RenderGraphNodeId},
+    resample::{Filter, MipChain},
 };
 
 use crate::platform;
 
+use self::{
+    atlas::{Atlas, AtlasEntry},
+    residency::Residency,
+};
+
 #[repr(C)]
 #[derive(Clone, Copy)]
 pub struct RoundedRectVertex {
@@ -26,6 +65,89 @@ pub struct RoundedRectVertex {
     pub outer_radii: [f32; 4],
     pub inner_radii: [f32; 4],
     pub color: Color,
+    pub uv: Point<f32, Px>,
+}
+
+/// A rect drawn with instancing, which the vertex shader expands into a quad.
+/// It is much smaller than the four vertices and six indices that it replaces.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub(crate) struct RectInstance {
+    /// The left, top, right, and bottom edges.
+    pub rect: [f32; 4],
+    pub outer_radii: [f32; 4],
+    pub inner_radii: [f32; 4],
+    /// Half-precision colors for each corner, in the same order as `uvs`.
+    pub colors: [[u16; 4]; 4],
+    /// Normalized 16-bit texture coordinates for the top-left, top-right,
+    /// bottom-right, and bottom-left corners.
+    pub uvs: [[u16; 2]; 4],
+}
+
+impl RectInstance {
+    /// Builds a rect from the vertices of a quad, in the same order as the
+    /// corners of `uvs`.
+    pub fn from_vertices(vertices: &[RoundedRectVertex; 4]) -> Self {
+        let [top_left, _, bottom_right, _] = vertices;
+
+        Self {
+            rect: [
+                top_left.position.x,
+                top_left.position.y,
+                bottom_right.position.x,
+                bottom_right.position.y,
+            ],
+            outer_radii: top_left.outer_radii,
+            inner_radii: top_left.inner_radii,
+            colors: vertices.map(|vertex| vertex.color.to_f16()),
+            uvs: vertices.map(|vertex| [vertex.uv.x, vertex.uv.y].map(to_unorm16)),
+        }
+    }
+
+    /// Expands the rect into a quad, as the vertex shader does.
+    pub fn to_vertices(self) -> [RoundedRectVertex; 4] {
+        let [left, top, right, bottom] = self.rect;
+        let positions = [(left, top), (right, top), (right, bottom), (left, bottom)];
+
+        let rect_size = Extent::new(right - left, bottom - top);
+        let rect_center = Point::new((left + right) / 2.0, (top + bottom) / 2.0);
+
+        std::array::from_fn(|corner| {
+            let (x, y) = positions[corner];
+            let [u, v] = self.uvs[corner].map(|uv| f32::from(uv) / 65535.0);
+
+            RoundedRectVertex {
+                position: Point::new(x, y),
+                rect_size,
+                rect_center,
+                outer_radii: self.outer_radii,
+                inner_radii: self.inner_radii,
+                color: Color::from_f16(self.colors[corner]),
+                uv: Point::new(u, v),
+            }
+        })
+    }
+
+    pub fn bounds(&self) -> Rect<f32, Px> {
+        let [left, top, right, bottom] = self.rect;
+        Rect::from_points(Point::new(left, top), Point::new(right, bottom))
+    }
+
+    pub fn translate(&mut self, offset: Offset<f32, Px>) {
+        let [left, top, right, bottom] = self.rect;
+        self.rect = [
+            left + offset.x,
+            top + offset.y,
+            right + offset.x,
+            bottom + offset.y,
+        ];
+    }
+}
+
+/// Texture coordinates outside of 0-1 are clamped, as the sampler would do.
+#[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+fn to_unorm16(value: f32) -> u16 {
+    (value.clamp(0.0, 1.0) * 65535.0).round() as u16
 }
 
 pub enum RectPart<T> {
@@ -48,6 +170,7 @@ pub struct DrawRect {
     colors: [Color; 4],
     outer_radii: [f32; 4],
     inner_radii: [f32; 4],
+    // Texture coordinates in the same order as colors.
     image: Option<(Image, [Point<f32, Px>; 4])>,
 }
 
@@ -96,6 +219,32 @@ impl DrawRect {
         self
     }
 
+    /// Fills the rect with an image, stretched to fit. The image is tinted by
+    /// the rect's colors, so you probably want to set them to white.
+    pub fn with_image(self, image: Image) -> Self {
+        self.with_image_uvs(
+            image,
+            [
+                Point::new(0.0, 0.0),
+                Point::new(1.0, 0.0),
+                Point::new(1.0, 1.0),
+                Point::new(0.0, 1.0),
+            ],
+        )
+    }
+
+    /// Fills the rect with a region of an image, given by normalized texture
+    /// coordinates for the top-left, top-right, bottom-right, and bottom-left
+    /// corners of the rect. Coordinates are clamped to the range 0-1.
+    pub fn with_image_uvs(mut self, image: Image, uvs: [Point<f32, Px>; 4]) -> Self {
+        self.image = Some((image, uvs));
+        self
+    }
+
+    pub(crate) fn image(&self) -> Option<Image> {
+        self.image.map(|(image, _)| image)
+    }
+
     pub fn with_radius(mut self, radius: f32) -> Self {
         self.outer_radii = [radius; 4];
         self
@@ -130,55 +279,24 @@ impl DrawRect {
         self
     }
 
-    pub(crate) fn to_vertices(&self) -> ([RoundedRectVertex; 4], [u16; 6]) {
+    pub(crate) fn to_instance(&self) -> RectInstance {
         let Self {
             rect,
             colors,
             outer_radii,
             inner_radii,
-            image: _,
+            image,
         } = self;
 
-        let rect_center = rect.center();
-
-        let vertices = [
-            RoundedRectVertex {
-                position: rect.top_left(),
-                rect_size: rect.extent(),
-                rect_center,
-                outer_radii: *outer_radii,
-                inner_radii: *inner_radii,
-                color: colors[0],
-            },
-            RoundedRectVertex {
-                position: rect.top_right(),
-                rect_size: rect.extent(),
-                rect_center,
-                outer_radii: *outer_radii,
-                inner_radii: *inner_radii,
-                color: colors[1],
-            },
-            RoundedRectVertex {
-                position: rect.bottom_right(),
-                rect_size: rect.extent(),
-                rect_center,
-                outer_radii: *outer_radii,
-                inner_radii: *inner_radii,
-                color: colors[2],
-            },
-            RoundedRectVertex {
-                position: rect.bottom_left(),
-                rect_size: rect.extent(),
-                rect_center,
-                outer_radii: *outer_radii,
-                inner_radii: *inner_radii,
-                color: colors[3],
-            },
-        ];
-
-        let indices = [0, 1, 2, 0, 2, 3];
+        let uvs = image.map_or([Point::zero(); 4], |(_, uvs)| uvs);
 
-        (vertices, indices)
+        RectInstance {
+            rect: [rect.left(), rect.top(), rect.right(), rect.bottom()],
+            outer_radii: *outer_radii,
+            inner_radii: *inner_radii,
+            colors: colors.map(Color::to_f16),
+            uvs: uvs.map(|uv| [uv.x, uv.y].map(to_unorm16)),
+        }
     }
 }
 
@@ -194,24 +312,65 @@ pub enum PowerPreference {
 /// these options cannot be changed without recreating the graphics context.
 #[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
 pub struct GraphicsConfig {
+    /// Enables the graphics API's validation layer.
     pub debug_mode: bool,
+    /// The debug view to draw with until it is changed with
+    /// [`GraphicsContext::set_debug_view`].
+    pub debug_view: DebugView,
     pub power_preference: PowerPreference,
+    pub atlas: AtlasConfig,
+    /// The maximum number of bytes of image memory to keep before evicting
+    /// reloadable images (see [`GraphicsContext::set_image_reloadable`]), or
+    /// `None` for no limit.
+    pub image_memory_budget: Option<u64>,
+    /// Keeps the contents of surface images between frames, so that
+    /// [`GraphicsContext::draw_damaged`] only has to redraw what changed.
+    /// This makes presenting a little slower, so leave it off if every frame
+    /// is drawn in full.
+    pub partial_redraws: bool,
 }
 
 pub struct GraphicsContext {
-    image_handles: RefCell<GenerationalPool<platform::Image>>,
+    images: RefCell<Images>,
     inner: RefCell<platform::Platform>,
+    debug_view: Cell<DebugView>,
+    history: RefCell<FrameHistory>,
+    /// Images whose contents have been updated, with the number of updates
+    /// made to any image by the time of each one's latest update. Surfaces
+    /// compare these with the number they have drawn.
+    updated_images: RefCell<Vec<(Image, u64)>>,
+    num_updates: Cell<u64>,
 }
 
 impl GraphicsContext {
     #[must_use]
     pub fn new(config: &GraphicsConfig) -> Self {
         Self {
-            image_handles: GenerationalPool::new().into(),
+            images: RefCell::new(Images {
+                textures: GenerationalPool::new(),
+                atlas: Atlas::new(config.atlas),
+                residency: Residency::new(config.image_memory_budget),
+                evicted: Vec::new(),
+            }),
             inner: RefCell::new(platform::Platform::new(config)),
+            debug_view: Cell::new(config.debug_view),
+            history: RefCell::new(FrameHistory::default()),
+            updated_images: RefCell::new(Vec::new()),
+            num_updates: Cell::new(0),
         }
     }
 
+    #[must_use]
+    pub fn debug_view(&self) -> DebugView {
+        self.debug_view.get()
+    }
+
+    /// Changes how the following frames are drawn, for debugging. Damage only
+    /// tracks changes to render graphs, so draw the next frame in full.
+    pub fn set_debug_view(&self, view: DebugView) {
+        self.debug_view.set(view);
+    }
+
     #[must_use]
     pub fn create_surface(&self, window: impl HasRawWindowHandle) -> Surface {
         Surface {
@@ -219,6 +378,7 @@ impl GraphicsContext {
                 .inner
                 .borrow()
                 .create_surface(window.raw_window_handle()),
+            updates_drawn: Cell::new(0),
         }
     }
 
@@ -228,7 +388,10 @@ impl GraphicsContext {
 
     pub fn get_next_image<'a>(&self, surface: &'a mut Surface) -> RenderTarget<'a> {
         let inner = self.inner.borrow().get_next_image(&mut surface.inner);
-        RenderTarget { inner }
+        RenderTarget {
+            inner,
+            updates_drawn: &surface.updates_drawn,
+        }
     }
 
     pub fn present(&self, surface: &mut Surface) {
@@ -239,32 +402,588 @@ impl GraphicsContext {
         self.inner.borrow().resize(&mut surface.inner);
     }
 
-    pub fn draw(&self, target: &RenderTarget, content: &RenderGraph) {
-        self.inner.borrow_mut().draw(&target.inner, content);
+    /// A copy of the stats of the most recently drawn frames, for performance
+    /// overlays.
+    #[must_use]
+    pub fn frame_history(&self) -> FrameHistory {
+        self.history.borrow().clone()
+    }
+
+    /// Draws the render graph to the target, batching together draws that
+    /// share an image where painting order allows it.
+    ///
+    /// Fails without drawing anything if the graph references an image that
+    /// has been destroyed or evicted from the atlas.
+    pub fn draw(
+        &self,
+        target: &RenderTarget,
+        content: &RenderGraph,
+    ) -> Result<FrameStats, DrawError> {
+        self.draw_inner(target, content, None)
+    }
+
+    /// Finds the region of the target that changed since `previous` was drawn
+    /// to its surface, like [`RenderGraph::damage`], but also including draws
+    /// of images that were updated since then.
+    #[must_use]
+    pub fn damage(
+        &self,
+        target: &RenderTarget,
+        content: &RenderGraph,
+        previous: &RenderGraph,
+    ) -> Option<Rect<f32, Px>> {
+        let drawn = target.updates_drawn.get();
+        let updated = self
+            .updated_images
+            .borrow()
+            .iter()
+            .filter(|(_, update)| *update > drawn)
+            .map(|(image, _)| *image)
+            .collect::<Vec<_>>();
+
+        content.damage_with_updated(previous, &updated)
+    }
+
+    /// Draws the render graph to the target, redrawing only the `damage`
+    /// region and leaving the rest as it was in the last frame. Use
+    /// [`Self::damage`] to find what changed since the last frame; if nothing
+    /// did, skip drawing and presenting altogether.
+    ///
+    /// Surfaces only keep their contents between frames if
+    /// [`GraphicsConfig::partial_redraws`] is set. Otherwise, this draws the
+    /// whole target.
+    ///
+    /// Fails without drawing anything if the graph references an image that
+    /// has been destroyed or evicted from the atlas.
+    pub fn draw_damaged(
+        &self,
+        target: &RenderTarget,
+        content: &RenderGraph,
+        damage: Rect<f32, Px>,
+    ) -> Result<FrameStats, DrawError> {
+        self.draw_inner(target, content, Some(damage))
+    }
+
+    fn draw_inner(
+        &self,
+        target: &RenderTarget,
+        content: &RenderGraph,
+        damage: Option<Rect<f32, Px>>,
+    ) -> Result<FrameStats, DrawError> {
+        let mut images = self.images.borrow_mut();
+
+        if let Some(image) = content.images().find(|image| !images.contains(*image)) {
+            return Err(DrawError::InvalidImage(image));
+        }
+
+        for image in content.images() {
+            if let Some(handle) = images.texture_handle(image) {
+                images.residency.mark_drawn(handle);
+            }
+        }
+
+        let mut inner = self.inner.borrow_mut();
+        let start = Instant::now();
+
+        // Debug views visualize what is inside display lists too.
+        let view = self.debug_view.get();
+        let inlined;
+        let content = if view == DebugView::Off {
+            content
+        } else {
+            inlined = content.inline_display_lists();
+            &inlined
+        };
+
+        // Anything outside the region won't be drawn, so it can be culled.
+        let region = inner.redraw_region(&target.inner, damage);
+        let compiled = content.compile_debug(&region, view);
+        let compile_time = start.elapsed();
+
+        let recorded = inner.draw(&target.inner, &compiled, &images, region);
+        let stats = FrameStats {
+            batches: compiled.stats,
+            record_time: compile_time + recorded.record_time,
+            ..recorded
+        };
+
+        images.atlas.end_frame();
+        images.residency.end_frame();
+
+        target.updates_drawn.set(self.num_updates.get());
+        self.history.borrow_mut().push(stats);
+        Ok(stats)
     }
 
+    /// Captures a frame for saving and replaying later, reading back the
+    /// contents of every image that it draws. This waits for the GPU, so it is
+    /// slow, and intended for debugging.
+    ///
+    /// Fails if the graph references an image that has been destroyed or
+    /// evicted from the atlas.
+    pub fn capture_frame(&self, content: &RenderGraph) -> Result<FrameCapture, DrawError> {
+        let content = &content.inline_display_lists();
+
+        let images = self.images.borrow();
+        let mut platform = self.inner.borrow_mut();
+
+        let mut captured: Vec<Image> = Vec::new();
+        for image in content.images() {
+            if !images.contains(image) {
+                return Err(DrawError::InvalidImage(image));
+            }
+
+            if !captured.contains(&image) {
+                captured.push(image);
+            }
+        }
+
+        // Atlas pages are read back once, however many images they hold.
+        let mut pages: Vec<(Handle<platform::Image>, PixelBuffer)> = Vec::new();
+
+        let pixels = captured
+            .iter()
+            .map(|image| match image.handle {
+                ImageHandle::Texture(handle) => {
+                    let texture = images.textures.get(handle).expect("image validated");
+                    platform.read_image(texture)
+                }
+                ImageHandle::Atlas(handle) => {
+                    let (page, rect) = images.atlas.allocation(handle).expect("image validated");
+                    let page_pixels = if let Some((_, pixels)) = pages.iter().find(|p| p.0 == *page)
+                    {
+                        pixels
+                    } else {
+                        let texture = images
+                            .textures
+                            .get(*page)
+                            .expect("atlas page has been destroyed");
+                        pages.push((*page, platform.read_image(texture)));
+                        &pages.last().unwrap().1
+                    };
+
+                    let padding = images.atlas.config().padding;
+                    page_pixels.crop(Rect::new(
+                        Point::new(rect.left() + padding, rect.top() + padding),
+                        Extent::new(
+                            rect.extent().width - padding * 2,
+                            rect.extent().height - padding * 2,
+                        ),
+                    ))
+                }
+            })
+            .collect();
+
+        let graph = content.map_images(|image| {
+            captured
+                .iter()
+                .position(|captured| captured == image)
+                .expect("every image was captured") as u32
+        });
+
+        Ok(FrameCapture::new(graph, pixels))
+    }
+
+    /// Uploads pixels to the GPU for drawing. Pixels with straight alpha are
+    /// premultiplied on the way, so the resulting image is always either
+    /// premultiplied or opaque.
     pub fn upload_image(&self, pixels: PixelBufferRef) -> Image {
-        let image = self.inner.borrow_mut().upload_image(pixels);
-        let handle = self.image_handles.borrow_mut().insert(image);
-        Image { handle }
+        let mut images = self.images.borrow_mut();
+        let mut platform = self.inner.borrow_mut();
+
+        let size = image_size(pixels);
+        images.make_room(&mut platform, size);
+
+        let handle = images.textures.insert(platform.upload_image(&[pixels]));
+        images.residency.insert(handle, size);
+        Image {
+            handle: ImageHandle::Texture(handle),
+        }
+    }
+
+    /// Uploads a small image into a shared atlas texture. Atlas images draw
+    /// exactly like standalone images, but many of them can share a single
+    /// texture.
+    ///
+    /// When the atlas is full, the least recently drawn atlas page is evicted,
+    /// invalidating every image in it (see [`Self::is_image_valid`]). Images
+    /// that don't fit in an atlas page, or that arrive when every page has
+    /// been drawn this frame, are uploaded as standalone images instead.
+    pub fn upload_atlas_image(&self, pixels: PixelBufferRef) -> Image {
+        let mut images = self.images.borrow_mut();
+        let mut platform = self.inner.borrow_mut();
+
+        let page_size = images.atlas.config().page_size;
+        let page_bytes = u64::from(page_size).pow(2) * PixelFormat::Rgba8.bytes_per_pixel() as u64;
+        let padding = images.atlas.config().padding;
+
+        let Images {
+            textures,
+            atlas,
+            residency,
+            ..
+        } = &mut *images;
+
+        // Stored the same way as standalone images, which are premultiplied
+        // when uploaded unless they are opaque.
+        let alpha_mode = match pixels.alpha_mode() {
+            AlphaMode::Opaque => AlphaMode::Opaque,
+            AlphaMode::Straight | AlphaMode::Premultiplied => AlphaMode::Premultiplied,
+        };
+
+        let allocation = atlas
+            .allocate(
+                Extent::new(pixels.width(), pixels.height()),
+                alpha_mode,
+                |size| {
+                    let page =
+                        textures.insert(platform.create_image(size, size, PixelFormat::Rgba8));
+                    residency.insert(page, page_bytes);
+                    page
+                },
+            )
+            .map(|allocation| (allocation.handle, *allocation.texture, allocation.rect));
+
+        if let Some((handle, page, rect)) = allocation {
+            // Atlas pages aren't reloadable, but adding one may push other
+            // images over the budget.
+            images.make_room(&mut platform, 0);
+
+            let page = images
+                .textures
+                .get(page)
+                .expect("atlas page has been destroyed");
+
+            platform.copy_to_image(page, rect.top_left(), atlas::pad(pixels, padding).as_ref());
+
+            Image {
+                handle: ImageHandle::Atlas(handle),
+            }
+        } else {
+            let size = image_size(pixels);
+            images.make_room(&mut platform, size);
+
+            let handle = images.textures.insert(platform.upload_image(&[pixels]));
+            images.residency.insert(handle, size);
+            Image {
+                handle: ImageHandle::Texture(handle),
+            }
+        }
+    }
+
+    /// Uploads an image along with its mip levels, for images that will be
+    /// drawn at less than their native size.
+    pub fn upload_mip_chain(&self, mips: &MipChain) -> Image {
+        let levels = mips
+            .levels()
+            .iter()
+            .map(PixelBuffer::as_ref)
+            .collect::<Vec<_>>();
+
+        let mut images = self.images.borrow_mut();
+        let mut platform = self.inner.borrow_mut();
+
+        let size = levels.iter().copied().map(image_size).sum();
+        images.make_room(&mut platform, size);
+
+        let handle = images.textures.insert(platform.upload_image(&levels));
+        images.residency.insert(handle, size);
+        Image {
+            handle: ImageHandle::Texture(handle),
+        }
+    }
+
+    /// Allows an image to be evicted when the image memory budget is exceeded
+    /// (see [`GraphicsConfig::image_memory_budget`]). Reloadable images are
+    /// evicted least recently drawn first, and are reported by
+    /// [`Self::take_evicted_images`] so that they can be loaded again if they
+    /// are needed.
+    ///
+    /// Atlas images are managed by the atlas, and are not affected.
+    pub fn set_image_reloadable(&self, image: &Image, reloadable: bool) {
+        let mut images = self.images.borrow_mut();
+        if let ImageHandle::Texture(handle) = image.handle {
+            images.residency.set_reloadable(handle, reloadable);
+        }
+    }
+
+    /// Returns the images that have been evicted to stay within the image
+    /// memory budget since the last call. Images destroyed since they were
+    /// evicted are left out, and only the most recent
+    /// [`MAX_EVICTED_REPORTS`] evictions are kept between calls.
+    #[must_use]
+    pub fn take_evicted_images(&self) -> Vec<Image> {
+        std::mem::take(&mut self.images.borrow_mut().evicted)
     }
 
+    /// The number of bytes of image memory in use, including atlas pages.
+    #[must_use]
+    pub fn image_memory_usage(&self) -> u64 {
+        self.images.borrow().residency.used()
+    }
+
+    /// Checks that an image has neither been destroyed nor evicted from the
+    /// atlas.
+    #[must_use]
+    pub fn is_image_valid(&self, image: &Image) -> bool {
+        self.images.borrow().contains(*image)
+    }
+
+    /// The size of an image in pixels.
+    ///
+    /// Panics if the image has been destroyed or evicted.
+    #[must_use]
+    pub fn image_extent(&self, image: &Image) -> Extent<u32, Px> {
+        let images = self.images.borrow();
+        match image.handle {
+            ImageHandle::Texture(handle) => images
+                .textures
+                .get(handle)
+                .expect("image has been destroyed")
+                .extent(),
+            ImageHandle::Atlas(handle) => {
+                let (_, rect) = images
+                    .atlas
+                    .allocation(handle)
+                    .expect("image has been destroyed or evicted");
+                let padding = images.atlas.config().padding * 2;
+                Extent::new(
+                    rect.extent().width - padding,
+                    rect.extent().height - padding,
+                )
+            }
+        }
+    }
+
+    /// How the alpha channel of an uploaded image is interpreted.
+    ///
+    /// Panics if the image has been destroyed or evicted from the atlas.
+    #[must_use]
+    pub fn image_alpha_mode(&self, image: &Image) -> AlphaMode {
+        let images = self.images.borrow();
+        match image.handle {
+            ImageHandle::Texture(handle) => images
+                .textures
+                .get(handle)
+                .expect("image has been destroyed")
+                .alpha_mode(),
+            // Atlas pages hold images with different alpha modes.
+            ImageHandle::Atlas(handle) => images
+                .atlas
+                .alpha_mode(handle)
+                .expect("image has been destroyed or evicted"),
+        }
+    }
+
+    /// Replaces the contents of `region` of an image with `pixels`, uploading
+    /// only that region. The pixels are converted to the image's format if
+    /// necessary. Only the image's most detailed mip level is updated.
+    ///
+    /// Panics if the image has been destroyed or evicted from the atlas, if
+    /// `pixels` is not the same size as `region`, or if `region` extends past
+    /// the edges of the image.
+    pub fn update_image(&self, image: &Image, region: Rect<u32, Px>, pixels: PixelBufferRef) {
+        assert_eq!(
+            (pixels.width(), pixels.height()),
+            (region.extent().width, region.extent().height),
+            "pixels must be the same size as the region being updated"
+        );
+
+        let images = self.images.borrow();
+        let mut platform = self.inner.borrow_mut();
+
+        match image.handle {
+            ImageHandle::Texture(handle) => {
+                let texture = images
+                    .textures
+                    .get(handle)
+                    .expect("image has been destroyed");
+
+                let extent = texture.extent();
+                assert!(
+                    region.right() <= extent.width && region.bottom() <= extent.height,
+                    "region extends past the edges of the image"
+                );
+
+                platform.copy_to_image(texture, region.top_left(), pixels);
+            }
+            ImageHandle::Atlas(handle) => {
+                let (page, rect) = images
+                    .atlas
+                    .allocation(handle)
+                    .expect("image has been destroyed or evicted");
+
+                let padding = images.atlas.config().padding;
+                let extent = Extent::new(
+                    rect.extent().width - padding * 2,
+                    rect.extent().height - padding * 2,
+                );
+                assert!(
+                    region.right() <= extent.width && region.bottom() <= extent.height,
+                    "region extends past the edges of the image"
+                );
+
+                let page = images
+                    .textures
+                    .get(*page)
+                    .expect("atlas page has been destroyed");
+
+                let (padded, at) = atlas::pad_region(pixels, region, extent, padding);
+                platform.copy_to_image(
+                    page,
+                    Point::new(rect.left() + at.x, rect.top() + at.y),
+                    padded.as_ref(),
+                );
+            }
+        }
+
+        let update = self.num_updates.get() + 1;
+        self.num_updates.set(update);
+
+        let mut updated = self.updated_images.borrow_mut();
+        if let Some(entry) = updated.iter_mut().find(|(i, _)| i == image) {
+            entry.1 = update;
+        } else {
+            updated.push((*image, update));
+        }
+    }
+
+    /// Destroys an image. The image's memory is released once the GPU has
+    /// finished with every frame that uses it, so it is safe to call this
+    /// while frames that draw the image are still in flight.
     pub fn destroy_image(&self, image: &mut Image) {
-        let image = self.image_handles.borrow_mut().remove(image.handle);
-        std::mem::drop(image);
+        self.updated_images
+            .borrow_mut()
+            .retain(|(updated, _)| updated != &*image);
+
+        let mut images = self.images.borrow_mut();
+        match image.handle {
+            ImageHandle::Texture(handle) => {
+                if let Some(image) = images.textures.remove(handle) {
+                    images.residency.remove(handle);
+                    self.inner.borrow_mut().destroy_image(image);
+                } else {
+                    // No need to report an eviction for an image that is gone.
+                    images.evicted.retain(|evicted| evicted != &*image);
+                }
+            }
+            ImageHandle::Atlas(handle) => {
+                images.atlas.deallocate(handle);
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DrawError {
+    /// The render graph references an image that has been destroyed or
+    /// evicted from the atlas.
+    InvalidImage(Image),
+}
+
+impl fmt::Display for DrawError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidImage(image) => {
+                write!(f, "attempted to draw destroyed or evicted image {image:?}")
+            }
+        }
     }
 }
 
+impl std::error::Error for DrawError {}
+
 pub struct Surface {
     inner: platform::Surface,
+    /// The number of image updates made by the time of the last frame drawn
+    /// to the surface.
+    updates_drawn: Cell<u64>,
 }
 
 pub struct RenderTarget<'a> {
     inner: platform::RenderTarget<'a>,
+    updates_drawn: &'a Cell<u64>,
 }
 
-#[repr(transparent)]
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub struct Image {
-    handle: Handle<platform::Image>,
+    handle: ImageHandle,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum ImageHandle {
+    Texture(Handle<platform::Image>),
+    Atlas(Handle<AtlasEntry>),
+}
+
+/// How many evictions are kept for [`GraphicsContext::take_evicted_images`],
+/// in case the application never takes them.
+pub const MAX_EVICTED_REPORTS: usize = 1024;
+
+/// Every image owned by a graphics context, both standalone textures and
+/// images packed into atlas pages.
+pub(crate) struct Images {
+    textures: GenerationalPool<platform::Image>,
+    atlas: Atlas<Handle<platform::Image>>,
+    residency: Residency<Handle<platform::Image>>,
+    /// Images evicted to stay within the memory budget, waiting to be reported
+    /// to the application.
+    evicted: Vec<Image>,
+}
+
+impl Images {
+    /// The texture that holds an image, whether or not it is an atlas image.
+    fn texture_handle(&self, image: Image) -> Option<Handle<platform::Image>> {
+        match image.handle {
+            ImageHandle::Texture(handle) => Some(handle),
+            ImageHandle::Atlas(handle) => self.atlas.allocation(handle).map(|(page, _)| *page),
+        }
+    }
+
+    /// Evicts reloadable images until `bytes` more bytes fit within the image
+    /// memory budget, or until nothing else can be evicted.
+    fn make_room(&mut self, platform: &mut platform::Platform, bytes: u64) {
+        for handle in self.residency.evict(bytes) {
+            if let Some(texture) = self.textures.remove(handle) {
+                platform.destroy_image(texture);
+                if self.evicted.len() == MAX_EVICTED_REPORTS {
+                    self.evicted.remove(0);
+                }
+
+                self.evicted.push(Image {
+                    handle: ImageHandle::Texture(handle),
+                });
+            }
+        }
+    }
+
+    pub(crate) fn contains(&self, image: Image) -> bool {
+        match image.handle {
+            ImageHandle::Texture(handle) => self.textures.get(handle).is_some(),
+            ImageHandle::Atlas(handle) => self.atlas.contains(handle),
+        }
+    }
+
+    /// Finds the texture that holds an image, along with the normalized
+    /// texture coordinates of the image within it.
+    pub(crate) fn get(&self, image: Image) -> Option<(&platform::Image, Rect<f32, Px>)> {
+        match image.handle {
+            ImageHandle::Texture(handle) => Some((
+                self.textures.get(handle)?,
+                Rect::new(Point::zero(), Extent::new(1.0, 1.0)),
+            )),
+            ImageHandle::Atlas(handle) => {
+                let (page, uv_rect) = self.atlas.get(handle)?;
+                Some((self.textures.get(*page)?, uv_rect))
+            }
+        }
+    }
+}
+
+/// The number of bytes of GPU memory used by an image with the same size and
+/// format as `pixels`.
+fn image_size(pixels: PixelBufferRef) -> u64 {
+    u64::from(pixels.width())
+        * u64::from(pixels.height())
+        * pixels.format().bytes_per_pixel() as u64
 }
@@ -0,0 +1,323 @@
+use geometry::{Extent, Point, Px, Rect};
+
+use super::{Color, Image, RoundedRectVertex};
+
+/// Distances from the edges of an image, in pixels, that divide it into a 3x3
+/// grid of corners, edges, and a center.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Insets {
+    pub left: f32,
+    pub top: f32,
+    pub right: f32,
+    pub bottom: f32,
+}
+
+impl Insets {
+    #[must_use]
+    pub fn new(left: f32, top: f32, right: f32, bottom: f32) -> Self {
+        Self {
+            left,
+            top,
+            right,
+            bottom,
+        }
+    }
+
+    #[must_use]
+    pub fn uniform(inset: f32) -> Self {
+        Self::new(inset, inset, inset, inset)
+    }
+}
+
+/// The most times a slice is repeated along one axis before falling back to
+/// stretching it.
+const MAX_TILES: u32 = 256;
+
+/// How the edges and center of a nine-slice image fill the space between the
+/// corners.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SliceMode {
+    /// Scale the slice to fill the space.
+    #[default]
+    Stretch,
+    /// Repeat the slice at its native size, cutting off the last repetition
+    /// at the far edge. Slices that would repeat too many times are stretched
+    /// instead.
+    Tile,
+}
+
+/// Draws an image into a rect, keeping its corners at their native size while
+/// the edges and center grow to fill the rest (a nine-patch).
+///
+/// If the rect is too small to fit the corners, they are scaled down to fit.
+#[derive(Clone)]
+pub struct DrawNineSlice {
+    image: Image,
+    image_extent: Extent<f32, Px>,
+    insets: Insets,
+    dest_rect: Rect<f32, Px>,
+    color: Color,
+    edge_mode: SliceMode,
+    center_mode: SliceMode,
+}
+
+impl DrawNineSlice {
+    /// `image_extent` is the size of the image in pixels, which can be found
+    /// with [`super::GraphicsContext::image_extent`].
+    pub fn new(
+        image: Image,
+        image_extent: Extent<u32, Px>,
+        insets: Insets,
+        dest_rect: Rect<f32, Px>,
+    ) -> Self {
+        #[allow(clippy::cast_precision_loss)]
+        let image_extent = Extent::new(image_extent.width as f32, image_extent.height as f32);
+
+        Self {
+            image,
+            image_extent,
+            insets,
+            dest_rect,
+            color: Color::WHITE,
+            edge_mode: SliceMode::Stretch,
+            center_mode: SliceMode::Stretch,
+        }
+    }
+
+    /// Tints the image. Defaults to white, which leaves the image unchanged.
+    pub fn with_color(mut self, color: Color) -> Self {
+        self.color = color;
+        self
+    }
+
+    pub fn with_edge_mode(mut self, mode: SliceMode) -> Self {
+        self.edge_mode = mode;
+        self
+    }
+
+    pub fn with_center_mode(mut self, mode: SliceMode) -> Self {
+        self.center_mode = mode;
+        self
+    }
+
+    pub(crate) fn image(&self) -> Image {
+        self.image
+    }
+
+    /// Builds a quad for each (part of a) slice. Every quad shares the
+    /// destination rect's shape so that there are no seams between them.
+    pub(crate) fn to_vertices(&self) -> (Vec<RoundedRectVertex>, Vec<u32>) {
+        let rect = self.dest_rect;
+        let insets = self.insets;
+
+        // The spans for each column, depending on whether the middle column is
+        // an edge or the center, and likewise for each row.
+        let columns = [self.edge_mode, self.center_mode].map(|mode| {
+            spans(
+                rect.left(),
+                rect.extent().width,
+                self.image_extent.width,
+                (insets.left, insets.right),
+                mode,
+            )
+        });
+
+        let rows = [self.edge_mode, self.center_mode].map(|mode| {
+            spans(
+                rect.top(),
+                rect.extent().height,
+                self.image_extent.height,
+                (insets.top, insets.bottom),
+                mode,
+            )
+        });
+
+        let mut vertices = Vec::new();
+        let mut indices = Vec::new();
+
+        for row in 0..3 {
+            for column in 0..3 {
+                let x_spans = &columns[usize::from(row == 1)][column];
+                let y_spans = &rows[usize::from(column == 1)][row];
+
+                for y in y_spans {
+                    for x in x_spans {
+                        let base = u32::try_from(vertices.len())
+                            .expect("too many tiles in nine-slice image");
+                        vertices.extend([
+                            self.vertex(x.dest.0, y.dest.0, x.uv.0, y.uv.0),
+                            self.vertex(x.dest.1, y.dest.0, x.uv.1, y.uv.0),
+                            self.vertex(x.dest.1, y.dest.1, x.uv.1, y.uv.1),
+                            self.vertex(x.dest.0, y.dest.1, x.uv.0, y.uv.1),
+                        ]);
+                        indices.extend([0, 1, 2, 0, 2, 3].map(|i| base + i));
+                    }
+                }
+            }
+        }
+
+        (vertices, indices)
+    }
+
+    fn vertex(&self, x: f32, y: f32, u: f32, v: f32) -> RoundedRectVertex {
+        RoundedRectVertex {
+            position: Point::new(x, y),
+            rect_size: self.dest_rect.extent(),
+            rect_center: self.dest_rect.center(),
+            outer_radii: [0.0; 4],
+            inner_radii: [0.0; 4],
+            color: self.color,
+            uv: Point::new(u, v),
+        }
+    }
+}
+
+/// A run of destination coordinates along one axis, and the normalized texture
+/// coordinates that map onto it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Span {
+    dest: (f32, f32),
+    uv: (f32, f32),
+}
+
+/// Divides one axis of the destination into spans for the near corner, the
+/// middle, and the far corner.
+fn spans(
+    start: f32,
+    length: f32,
+    image_length: f32,
+    (near, far): (f32, f32),
+    mode: SliceMode,
+) -> [Vec<Span>; 3] {
+    // Shrink the corners if they don't fit.
+    let scale = if near + far > length {
+        length / (near + far)
+    } else {
+        1.0
+    };
+
+    let near_end = start + near * scale;
+    let far_start = start + length - far * scale;
+    let middle = (near, image_length - far);
+
+    let corner = |dest: (f32, f32), src: (f32, f32)| {
+        if dest.1 > dest.0 {
+            vec![Span {
+                dest,
+                uv: (src.0 / image_length, src.1 / image_length),
+            }]
+        } else {
+            Vec::new()
+        }
+    };
+
+    let middle_spans = match mode {
+        _ if far_start <= near_end || middle.1 <= middle.0 => Vec::new(),
+        SliceMode::Stretch => corner((near_end, far_start), middle),
+        SliceMode::Tile => {
+            let tile = middle.1 - middle.0;
+            let count = ((far_start - near_end) / tile).ceil();
+
+            // Slices too small to repeat in a reasonable number of quads are
+            // stretched instead.
+            if count.is_finite() && count <= MAX_TILES as f32 {
+                #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+                let count = count as u32;
+                (0..count)
+                    .filter_map(|i| {
+                        let position = near_end + i as f32 * tile;
+                        let end = (near_end + (i + 1) as f32 * tile).min(far_start);
+                        // Far from the origin, tiles round to the nearest
+                        // representable positions, so some vanish and the
+                        // rest grow to cover them.
+                        let src_end = (middle.0 + (end - position)).min(middle.1);
+                        (end > position).then(|| Span {
+                            dest: (position, end),
+                            uv: (middle.0 / image_length, src_end / image_length),
+                        })
+                    })
+                    .collect()
+            } else {
+                corner((near_end, far_start), middle)
+            }
+        }
+    };
+
+    [
+        corner((start, near_end), (0.0, near)),
+        middle_spans,
+        corner(
+            (far_start, start + length),
+            (image_length - far, image_length),
+        ),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dest(spans: &[Span]) -> Vec<(f32, f32)> {
+        spans.iter().map(|span| span.dest).collect()
+    }
+
+    fn uv(spans: &[Span]) -> Vec<(f32, f32)> {
+        spans.iter().map(|span| span.uv).collect()
+    }
+
+    #[test]
+    fn stretched_spans() {
+        let [near, middle, far] = spans(10.0, 100.0, 20.0, (4.0, 6.0), SliceMode::Stretch);
+
+        assert_eq!(dest(&near), [(10.0, 14.0)]);
+        assert_eq!(dest(&middle), [(14.0, 104.0)]);
+        assert_eq!(dest(&far), [(104.0, 110.0)]);
+
+        assert_eq!(uv(&near), [(0.0, 0.2)]);
+        assert_eq!(uv(&middle), [(0.2, 0.7)]);
+        assert_eq!(uv(&far), [(0.7, 1.0)]);
+    }
+
+    #[test]
+    fn tiled_spans() {
+        let [_, middle, _] = spans(0.0, 30.0, 20.0, (5.0, 5.0), SliceMode::Tile);
+
+        // 20px between the corners, filled with 10px tiles.
+        assert_eq!(dest(&middle), [(5.0, 15.0), (15.0, 25.0)]);
+        assert_eq!(uv(&middle), [(0.25, 0.75), (0.25, 0.75)]);
+
+        // The last tile is cut off.
+        let [_, middle, _] = spans(0.0, 25.0, 20.0, (5.0, 5.0), SliceMode::Tile);
+        assert_eq!(dest(&middle), [(5.0, 15.0), (15.0, 20.0)]);
+        assert_eq!(uv(&middle), [(0.25, 0.75), (0.25, 0.5)]);
+    }
+
+    #[test]
+    fn tiny_tiles_stretch() {
+        let [_, middle, _] = spans(0.0, 30.0, 10.001, (5.0, 5.0), SliceMode::Tile);
+        assert_eq!(middle.len(), 1);
+        assert_eq!(middle[0].dest, (5.0, 25.0));
+    }
+
+    #[test]
+    fn tiles_far_from_the_origin() {
+        // Adding a tile to positions this large doesn't change them.
+        let [_, middle, _] = spans(1.0e9, 1000.0, 20.0, (5.0, 5.0), SliceMode::Tile);
+        assert!(!middle.is_empty());
+        assert!(middle.len() <= MAX_TILES as usize);
+        assert!(middle.iter().all(|span| span.dest.1 > span.dest.0));
+    }
+
+    #[test]
+    fn corners_shrink_to_fit() {
+        let [near, middle, far] = spans(0.0, 5.0, 20.0, (5.0, 5.0), SliceMode::Stretch);
+
+        assert_eq!(dest(&near), [(0.0, 2.5)]);
+        assert!(middle.is_empty());
+        assert_eq!(dest(&far), [(2.5, 5.0)]);
+
+        // Corners keep their full texture coordinates.
+        assert_eq!(uv(&near), [(0.0, 0.25)]);
+        assert_eq!(uv(&far), [(0.75, 1.0)]);
+    }
+}
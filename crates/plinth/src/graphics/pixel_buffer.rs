@@ -1,12 +1,22 @@
-use std::ops::Deref;
+use std::{
+    ops::{Deref, DerefMut},
+    path::Path,
+};
 
-use super::Color;
+use geometry::{Extent, Point, Px, Rect};
+
+use super::{
+    image_format::{self, DecodeError},
+    resample::{self, Filter, MipChain},
+    Color, ImageFormat,
+};
 
 /// Describes the binary representation of a pixel in a pixel buffer.
 #[repr(u8)]
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum PixelFormat {
     Rgba8,
+    Bgra8,
 }
 
 impl PixelFormat {
@@ -15,7 +25,7 @@ impl PixelFormat {
     #[must_use]
     pub fn bytes_per_pixel(self) -> usize {
         match self {
-            Self::Rgba8 => 4,
+            Self::Rgba8 | Self::Bgra8 => 4,
         }
     }
 
@@ -28,6 +38,13 @@ impl PixelFormat {
                 let a = bytes[3] as f32 / 255.0;
                 Color::new(r, g, b, a)
             }
+            Self::Bgra8 => {
+                let b = bytes[0] as f32 / 255.0;
+                let g = bytes[1] as f32 / 255.0;
+                let r = bytes[2] as f32 / 255.0;
+                let a = bytes[3] as f32 / 255.0;
+                Color::new(r, g, b, a)
+            }
         }
     }
 
@@ -56,6 +73,11 @@ impl PixelFormat {
 
                 (4, bytes)
             }
+            Self::Bgra8 => {
+                let (count, mut bytes) = Self::Rgba8.write_color(color);
+                bytes.swap(0, 2);
+                (count, bytes)
+            }
         }
     }
 }
@@ -67,13 +89,64 @@ pub enum ColorSpace {
     Srgb,
 }
 
+/// Describes how the alpha channel of a pixel buffer relates to its color
+/// channels.
+#[repr(u8)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum AlphaMode {
+    /// Color channels are independent of alpha. This is what most image files
+    /// contain.
+    #[default]
+    Straight,
+    /// Color channels have already been multiplied by alpha. This is what the
+    /// renderer blends with, since it filters without dark fringes around
+    /// translucent edges.
+    Premultiplied,
+    /// Alpha is ignored and every pixel is treated as fully opaque.
+    Opaque,
+}
+
+impl AlphaMode {
+    /// Converts a color from this alpha mode to another.
+    #[must_use]
+    pub fn convert(self, color: Color, to: AlphaMode) -> Color {
+        match (self, to) {
+            (Self::Straight, Self::Premultiplied) => color.premultiplied(),
+            (Self::Premultiplied, Self::Straight) => color.unpremultiplied(),
+            (_, Self::Opaque) | (Self::Opaque, _) => Color { a: 1.0, ..color },
+            _ => color,
+        }
+    }
+}
+
+/// Clockwise rotations that can be applied to a pixel buffer.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Rotation {
+    Rotate90,
+    Rotate180,
+    Rotate270,
+}
+
 /// A reference-counted pixel buffer.
+///
+/// Owned pixel buffers are always tightly packed, so that `row_pitch() ==
+/// width() * format().bytes_per_pixel()`.
 #[derive(Clone)]
 pub struct PixelBuffer {
     raw: RawPixelBuffer<Box<[u8]>>,
 }
 
 impl PixelBuffer {
+    /// Creates a pixel buffer filled with transparent black.
+    #[must_use]
+    pub fn new(width: u32, height: u32, format: PixelFormat, color_space: ColorSpace) -> Self {
+        let bytes = vec![0; width as usize * height as usize * format.bytes_per_pixel()];
+
+        Self {
+            raw: RawPixelBuffer::packed(format, color_space, width, height, bytes.into()),
+        }
+    }
+
     /// Creates a pixel buffer from a byte array. The byte array will be copied
     /// into the pixel buffer.
     #[must_use]
@@ -84,52 +157,25 @@ impl PixelBuffer {
         color_space: ColorSpace,
     ) -> Self {
         Self {
-            raw: RawPixelBuffer {
-                format,
-                color_space,
-                width,
-                bytes: bytes.into(),
-            },
+            raw: RawPixelBuffer::packed_from_bytes(format, color_space, width, bytes.into()),
         }
     }
 
+    /// Decodes a PNG file.
+    ///
+    /// Panics if the file cannot be decoded. See [`Self::decode`] for a
+    /// fallible version.
     #[must_use]
     pub fn from_file(bytes: &[u8]) -> Self {
-        let (bytes, width, format, color_space) = if let Ok(mut reader) = {
-            let png = png::Decoder::new(bytes);
-            png.read_info()
-        } {
-            let mut bytes = vec![0; reader.output_buffer_size()];
-
-            let _ = reader.next_frame(&mut bytes).unwrap();
-
-            let info = reader.info();
-
-            let format = match info.color_type {
-                png::ColorType::Rgba => match info.bit_depth {
-                    png::BitDepth::Eight => PixelFormat::Rgba8,
-                    _ => todo!("only 8-bit RGBA PNGs are supported at the moment"),
-                },
-                _ => todo!("only 8-bit RGBA PNGs are supported at the moment"),
-            };
-
-            let color_space = match info.srgb {
-                Some(_) => ColorSpace::Srgb,
-                _ => todo!("color spaces other than SRGB are not supported yet"),
-            };
-
-            (bytes, info.width, format, color_space)
-        } else {
-            todo!("only the PNG format is supported at the moment")
-        };
+        Self::decode(bytes).expect("failed to decode image")
+    }
 
-        Self {
-            raw: RawPixelBuffer {
-                format,
-                color_space,
-                width,
-                bytes: bytes.into(),
-            },
+    /// Decodes a PNG file into straight alpha RGBA8.
+    pub fn decode(bytes: &[u8]) -> Result<Self, DecodeError> {
+        if bytes.starts_with(image_format::PNG_SIGNATURE) {
+            image_format::decode_png(bytes)
+        } else {
+            Err(DecodeError::UnknownFormat)
         }
     }
 
@@ -169,12 +215,7 @@ impl PixelBuffer {
         };
 
         Self {
-            raw: RawPixelBuffer {
-                format,
-                color_space,
-                width,
-                bytes: bytes.into(),
-            },
+            raw: RawPixelBuffer::packed_from_bytes(format, color_space, width, bytes.into()),
         }
     }
 
@@ -190,6 +231,21 @@ impl PixelBuffer {
         self.raw.color_space
     }
 
+    #[inline]
+    #[must_use]
+    pub fn alpha_mode(&self) -> AlphaMode {
+        self.raw.alpha_mode
+    }
+
+    /// Declares how the alpha channel of the pixels should be interpreted.
+    /// This does not modify the pixels themselves.
+    #[inline]
+    #[must_use]
+    pub fn with_alpha_mode(mut self, alpha_mode: AlphaMode) -> Self {
+        self.raw.alpha_mode = alpha_mode;
+        self
+    }
+
     #[inline]
     #[must_use]
     pub fn width(&self) -> u32 {
@@ -199,7 +255,13 @@ impl PixelBuffer {
     #[inline]
     #[must_use]
     pub fn height(&self) -> u32 {
-        self.raw.height()
+        self.raw.height
+    }
+
+    #[inline]
+    #[must_use]
+    pub fn row_pitch(&self) -> usize {
+        self.raw.row_pitch
     }
 
     #[inline]
@@ -208,16 +270,116 @@ impl PixelBuffer {
         self.raw.bytes.as_ref()
     }
 
+    #[inline]
+    #[must_use]
+    pub fn bytes_mut(&mut self) -> &mut [u8] {
+        self.raw.bytes.as_mut()
+    }
+
     #[inline]
     #[must_use]
     pub fn rows(&self) -> RowIter {
         self.raw.rows()
     }
 
+    #[inline]
+    #[must_use]
+    pub fn rows_mut(&mut self) -> RowIterMut {
+        self.raw.rows_mut()
+    }
+
     #[must_use]
     pub fn as_ref(&self) -> PixelBufferRef {
         self.into()
     }
+
+    #[must_use]
+    pub fn as_mut(&mut self) -> PixelBufferMut {
+        self.into()
+    }
+
+    /// Returns a view of a rectangular region of the pixel buffer.
+    ///
+    /// Panics if the region is not contained within the pixel buffer.
+    #[must_use]
+    pub fn sub_view(&self, rect: Rect<u32, Px>) -> PixelBufferRef {
+        self.as_ref().sub_view(rect)
+    }
+
+    /// Returns a mutable view of a rectangular region of the pixel buffer.
+    ///
+    /// Panics if the region is not contained within the pixel buffer.
+    #[must_use]
+    pub fn sub_view_mut(&mut self, rect: Rect<u32, Px>) -> PixelBufferMut {
+        self.as_mut().into_sub_view(rect)
+    }
+
+    /// Copies a rectangular region of the pixel buffer into a new pixel
+    /// buffer.
+    #[must_use]
+    pub fn crop(&self, rect: Rect<u32, Px>) -> Self {
+        self.sub_view(rect).into()
+    }
+
+    /// Converts straight alpha to premultiplied alpha in place. Does nothing
+    /// if the pixel buffer is already premultiplied or opaque.
+    pub fn premultiply(&mut self) {
+        let mut pixels = self.as_mut();
+        pixels.premultiply();
+        self.raw.alpha_mode = pixels.alpha_mode();
+    }
+
+    /// Mirrors the pixel buffer left-to-right in place.
+    pub fn flip_horizontal(&mut self) {
+        self.as_mut().flip_horizontal();
+    }
+
+    /// Mirrors the pixel buffer top-to-bottom in place.
+    pub fn flip_vertical(&mut self) {
+        self.as_mut().flip_vertical();
+    }
+
+    /// Rotates the pixel buffer clockwise. Rotating by 90 or 270 degrees swaps
+    /// the width and height of the buffer.
+    pub fn rotate(&mut self, rotation: Rotation) {
+        match rotation {
+            Rotation::Rotate180 => self.as_mut().rotate_180(),
+            Rotation::Rotate90 | Rotation::Rotate270 => *self = self.as_ref().rotated(rotation),
+        }
+    }
+
+    /// Copies the pixel buffer into a new pixel buffer with a different pixel
+    /// format.
+    #[must_use]
+    pub fn convert(&self, format: PixelFormat) -> Self {
+        self.as_ref().convert(format)
+    }
+
+    /// Resamples the pixel buffer to a new size using the given filter.
+    #[must_use]
+    pub fn resize(&self, extent: Extent<u32, Px>, filter: Filter) -> Self {
+        self.as_ref().resize(extent, filter)
+    }
+
+    /// Builds a full mip chain for the pixel buffer, suitable for
+    /// [`GraphicsContext::upload_mip_chain`](super::GraphicsContext::upload_mip_chain).
+    #[must_use]
+    pub fn generate_mips(&self) -> MipChain {
+        self.as_ref().generate_mips()
+    }
+
+    /// Encodes the pixel buffer into the given file format, as
+    /// [`PixelBufferRef::encode`] describes.
+    #[must_use]
+    pub fn encode(&self, format: ImageFormat) -> Vec<u8> {
+        self.as_ref().encode(format)
+    }
+
+    /// Writes the pixel buffer to a file. The file format is chosen from the
+    /// path's extension, defaulting to PNG if it is not recognized.
+    pub fn save(&self, path: impl AsRef<Path>) -> std::io::Result<()> {
+        self.as_ref().save(path)
+    }
 }
 
 /// A pixel buffer representation over a slice of pixels.
@@ -241,12 +403,26 @@ impl<'a> PixelBufferRef<'a> {
         assert_eq!(bytes.len() % row_stride, 0);
 
         Self {
-            raw: RawPixelBuffer {
-                format,
-                color_space,
-                width,
-                bytes,
-            },
+            raw: RawPixelBuffer::packed_from_bytes(format, color_space, width, bytes),
+        }
+    }
+
+    /// Creates a pixel buffer over a byte array whose rows are `row_pitch`
+    /// bytes apart, such as a GPU readback buffer or a region of a larger
+    /// image. Padding at the end of each row is ignored.
+    ///
+    /// The last row need not be padded.
+    #[must_use]
+    pub fn from_bytes_with_pitch(
+        bytes: &'a [u8],
+        width: u32,
+        height: u32,
+        row_pitch: usize,
+        format: PixelFormat,
+        color_space: ColorSpace,
+    ) -> Self {
+        Self {
+            raw: RawPixelBuffer::strided(format, color_space, width, height, row_pitch, bytes),
         }
     }
 
@@ -262,6 +438,21 @@ impl<'a> PixelBufferRef<'a> {
         self.raw.color_space
     }
 
+    #[inline]
+    #[must_use]
+    pub fn alpha_mode(&self) -> AlphaMode {
+        self.raw.alpha_mode
+    }
+
+    /// Declares how the alpha channel of the pixels should be interpreted.
+    /// This does not modify the pixels themselves.
+    #[inline]
+    #[must_use]
+    pub fn with_alpha_mode(mut self, alpha_mode: AlphaMode) -> Self {
+        self.raw.alpha_mode = alpha_mode;
+        self
+    }
+
     #[inline]
     #[must_use]
     pub fn width(&self) -> u32 {
@@ -271,9 +462,16 @@ impl<'a> PixelBufferRef<'a> {
     #[inline]
     #[must_use]
     pub fn height(&self) -> u32 {
-        self.raw.height()
+        self.raw.height
+    }
+
+    #[inline]
+    #[must_use]
+    pub fn row_pitch(&self) -> usize {
+        self.raw.row_pitch
     }
 
+    /// The underlying bytes of the pixel buffer, including any row padding.
     #[inline]
     #[must_use]
     pub fn bytes(&self) -> &[u8] {
@@ -282,8 +480,114 @@ impl<'a> PixelBufferRef<'a> {
 
     #[inline]
     #[must_use]
-    pub fn rows(&self) -> RowIter {
-        self.raw.rows()
+    pub fn rows(&self) -> RowIter<'a> {
+        RowIter::new(&self.raw, self.raw.bytes)
+    }
+
+    /// Returns true if there is no padding between rows.
+    #[inline]
+    #[must_use]
+    pub fn is_packed(&self) -> bool {
+        self.raw.is_packed()
+    }
+
+    /// Returns a view of a rectangular region of the pixel buffer.
+    ///
+    /// Panics if the region is not contained within the pixel buffer.
+    #[must_use]
+    pub fn sub_view(&self, rect: Rect<u32, Px>) -> Self {
+        let range = self.raw.sub_view_range(rect);
+
+        Self {
+            raw: RawPixelBuffer {
+                width: rect.extent().width,
+                height: rect.extent().height,
+                bytes: &self.raw.bytes[range],
+                ..self.raw
+            },
+        }
+    }
+
+    /// Copies a rectangular region of the pixel buffer into a new pixel
+    /// buffer.
+    #[must_use]
+    pub fn crop(&self, rect: Rect<u32, Px>) -> PixelBuffer {
+        self.sub_view(rect).into()
+    }
+
+    /// Copies the pixel buffer into a new, rotated pixel buffer.
+    #[must_use]
+    pub fn rotated(&self, rotation: Rotation) -> PixelBuffer {
+        let (width, height) = match rotation {
+            Rotation::Rotate180 => (self.width(), self.height()),
+            Rotation::Rotate90 | Rotation::Rotate270 => (self.height(), self.width()),
+        };
+
+        let mut out = PixelBuffer::new(width, height, self.format(), self.color_space())
+            .with_alpha_mode(self.alpha_mode());
+        let bpp = self.format().bytes_per_pixel();
+        let src_rows: Vec<&[u8]> = self.rows().collect();
+
+        for (y, dst_row) in out.rows_mut().enumerate() {
+            for (x, dst) in dst_row.chunks_exact_mut(bpp).enumerate() {
+                let (src_x, src_y) = match rotation {
+                    Rotation::Rotate90 => (y, self.height() as usize - 1 - x),
+                    Rotation::Rotate180 => (
+                        self.width() as usize - 1 - x,
+                        self.height() as usize - 1 - y,
+                    ),
+                    Rotation::Rotate270 => (self.width() as usize - 1 - y, x),
+                };
+
+                dst.copy_from_slice(&src_rows[src_y][src_x * bpp..(src_x + 1) * bpp]);
+            }
+        }
+
+        out
+    }
+
+    /// Copies the pixel buffer into a new pixel buffer with a different pixel
+    /// format.
+    #[must_use]
+    pub fn convert(&self, format: PixelFormat) -> PixelBuffer {
+        let mut out = PixelBuffer::new(self.width(), self.height(), format, self.color_space())
+            .with_alpha_mode(self.alpha_mode());
+        out.as_mut().copy_from(*self);
+        out
+    }
+
+    /// Resamples the pixel buffer to a new size using the given filter.
+    #[must_use]
+    pub fn resize(&self, extent: Extent<u32, Px>, filter: Filter) -> PixelBuffer {
+        resample::resize(*self, extent, filter)
+    }
+
+    /// Builds a full mip chain for the pixel buffer, suitable for
+    /// [`GraphicsContext::upload_mip_chain`](super::GraphicsContext::upload_mip_chain).
+    #[must_use]
+    pub fn generate_mips(&self) -> MipChain {
+        resample::generate_mips(*self)
+    }
+
+    /// Encodes the pixel buffer into the given file format.
+    ///
+    /// The color space is written to the file. Pixels are always written as
+    /// RGBA8 with straight alpha, so the pixel format and alpha mode are not
+    /// kept, and premultiplied pixels lose some precision when they are
+    /// unpremultiplied.
+    ///
+    /// Panics if the image is too large for the format (see [`ImageFormat`]).
+    #[must_use]
+    pub fn encode(&self, format: ImageFormat) -> Vec<u8> {
+        image_format::encode(*self, format)
+    }
+
+    /// Writes the pixel buffer to a file. The file format is chosen from the
+    /// path's extension, defaulting to PNG if it is not recognized.
+    pub fn save(&self, path: impl AsRef<Path>) -> std::io::Result<()> {
+        let path = path.as_ref();
+        let format = ImageFormat::from_path(path).unwrap_or(ImageFormat::Png);
+        std::fs::write(path, self.encode(format))
     }
 }
 
@@ -291,66 +595,504 @@ impl<'a> From<&'a PixelBuffer> for PixelBufferRef<'a> {
     #[inline]
     fn from(pixel_buffer: &'a PixelBuffer) -> Self {
         Self {
+            raw: pixel_buffer.raw.borrow(),
+        }
+    }
+}
+
+impl From<PixelBufferRef<'_>> for PixelBuffer {
+    /// Copies the pixels into a new, tightly packed pixel buffer.
+    fn from(pixels: PixelBufferRef) -> Self {
+        let bytes = if pixels.is_packed() {
+            pixels.bytes().into()
+        } else {
+            pixels.rows().flatten().copied().collect()
+        };
+
+        Self {
+            raw: RawPixelBuffer::packed(
+                pixels.format(),
+                pixels.color_space(),
+                pixels.width(),
+                pixels.height(),
+                bytes,
+            ),
+        }
+        .with_alpha_mode(pixels.alpha_mode())
+    }
+}
+
+/// A mutable pixel buffer representation over a slice of pixels.
+#[allow(clippy::module_name_repetitions)]
+pub struct PixelBufferMut<'a> {
+    raw: RawPixelBuffer<&'a mut [u8]>,
+}
+
+impl<'a> PixelBufferMut<'a> {
+    /// Creates a mutable pixel buffer over a tightly packed byte array.
+    #[must_use]
+    pub fn from_bytes(
+        bytes: &'a mut [u8],
+        width: u32,
+        format: PixelFormat,
+        color_space: ColorSpace,
+    ) -> Self {
+        let row_stride = width as usize * format.bytes_per_pixel();
+        assert_eq!(bytes.len() % row_stride, 0);
+
+        Self {
+            raw: RawPixelBuffer::packed_from_bytes(format, color_space, width, bytes),
+        }
+    }
+
+    /// Creates a mutable pixel buffer over a byte array whose rows are
+    /// `row_pitch` bytes apart. See [`PixelBufferRef::from_bytes_with_pitch`].
+    #[must_use]
+    pub fn from_bytes_with_pitch(
+        bytes: &'a mut [u8],
+        width: u32,
+        height: u32,
+        row_pitch: usize,
+        format: PixelFormat,
+        color_space: ColorSpace,
+    ) -> Self {
+        Self {
+            raw: RawPixelBuffer::strided(format, color_space, width, height, row_pitch, bytes),
+        }
+    }
+
+    #[inline]
+    #[must_use]
+    pub fn format(&self) -> PixelFormat {
+        self.raw.format
+    }
+
+    #[inline]
+    #[must_use]
+    pub fn color_space(&self) -> ColorSpace {
+        self.raw.color_space
+    }
+
+    #[inline]
+    #[must_use]
+    pub fn alpha_mode(&self) -> AlphaMode {
+        self.raw.alpha_mode
+    }
+
+    /// Declares how the alpha channel of the pixels should be interpreted.
+    /// This does not modify the pixels themselves.
+    #[inline]
+    #[must_use]
+    pub fn with_alpha_mode(mut self, alpha_mode: AlphaMode) -> Self {
+        self.raw.alpha_mode = alpha_mode;
+        self
+    }
+
+    #[inline]
+    #[must_use]
+    pub fn width(&self) -> u32 {
+        self.raw.width
+    }
+
+    #[inline]
+    #[must_use]
+    pub fn height(&self) -> u32 {
+        self.raw.height
+    }
+
+    #[inline]
+    #[must_use]
+    pub fn row_pitch(&self) -> usize {
+        self.raw.row_pitch
+    }
+
+    #[inline]
+    #[must_use]
+    pub fn rows(&self) -> RowIter {
+        self.raw.rows()
+    }
+
+    #[inline]
+    #[must_use]
+    pub fn rows_mut(&mut self) -> RowIterMut {
+        self.raw.rows_mut()
+    }
+
+    #[must_use]
+    pub fn as_ref(&self) -> PixelBufferRef {
+        PixelBufferRef {
+            raw: self.raw.borrow(),
+        }
+    }
+
+    /// Reborrows the pixel buffer, so that it can be passed by value without
+    /// giving it up.
+    #[must_use]
+    pub fn as_mut(&mut self) -> PixelBufferMut {
+        PixelBufferMut {
+            raw: self.raw.borrow_mut(),
+        }
+    }
+
+    /// Returns a mutable view of a rectangular region of the pixel buffer.
+    ///
+    /// Panics if the region is not contained within the pixel buffer.
+    #[must_use]
+    pub fn sub_view_mut(&mut self, rect: Rect<u32, Px>) -> PixelBufferMut {
+        self.as_mut().into_sub_view(rect)
+    }
+
+    /// Like [`Self::sub_view_mut`], but consumes the view to preserve its
+    /// lifetime.
+    #[must_use]
+    pub fn into_sub_view(self, rect: Rect<u32, Px>) -> PixelBufferMut<'a> {
+        let range = self.raw.sub_view_range(rect);
+
+        PixelBufferMut {
             raw: RawPixelBuffer {
-                format: pixel_buffer.raw.format,
-                color_space: pixel_buffer.raw.color_space,
-                width: pixel_buffer.raw.width,
-                bytes: pixel_buffer.raw.bytes.as_ref(),
+                format: self.raw.format,
+                color_space: self.raw.color_space,
+                alpha_mode: self.raw.alpha_mode,
+                width: rect.extent().width,
+                height: rect.extent().height,
+                row_pitch: self.raw.row_pitch,
+                bytes: &mut self.raw.bytes[range],
             },
         }
     }
+
+    /// Sets every pixel in the buffer to `color`.
+    pub fn fill(&mut self, color: Color) {
+        let bpp = self.format().bytes_per_pixel();
+        let (_, bytes) = self.format().write_color(color);
+
+        for row in self.rows_mut() {
+            for pixel in row.chunks_exact_mut(bpp) {
+                pixel.copy_from_slice(&bytes[..bpp]);
+            }
+        }
+    }
+
+    /// Copies `src` into this pixel buffer with its top-left corner at `at`,
+    /// converting between pixel formats if necessary. Pixels that fall outside
+    /// of this pixel buffer are discarded.
+    pub fn blit(&mut self, src: PixelBufferRef, at: Point<u32, Px>) {
+        if at.x >= self.width() || at.y >= self.height() {
+            return;
+        }
+
+        let width = src.width().min(self.width() - at.x);
+        let height = src.height().min(self.height() - at.y);
+
+        let src = src.sub_view(Rect::new(Point::zero(), Extent::new(width, height)));
+        self.sub_view_mut(Rect::new(at, Extent::new(width, height)))
+            .copy_from(src);
+    }
+
+    /// Copies `src` into this pixel buffer, converting between pixel formats
+    /// and alpha modes if necessary.
+    ///
+    /// Panics if the two buffers differ in size.
+    pub fn copy_from(&mut self, src: PixelBufferRef) {
+        assert_eq!(self.width(), src.width());
+        assert_eq!(self.height(), src.height());
+
+        let src_format = src.format();
+        let dst_format = self.format();
+        let src_alpha = src.alpha_mode();
+        let dst_alpha = self.alpha_mode();
+
+        // Opaque pixels may still have stray alpha values, which must not be
+        // copied into an opaque buffer as they are.
+        let verbatim =
+            src_format == dst_format && src_alpha == dst_alpha && dst_alpha != AlphaMode::Opaque;
+
+        for (dst_row, src_row) in self.rows_mut().zip(src.rows()) {
+            if verbatim {
+                dst_row.copy_from_slice(src_row);
+            } else {
+                for (dst, src) in dst_row
+                    .chunks_exact_mut(dst_format.bytes_per_pixel())
+                    .zip(src_row.chunks_exact(src_format.bytes_per_pixel()))
+                {
+                    let color = src_alpha.convert(src_format.read_color(src), dst_alpha);
+                    let (count, bytes) = dst_format.write_color(color);
+                    dst.copy_from_slice(&bytes[..count as usize]);
+                }
+            }
+        }
+    }
+
+    /// Converts straight alpha to premultiplied alpha in place. Does nothing
+    /// if the pixel buffer is already premultiplied or opaque.
+    pub fn premultiply(&mut self) {
+        if self.alpha_mode() != AlphaMode::Straight {
+            return;
+        }
+
+        let format = self.format();
+        for row in self.rows_mut() {
+            for pixel in row.chunks_exact_mut(format.bytes_per_pixel()) {
+                let (count, bytes) = format.write_color(format.read_color(pixel).premultiplied());
+                pixel.copy_from_slice(&bytes[..count as usize]);
+            }
+        }
+
+        self.raw.alpha_mode = AlphaMode::Premultiplied;
+    }
+
+    /// Mirrors the pixel buffer left-to-right in place.
+    pub fn flip_horizontal(&mut self) {
+        let bpp = self.format().bytes_per_pixel();
+        let width = self.width() as usize;
+
+        for row in self.rows_mut() {
+            for x in 0..width / 2 {
+                let (left, right) = row.split_at_mut((width - 1 - x) * bpp);
+                left[x * bpp..(x + 1) * bpp].swap_with_slice(&mut right[..bpp]);
+            }
+        }
+    }
+
+    /// Mirrors the pixel buffer top-to-bottom in place.
+    pub fn flip_vertical(&mut self) {
+        let row_size = self.width() as usize * self.format().bytes_per_pixel();
+        let row_pitch = self.row_pitch();
+        let height = self.height() as usize;
+
+        for y in 0..height / 2 {
+            let (top, bottom) = self.raw.bytes.split_at_mut((height - 1 - y) * row_pitch);
+            top[y * row_pitch..y * row_pitch + row_size].swap_with_slice(&mut bottom[..row_size]);
+        }
+    }
+
+    /// Rotates the pixel buffer by 180 degrees in place.
+    pub fn rotate_180(&mut self) {
+        self.flip_horizontal();
+        self.flip_vertical();
+    }
+}
+
+impl<'a> From<&'a mut PixelBuffer> for PixelBufferMut<'a> {
+    #[inline]
+    fn from(pixel_buffer: &'a mut PixelBuffer) -> Self {
+        Self {
+            raw: pixel_buffer.raw.borrow_mut(),
+        }
+    }
 }
 
 pub struct RowIter<'a> {
+    row_size: usize,
     row_pitch: usize,
-    cursor: usize,
+    remaining: u32,
     bytes: &'a [u8],
 }
 
+impl<'a> RowIter<'a> {
+    fn new<T: Deref<Target = [u8]>>(raw: &RawPixelBuffer<T>, bytes: &'a [u8]) -> Self {
+        Self {
+            row_size: raw.row_size(),
+            row_pitch: raw.row_pitch,
+            remaining: raw.height,
+            bytes,
+        }
+    }
+}
+
 impl<'a> Iterator for RowIter<'a> {
     type Item = &'a [u8];
 
     fn next(&mut self) -> Option<Self::Item> {
-        if self.cursor >= self.bytes.len() {
+        if self.remaining == 0 {
             return None;
         }
 
-        let row = &self.bytes[self.cursor..self.cursor + self.row_pitch];
-        self.cursor += self.row_pitch;
+        let row = &self.bytes[..self.row_size];
+        self.bytes = &self.bytes[self.row_pitch.min(self.bytes.len())..];
+        self.remaining -= 1;
         Some(row)
     }
 }
 
+pub struct RowIterMut<'a> {
+    row_size: usize,
+    row_pitch: usize,
+    remaining: u32,
+    bytes: &'a mut [u8],
+}
+
+impl<'a> Iterator for RowIterMut<'a> {
+    type Item = &'a mut [u8];
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+
+        let bytes = std::mem::take(&mut self.bytes);
+        let (row, rest) = bytes.split_at_mut(self.row_pitch.min(bytes.len()));
+        self.bytes = rest;
+        self.remaining -= 1;
+        Some(&mut row[..self.row_size])
+    }
+}
+
 /// The actual implementation, generic over the storage mechanism.
 #[derive(Clone, Copy)]
 struct RawPixelBuffer<T>
 where
-    T: Deref<Target = [u8]> + Clone,
+    T: Deref<Target = [u8]>,
 {
     format: PixelFormat,
     color_space: ColorSpace,
+    alpha_mode: AlphaMode,
     width: u32,
+    height: u32,
+    /// The distance in bytes between the start of each row.
+    row_pitch: usize,
     bytes: T,
 }
 
 impl<T> RawPixelBuffer<T>
 where
-    T: Deref<Target = [u8]> + Clone,
+    T: Deref<Target = [u8]>,
 {
+    fn packed(
+        format: PixelFormat,
+        color_space: ColorSpace,
+        width: u32,
+        height: u32,
+        bytes: T,
+    ) -> Self {
+        let row_pitch = width as usize * format.bytes_per_pixel();
+        debug_assert_eq!(bytes.len(), row_pitch * height as usize);
+
+        Self {
+            format,
+            color_space,
+            alpha_mode: AlphaMode::Straight,
+            width,
+            height,
+            row_pitch,
+            bytes,
+        }
+    }
+
+    fn packed_from_bytes(
+        format: PixelFormat,
+        color_space: ColorSpace,
+        width: u32,
+        bytes: T,
+    ) -> Self {
+        let row_size = width as usize * format.bytes_per_pixel();
+        let num_rows = bytes.len() / row_size;
+        let height = u32::try_from(num_rows).expect("checked cast from usize to u32");
+        Self::packed(format, color_space, width, height, bytes)
+    }
+
+    fn strided(
+        format: PixelFormat,
+        color_space: ColorSpace,
+        width: u32,
+        height: u32,
+        row_pitch: usize,
+        bytes: T,
+    ) -> Self {
+        let row_size = width as usize * format.bytes_per_pixel();
+        assert!(row_pitch >= row_size, "row pitch smaller than a row");
+        assert!(
+            bytes.len() >= Self::required_len(row_size, row_pitch, height),
+            "not enough bytes for the given dimensions"
+        );
+
+        Self {
+            format,
+            color_space,
+            alpha_mode: AlphaMode::Straight,
+            width,
+            height,
+            row_pitch,
+            bytes,
+        }
+    }
+
     #[inline]
-    fn height(&self) -> u32 {
-        let row_size = self.width as usize * self.format.bytes_per_pixel();
-        let num_rows = self.bytes.len() / row_size;
-        u32::try_from(num_rows).expect("checked cast from usize to u32")
+    fn borrow(&self) -> RawPixelBuffer<&[u8]> {
+        RawPixelBuffer {
+            format: self.format,
+            color_space: self.color_space,
+            alpha_mode: self.alpha_mode,
+            width: self.width,
+            height: self.height,
+            row_pitch: self.row_pitch,
+            bytes: self.bytes.as_ref(),
+        }
+    }
+
+    /// The number of bytes spanned by `height` rows, not counting padding after
+    /// the last row.
+    fn required_len(row_size: usize, row_pitch: usize, height: u32) -> usize {
+        match height {
+            0 => 0,
+            height => (height as usize - 1) * row_pitch + row_size,
+        }
+    }
+
+    #[inline]
+    fn row_size(&self) -> usize {
+        self.width as usize * self.format.bytes_per_pixel()
+    }
+
+    #[inline]
+    fn is_packed(&self) -> bool {
+        self.row_pitch == self.row_size() || self.height <= 1
     }
 
     #[inline]
     fn rows(&self) -> RowIter {
-        let row_pitch = self.width as usize * self.format.bytes_per_pixel();
-        RowIter {
-            row_pitch,
-            cursor: 0,
-            bytes: self.bytes.as_ref(),
+        RowIter::new(self, self.bytes.as_ref())
+    }
+
+    /// The range of bytes covered by a sub-rectangle of the pixel buffer.
+    fn sub_view_range(&self, rect: Rect<u32, Px>) -> std::ops::Range<usize> {
+        assert!(
+            rect.right() <= self.width && rect.bottom() <= self.height,
+            "sub-view out of bounds"
+        );
+
+        let bpp = self.format.bytes_per_pixel();
+        let row_size = rect.extent().width as usize * bpp;
+        let start = rect.top() as usize * self.row_pitch + rect.left() as usize * bpp;
+        let len = Self::required_len(row_size, self.row_pitch, rect.extent().height);
+        start..start + len
+    }
+}
+
+impl<T> RawPixelBuffer<T>
+where
+    T: DerefMut<Target = [u8]>,
+{
+    #[inline]
+    fn borrow_mut(&mut self) -> RawPixelBuffer<&mut [u8]> {
+        RawPixelBuffer {
+            format: self.format,
+            color_space: self.color_space,
+            alpha_mode: self.alpha_mode,
+            width: self.width,
+            height: self.height,
+            row_pitch: self.row_pitch,
+            bytes: self.bytes.as_mut(),
+        }
+    }
+
+    #[inline]
+    fn rows_mut(&mut self) -> RowIterMut {
+        RowIterMut {
+            row_size: self.row_size(),
+            row_pitch: self.row_pitch,
+            remaining: self.height,
+            bytes: self.bytes.as_mut(),
         }
     }
 }
@@ -384,6 +1126,7 @@ mod tests {
             let buffer_ref: PixelBufferRef = (&buffer).into();
             assert_eq!(buffer_ref.format(), buffer.format());
             assert_eq!(buffer_ref.color_space(), buffer.color_space());
+            assert_eq!(buffer_ref.alpha_mode(), buffer.alpha_mode());
             assert_eq!(buffer_ref.width(), buffer.width());
             assert_eq!(buffer_ref.height(), buffer.height());
             assert_eq!(buffer_ref.bytes(), buffer.bytes());
@@ -398,4 +1141,166 @@ mod tests {
             assert_eq!(buffer_ref.bytes(), buffer.bytes());
         }
     }
+
+    fn numbered(width: u32, height: u32) -> PixelBuffer {
+        let bytes: Vec<u8> = (0..width * height)
+            .flat_map(|i| [i as u8, 0, 0, 255])
+            .collect();
+        PixelBuffer::from_bytes(&bytes, width, PixelFormat::Rgba8, ColorSpace::Srgb)
+    }
+
+    fn red_channel(pixels: PixelBufferRef) -> Vec<u8> {
+        pixels
+            .rows()
+            .flat_map(|row| row.chunks_exact(4).map(|p| p[0]))
+            .collect()
+    }
+
+    #[test]
+    fn strided_views() {
+        // 3x2 pixels with 4 bytes of padding per row, and none after the last.
+        let bytes = [
+            1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 0, 0, 0, 0, //
+            4, 4, 4, 4, 5, 5, 5, 5, 6, 6, 6, 6,
+        ];
+        let pixels = PixelBufferRef::from_bytes_with_pitch(
+            &bytes,
+            3,
+            2,
+            16,
+            PixelFormat::Rgba8,
+            ColorSpace::Srgb,
+        );
+
+        assert_eq!(pixels.height(), 2);
+        assert!(!pixels.is_packed());
+        assert_eq!(red_channel(pixels), [1, 2, 3, 4, 5, 6]);
+
+        let sub = pixels.sub_view(Rect::new(Point::new(1, 0), Extent::new(2, 2)));
+        assert_eq!(sub.width(), 2);
+        assert_eq!(sub.height(), 2);
+        assert_eq!(red_channel(sub), [2, 3, 5, 6]);
+
+        let packed: PixelBuffer = sub.into();
+        assert_eq!(packed.row_pitch(), 8);
+        assert_eq!(red_channel(packed.as_ref()), [2, 3, 5, 6]);
+    }
+
+    #[test]
+    fn mutable_sub_views() {
+        let mut pixels = numbered(4, 4);
+        pixels
+            .sub_view_mut(Rect::new(Point::new(1, 1), Extent::new(2, 2)))
+            .fill(Color::BLACK);
+
+        assert_eq!(
+            red_channel(pixels.as_ref()),
+            [0, 1, 2, 3, 4, 0, 0, 7, 8, 0, 0, 11, 12, 13, 14, 15]
+        );
+
+        let cropped = pixels.crop(Rect::new(Point::new(2, 2), Extent::new(2, 2)));
+        assert_eq!(red_channel(cropped.as_ref()), [0, 11, 14, 15]);
+    }
+
+    #[test]
+    fn flips_and_rotations() {
+        let mut pixels = numbered(3, 2);
+
+        pixels.flip_horizontal();
+        assert_eq!(red_channel(pixels.as_ref()), [2, 1, 0, 5, 4, 3]);
+        pixels.flip_horizontal();
+
+        pixels.flip_vertical();
+        assert_eq!(red_channel(pixels.as_ref()), [3, 4, 5, 0, 1, 2]);
+        pixels.flip_vertical();
+
+        pixels.rotate(Rotation::Rotate180);
+        assert_eq!(red_channel(pixels.as_ref()), [5, 4, 3, 2, 1, 0]);
+        pixels.rotate(Rotation::Rotate180);
+
+        let cw = pixels.as_ref().rotated(Rotation::Rotate90);
+        assert_eq!((cw.width(), cw.height()), (2, 3));
+        assert_eq!(red_channel(cw.as_ref()), [3, 0, 4, 1, 5, 2]);
+
+        let ccw = pixels.as_ref().rotated(Rotation::Rotate270);
+        assert_eq!((ccw.width(), ccw.height()), (2, 3));
+        assert_eq!(red_channel(ccw.as_ref()), [2, 5, 1, 4, 0, 3]);
+
+        pixels.rotate(Rotation::Rotate90);
+        pixels.rotate(Rotation::Rotate270);
+        assert_eq!(red_channel(pixels.as_ref()), [0, 1, 2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn blit_with_conversion() {
+        let src = PixelBuffer::from_colors(
+            &[Color::RED, Color::BLUE],
+            2,
+            PixelFormat::Rgba8,
+            ColorSpace::Srgb,
+        );
+
+        let bgra = src.convert(PixelFormat::Bgra8);
+        assert_eq!(bgra.bytes(), [0, 0, 255, 255, 255, 0, 0, 255]);
+        assert_eq!(bgra.convert(PixelFormat::Rgba8).bytes(), src.bytes());
+
+        // Blitting partially out of bounds clips to the destination.
+        let mut dst = PixelBuffer::new(3, 2, PixelFormat::Bgra8, ColorSpace::Srgb);
+        dst.as_mut().blit(src.as_ref(), Point::new(2, 1));
+        assert_eq!(
+            dst.bytes(),
+            [
+                0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, //
+                0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 255, 255,
+            ]
+        );
+    }
+
+    #[test]
+    fn alpha_modes() {
+        let colors = [
+            Color::new(1.0, 0.5, 0.0, 0.5),
+            Color::new(1.0, 1.0, 1.0, 0.0),
+        ];
+        let mut pixels = PixelBuffer::from_colors(&colors, 2, PixelFormat::Rgba8, ColorSpace::Srgb);
+        assert_eq!(pixels.alpha_mode(), AlphaMode::Straight);
+
+        let straight = pixels.clone();
+
+        pixels.premultiply();
+        assert_eq!(pixels.alpha_mode(), AlphaMode::Premultiplied);
+        assert_eq!(pixels.bytes(), [128, 64, 0, 128, 0, 0, 0, 0]);
+
+        // Premultiplying twice is a no-op.
+        pixels.premultiply();
+        assert_eq!(pixels.bytes(), [128, 64, 0, 128, 0, 0, 0, 0]);
+
+        // Copying converts between alpha modes, as well as formats.
+        let mut bgra = PixelBuffer::new(2, 1, PixelFormat::Bgra8, ColorSpace::Srgb)
+            .with_alpha_mode(AlphaMode::Premultiplied);
+        bgra.as_mut().copy_from(straight.as_ref());
+        assert_eq!(bgra.bytes(), [0, 64, 128, 128, 0, 0, 0, 0]);
+
+        let mut opaque = PixelBuffer::new(2, 1, PixelFormat::Rgba8, ColorSpace::Srgb)
+            .with_alpha_mode(AlphaMode::Opaque);
+        opaque.as_mut().copy_from(straight.as_ref());
+        assert_eq!(opaque.bytes(), [255, 128, 0, 255, 255, 255, 255, 255]);
+    }
+
+    #[test]
+    fn opaque_alpha_is_forced() {
+        let bytes = [10, 20, 30, 40, 50, 60, 70, 0];
+        let src = PixelBufferRef::from_bytes(&bytes, 2, PixelFormat::Rgba8, ColorSpace::Srgb)
+            .with_alpha_mode(AlphaMode::Opaque);
+
+        // Same format and alpha mode, which would otherwise be copied as is.
+        let mut dst = PixelBuffer::new(2, 1, PixelFormat::Rgba8, ColorSpace::Srgb)
+            .with_alpha_mode(AlphaMode::Opaque);
+        dst.as_mut().copy_from(src);
+        assert_eq!(dst.bytes(), [10, 20, 30, 255, 50, 60, 70, 255]);
+
+        let straight = PixelBufferRef::from_bytes(&bytes, 2, PixelFormat::Rgba8, ColorSpace::Srgb);
+        dst.as_mut().copy_from(straight);
+        assert_eq!(dst.bytes(), [10, 20, 30, 255, 50, 60, 70, 255]);
+    }
 }
@@ -1,23 +1,49 @@
-use super::{DrawRect, RoundedRectVertex};
+use std::sync::OnceLock;
+
+use geometry::{Offset, Point, Px, Rect};
+
+use super::{
+    capture::{CaptureError, Reader, Writer},
+    spatial_index::SpatialIndex,
+    DisplayList, DrawNineSlice, DrawRect, Image, RectInstance, RoundedRectVertex,
+};
 
 #[allow(clippy::module_name_repetitions)]
 #[repr(u16)]
-pub enum RenderGraphCommand {
+pub enum RenderGraphCommand<I = Image> {
     Root,
-    DrawRect { first_index: u16, num_indices: u16 },
+    /// Draws `num_instances` rects, starting at `first_instance`.
+    DrawRect {
+        first_instance: u32,
+        num_instances: u32,
+        image: Option<I>,
+    },
+    DrawNineSlice {
+        first_index: u32,
+        num_indices: u32,
+        image: I,
+    },
+    /// Draws the display list at `index` in the graph's display lists.
+    DrawDisplayList {
+        index: u32,
+    },
 }
 
-struct RenderGraphNode {
-    next: u16,
-    first_child: u16,
-    last_child: u16,
-    command: RenderGraphCommand,
+struct RenderGraphNode<I> {
+    next: u32,
+    first_child: u32,
+    last_child: u32,
+    /// Conservative bounds of what the command draws, not including children.
+    bounds: Option<Rect<f32, Px>>,
+    /// Identifies whatever drew the node, for hit testing.
+    tag: Option<u64>,
+    command: RenderGraphCommand<I>,
 }
 
 #[allow(clippy::module_name_repetitions)]
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub struct RenderGraphNodeId {
-    index: u16,
+    index: u32,
 }
 
 impl RenderGraphNodeId {
@@ -25,25 +51,49 @@ impl RenderGraphNodeId {
     pub const fn root() -> Self {
         Self { index: 0 }
     }
+
+    pub(crate) fn from_index(index: usize) -> Self {
+        Self {
+            index: to_u32(index),
+        }
+    }
+
+    pub(crate) const fn index(self) -> usize {
+        self.index as usize
+    }
 }
 
-pub struct RenderGraph {
-    pub(crate) imm_indices: Vec<u16>,
+/// A tree of drawing commands.
+///
+/// Images are referred to by `I`, which is [`Image`] except for captured
+/// frames, where images are indices into the capture's list of images.
+pub struct RenderGraph<I = Image> {
+    pub(crate) imm_indices: Vec<u32>,
     pub(crate) imm_rect_vertices: Vec<RoundedRectVertex>,
-    nodes: Vec<RenderGraphNode>,
+    pub(crate) imm_rect_instances: Vec<RectInstance>,
+    /// Display lists drawn by the graph, and where they are drawn.
+    pub(crate) display_lists: Vec<(DisplayList, Offset<f32, Px>)>,
+    nodes: Vec<RenderGraphNode<I>>,
+    /// Built when first needed, and reset whenever a node is added.
+    spatial_index: OnceLock<SpatialIndex>,
 }
 
-impl Default for RenderGraph {
+impl<I> Default for RenderGraph<I> {
     fn default() -> Self {
         Self {
             imm_indices: Vec::new(),
             imm_rect_vertices: Vec::new(),
+            imm_rect_instances: Vec::new(),
+            display_lists: Vec::new(),
             nodes: vec![RenderGraphNode {
                 next: 0,
                 first_child: 0,
                 last_child: 0,
+                bounds: None,
+                tag: None,
                 command: RenderGraphCommand::Root,
             }],
+            spatial_index: OnceLock::new(),
         }
     }
 }
@@ -54,21 +104,273 @@ impl RenderGraph {
         Self::default()
     }
 
+    /// Every image drawn by the graph, possibly with duplicates.
+    pub(crate) fn images(&self) -> impl Iterator<Item = Image> + '_ {
+        let lists = self
+            .display_lists
+            .iter()
+            .flat_map(|(list, _)| list.data().images.iter().copied());
+
+        self.nodes
+            .iter()
+            .filter_map(|node| match node.command {
+                RenderGraphCommand::Root | RenderGraphCommand::DrawDisplayList { .. } => None,
+                RenderGraphCommand::DrawRect { image, .. } => image,
+                RenderGraphCommand::DrawNineSlice { image, .. } => Some(image),
+            })
+            .chain(lists)
+    }
+
+    pub fn draw_rect(&mut self, parent: RenderGraphNodeId, rect: &DrawRect) -> RenderGraphNodeId {
+        let first_instance = to_u32(self.imm_rect_instances.len());
+        self.imm_rect_instances.push(rect.to_instance());
+
+        self.push_node(
+            parent,
+            RenderGraphCommand::DrawRect {
+                first_instance,
+                num_instances: 1,
+                image: rect.image(),
+            },
+        )
+    }
+
+    pub fn draw_nine_slice(
+        &mut self,
+        parent: RenderGraphNodeId,
+        nine_slice: &DrawNineSlice,
+    ) -> RenderGraphNodeId {
+        let (vertices, indices) = nine_slice.to_vertices();
+        let (first_index, num_indices) = self.push_mesh(&vertices, &indices);
+
+        self.push_node(
+            parent,
+            RenderGraphCommand::DrawNineSlice {
+                first_index,
+                num_indices,
+                image: nine_slice.image(),
+            },
+        )
+    }
+
+    /// Draws a display list with its origin at `offset`.
+    pub fn draw_display_list(
+        &mut self,
+        parent: RenderGraphNodeId,
+        list: &DisplayList,
+        offset: Offset<f32, Px>,
+    ) -> RenderGraphNodeId {
+        let index = to_u32(self.display_lists.len());
+        self.display_lists.push((list.clone(), offset));
+        self.push_node(parent, RenderGraphCommand::DrawDisplayList { index })
+    }
+
+    /// Copies the contents of `graph` (the children of its root) to the end of
+    /// `parent`'s children, renumbering its vertices, indices, and display
+    /// lists to fit into this graph.
+    ///
+    /// Render graphs are `Send`, so independent parts of a large view can be
+    /// built on worker threads and then spliced together. Tags are copied,
+    /// but node ids from `graph` can't be used with this graph.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the combined graph would hold more vertices, indices, or
+    /// nodes than a `u32` can address.
+    pub fn splice(&mut self, parent: RenderGraphNodeId, graph: &RenderGraph) {
+        self.append(parent, graph, Offset::zero());
+    }
+
+    /// Copies the graph, replacing every display list with a copy of its
+    /// contents. Captures do this so that they don't need to save display
+    /// lists separately.
+    pub(crate) fn inline_display_lists(&self) -> RenderGraph {
+        // The nodes are copied over one at a time, so that each list's
+        // contents can be added before the children of the node that drew it,
+        // as they are painted.
+        let mut graph = self.map_images(|image| *image);
+        graph.display_lists.clear();
+        graph.nodes.truncate(1);
+        graph.nodes[0].first_child = 0;
+        graph.nodes[0].last_child = 0;
+        graph.spatial_index.take();
+
+        let mut stack = vec![(RenderGraphNodeId::root(), RenderGraphNodeId::root())];
+        while let Some((from, to)) = stack.pop() {
+            for child in self.iter_children(from) {
+                let command = match self.get(child) {
+                    RenderGraphCommand::Root => unreachable!("only the root is a root"),
+                    RenderGraphCommand::DrawRect {
+                        first_instance,
+                        num_instances,
+                        image,
+                    } => RenderGraphCommand::DrawRect {
+                        first_instance: *first_instance,
+                        num_instances: *num_instances,
+                        image: *image,
+                    },
+                    RenderGraphCommand::DrawNineSlice {
+                        first_index,
+                        num_indices,
+                        image,
+                    } => RenderGraphCommand::DrawNineSlice {
+                        first_index: *first_index,
+                        num_indices: *num_indices,
+                        image: *image,
+                    },
+                    // An empty draw takes the list's place, with the list's
+                    // contents as its first children.
+                    RenderGraphCommand::DrawDisplayList { .. } => RenderGraphCommand::DrawRect {
+                        first_instance: 0,
+                        num_instances: 0,
+                        image: None,
+                    },
+                };
+
+                let node = graph.push_node(to, command);
+                graph.nodes[node.index as usize].tag = self.tag(child);
+
+                if let RenderGraphCommand::DrawDisplayList { index } = self.get(child) {
+                    let (list, offset) = &self.display_lists[*index as usize];
+                    let contents = list.data().graph.inline_display_lists();
+                    graph.append(node, &contents, *offset);
+                }
+
+                stack.push((child, node));
+            }
+        }
+
+        graph
+    }
+
+    /// Like [`Self::splice`], but also moves everything by `offset`.
+    fn append(&mut self, parent: RenderGraphNodeId, other: &RenderGraph, offset: Offset<f32, Px>) {
+        let first_instance = to_u32(self.imm_rect_instances.len());
+        self.imm_rect_instances
+            .extend(other.imm_rect_instances.iter().map(|rect| {
+                let mut rect = *rect;
+                rect.translate(offset);
+                rect
+            }));
+
+        let first_vertex = to_u32(self.imm_rect_vertices.len());
+        self.imm_rect_vertices
+            .extend(
+                other
+                    .imm_rect_vertices
+                    .iter()
+                    .map(|vertex| RoundedRectVertex {
+                        position: vertex.position + offset,
+                        rect_center: vertex.rect_center + offset,
+                        ..*vertex
+                    }),
+            );
+
+        let first_index = to_u32(self.imm_indices.len());
+        self.imm_indices.extend(other.imm_indices.iter().map(|i| {
+            i.checked_add(first_vertex)
+                .expect("render graph exceeds 32-bit limits")
+        }));
+
+        let first_list = to_u32(self.display_lists.len());
+        self.display_lists.extend(
+            other
+                .display_lists
+                .iter()
+                .map(|(list, list_offset)| (list.clone(), *list_offset + offset)),
+        );
+
+        let offset_by = |first: u32, by: u32| {
+            first
+                .checked_add(by)
+                .expect("render graph exceeds 32-bit limits")
+        };
+
+        let mut stack = vec![(RenderGraphNodeId::root(), parent)];
+        while let Some((from, to)) = stack.pop() {
+            for child in other.iter_children(from) {
+                let command = match other.get(child) {
+                    RenderGraphCommand::Root => unreachable!("only the root is a root"),
+                    RenderGraphCommand::DrawRect {
+                        first_instance: first,
+                        num_instances,
+                        image,
+                    } => RenderGraphCommand::DrawRect {
+                        first_instance: offset_by(*first, first_instance),
+                        num_instances: *num_instances,
+                        image: *image,
+                    },
+                    RenderGraphCommand::DrawNineSlice {
+                        first_index: first,
+                        num_indices,
+                        image,
+                    } => RenderGraphCommand::DrawNineSlice {
+                        first_index: offset_by(*first, first_index),
+                        num_indices: *num_indices,
+                        image: *image,
+                    },
+                    RenderGraphCommand::DrawDisplayList { index } => {
+                        RenderGraphCommand::DrawDisplayList {
+                            index: offset_by(*index, first_list),
+                        }
+                    }
+                };
+
+                let node = self.push_node(to, command);
+                self.nodes[node.index as usize].tag = other.tag(child);
+                stack.push((child, node));
+            }
+        }
+    }
+
+    /// Appends vertices and indices to the immediate buffers, returning the
+    /// first index and number of indices.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the graph would hold more vertices or indices than a `u32`
+    /// can address.
+    fn push_mesh(&mut self, vertices: &[RoundedRectVertex], indices: &[u32]) -> (u32, u32) {
+        let vertex_offset = to_u32(self.imm_rect_vertices.len());
+        self.imm_rect_vertices.extend_from_slice(vertices);
+
+        let first_index = to_u32(self.imm_indices.len());
+        self.imm_indices.extend(indices.iter().map(|i| {
+            i.checked_add(vertex_offset)
+                .expect("render graph exceeds 32-bit limits")
+        }));
+
+        (first_index, to_u32(indices.len()))
+    }
+}
+
+impl<I> RenderGraph<I> {
     #[must_use]
-    pub fn get(&self, node: RenderGraphNodeId) -> &RenderGraphCommand {
+    pub fn get(&self, node: RenderGraphNodeId) -> &RenderGraphCommand<I> {
         &self.nodes[node.index as usize].command
     }
 
+    /// Tags the node with a value that identifies whatever drew it, like a
+    /// widget, so that [`Self::hit_test`] results can be mapped back to it.
+    pub fn set_tag(&mut self, node: RenderGraphNodeId, tag: u64) {
+        self.nodes[node.index as usize].tag = Some(tag);
+    }
+
+    #[must_use]
+    pub fn tag(&self, node: RenderGraphNodeId) -> Option<u64> {
+        self.nodes[node.index as usize].tag
+    }
+
     pub fn iter_children(
         &self,
         node: RenderGraphNodeId,
     ) -> impl Iterator<Item = RenderGraphNodeId> + '_ {
-        struct It<'a> {
-            current: u16,
-            graph: &'a RenderGraph,
+        struct It<'a, I> {
+            current: u32,
+            graph: &'a RenderGraph<I>,
         }
 
-        impl<'a> Iterator for It<'a> {
+        impl<'a, I> Iterator for It<'a, I> {
             type Item = RenderGraphNodeId;
 
             fn next(&mut self) -> Option<Self::Item> {
@@ -90,26 +392,103 @@ impl RenderGraph {
         }
     }
 
-    pub fn draw_rect(&mut self, parent: RenderGraphNodeId, rect: &DrawRect) {
-        let (vertices, indices) = rect.to_vertices();
+    /// The bounds of what the node draws, not including its children, or
+    /// `None` if it doesn't draw anything.
+    pub(crate) fn bounds(&self, node: RenderGraphNodeId) -> Option<Rect<f32, Px>> {
+        self.nodes[node.index as usize].bounds
+    }
+
+    pub(crate) fn spatial_index(&self) -> &SpatialIndex {
+        self.spatial_index.get_or_init(|| SpatialIndex::new(self))
+    }
+
+    /// The number of nodes in the graph, including the root.
+    pub(crate) fn num_nodes(&self) -> usize {
+        self.nodes.len()
+    }
 
-        let vertex_offset = self.imm_rect_vertices.len();
-        self.imm_rect_vertices.extend_from_slice(&vertices);
+    /// Copies the graph, replacing each image with `f(image)`.
+    pub(crate) fn map_images<J>(&self, mut f: impl FnMut(&I) -> J) -> RenderGraph<J> {
+        let nodes = self
+            .nodes
+            .iter()
+            .map(|node| RenderGraphNode {
+                next: node.next,
+                first_child: node.first_child,
+                last_child: node.last_child,
+                bounds: node.bounds,
+                tag: node.tag,
+                command: match &node.command {
+                    RenderGraphCommand::Root => RenderGraphCommand::Root,
+                    RenderGraphCommand::DrawRect {
+                        first_instance,
+                        num_instances,
+                        image,
+                    } => RenderGraphCommand::DrawRect {
+                        first_instance: *first_instance,
+                        num_instances: *num_instances,
+                        image: image.as_ref().map(&mut f),
+                    },
+                    RenderGraphCommand::DrawNineSlice {
+                        first_index,
+                        num_indices,
+                        image,
+                    } => RenderGraphCommand::DrawNineSlice {
+                        first_index: *first_index,
+                        num_indices: *num_indices,
+                        image: f(image),
+                    },
+                    RenderGraphCommand::DrawDisplayList { index } => {
+                        RenderGraphCommand::DrawDisplayList { index: *index }
+                    }
+                },
+            })
+            .collect();
 
-        let first_index = self.imm_indices.len();
-        self.imm_indices
-            .extend(indices.map(|i| i + vertex_offset as u16));
+        RenderGraph {
+            imm_indices: self.imm_indices.clone(),
+            imm_rect_vertices: self.imm_rect_vertices.clone(),
+            imm_rect_instances: self.imm_rect_instances.clone(),
+            display_lists: self.display_lists.clone(),
+            nodes,
+            spatial_index: OnceLock::new(),
+        }
+    }
+
+    /// Every command in the graph, in painting order: parents before their
+    /// children, and children in the order they were added.
+    pub(crate) fn paint_order(&self) -> Vec<&RenderGraphCommand<I>> {
+        let mut commands = Vec::with_capacity(self.nodes.len());
 
-        let node_id = self.nodes.len() as u16;
+        // A stack instead of recursion, since graphs can be very deep.
+        // Children are pushed in reverse so that they are popped in order.
+        let mut stack = vec![RenderGraphNodeId::root()];
+        while let Some(node) = stack.pop() {
+            commands.push(self.get(node));
+
+            let start = stack.len();
+            stack.extend(self.iter_children(node));
+            stack[start..].reverse();
+        }
+
+        commands
+    }
+
+    pub(crate) fn push_node(
+        &mut self,
+        parent: RenderGraphNodeId,
+        command: RenderGraphCommand<I>,
+    ) -> RenderGraphNodeId {
+        let node_id = to_u32(self.nodes.len());
         self.nodes.push(RenderGraphNode {
             next: 0,
             first_child: 0,
             last_child: 0,
-            command: RenderGraphCommand::DrawRect {
-                first_index: first_index as u16,
-                num_indices: indices.len() as u16,
-            },
+            bounds: self.command_bounds(&command),
+            tag: None,
+            command,
         });
+        self.spatial_index.take();
 
         let parent = &mut self.nodes[parent.index as usize];
         let prev_sibling = parent.last_child as usize;
@@ -120,5 +499,424 @@ impl RenderGraph {
         } else {
             self.nodes[prev_sibling].next = node_id;
         }
+
+        RenderGraphNodeId { index: node_id }
+    }
+
+    fn command_bounds(&self, command: &RenderGraphCommand<I>) -> Option<Rect<f32, Px>> {
+        match command {
+            RenderGraphCommand::Root => None,
+            RenderGraphCommand::DrawRect {
+                first_instance,
+                num_instances,
+                ..
+            } => {
+                let first = *first_instance as usize;
+                bounding_box(
+                    self.imm_rect_instances[first..first + *num_instances as usize]
+                        .iter()
+                        .map(|rect| {
+                            let bounds = rect.bounds();
+                            (bounds.top_left(), bounds.bottom_right())
+                        }),
+                )
+            }
+            RenderGraphCommand::DrawNineSlice {
+                first_index,
+                num_indices,
+                ..
+            } => {
+                let first = *first_index as usize;
+                bounding_box(
+                    self.imm_indices[first..first + *num_indices as usize]
+                        .iter()
+                        .map(|i| {
+                            let position = self.imm_rect_vertices[*i as usize].position;
+                            (position, position)
+                        }),
+                )
+            }
+            RenderGraphCommand::DrawDisplayList { index } => {
+                let (list, offset) = &self.display_lists[*index as usize];
+                list.bounds().map(|bounds| bounds + *offset)
+            }
+        }
+    }
+}
+
+/// The smallest rect containing every `(top_left, bottom_right)` pair.
+fn bounding_box(
+    mut corners: impl Iterator<Item = (Point<f32, Px>, Point<f32, Px>)>,
+) -> Option<Rect<f32, Px>> {
+    let first = corners.next()?;
+    let (min, max) = corners.fold(first, |(min, max), (a, b)| (min.min(a), max.max(b)));
+    Some(Rect::from_points(min, max))
+}
+
+/// Captured render graphs refer to images by their index in the capture.
+impl RenderGraph<u32> {
+    const NO_IMAGE: u32 = u32::MAX;
+
+    pub(crate) fn write(&self, w: &mut Writer) {
+        w.u32(to_u32(self.imm_rect_vertices.len()));
+        for vertex in &self.imm_rect_vertices {
+            w.vertex(vertex);
+        }
+
+        w.u32(to_u32(self.imm_indices.len()));
+        for index in &self.imm_indices {
+            w.u32(*index);
+        }
+
+        w.u32(to_u32(self.imm_rect_instances.len()));
+        for instance in &self.imm_rect_instances {
+            w.instance(instance);
+        }
+
+        w.u32(to_u32(self.nodes.len()));
+        for node in &self.nodes {
+            w.u32(node.next);
+            w.u32(node.first_child);
+            w.u32(node.last_child);
+
+            match node.command {
+                RenderGraphCommand::Root => w.u8(0),
+                RenderGraphCommand::DrawRect {
+                    first_instance,
+                    num_instances,
+                    image,
+                } => {
+                    w.u8(1);
+                    w.u32(first_instance);
+                    w.u32(num_instances);
+                    w.u32(image.unwrap_or(Self::NO_IMAGE));
+                }
+                RenderGraphCommand::DrawNineSlice {
+                    first_index,
+                    num_indices,
+                    image,
+                } => {
+                    w.u8(2);
+                    w.u32(first_index);
+                    w.u32(num_indices);
+                    w.u32(image);
+                }
+                RenderGraphCommand::DrawDisplayList { .. } => {
+                    unreachable!("display lists are inlined before capturing")
+                }
+            }
+        }
+    }
+
+    /// Reads a graph written by [`Self::write`], checking that every node,
+    /// index, and image it refers to exists so that it can be drawn safely.
+    ///
+    /// Version 1 captures stored indices and node links as `u16`, and before
+    /// version 3 rects were drawn as quads instead of instances.
+    pub(crate) fn read(
+        r: &mut Reader,
+        version: u32,
+        num_images: usize,
+    ) -> Result<Self, CaptureError> {
+        let index = |r: &mut Reader| {
+            if version == 1 {
+                r.u16().map(u32::from)
+            } else {
+                r.u32()
+            }
+        };
+
+        let num_vertices = r.u32()? as usize;
+        let imm_rect_vertices = (0..num_vertices)
+            .map(|_| r.vertex())
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let num_indices = r.u32()? as usize;
+        let imm_indices = (0..num_indices)
+            .map(|_| index(r))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        if imm_indices.iter().any(|i| *i as usize >= num_vertices) {
+            return Err(CaptureError::Malformed("index out of bounds".into()));
+        }
+
+        let mut imm_rect_instances = if version >= 3 {
+            let num_instances = r.u32()? as usize;
+            (0..num_instances)
+                .map(|_| r.instance())
+                .collect::<Result<Vec<_>, _>>()?
+        } else {
+            Vec::new()
+        };
+
+        let num_nodes = r.u32()? as usize;
+        if num_nodes == 0 {
+            return Err(CaptureError::Malformed("invalid node count".into()));
+        }
+
+        // Not preallocated, since the count hasn't been checked against the
+        // length of the data yet.
+        let mut nodes = Vec::new();
+        for i in 0..num_nodes {
+            let next = index(r)?;
+            let first_child = index(r)?;
+            let last_child = index(r)?;
+
+            // Links always point forward, since nodes are added after their
+            // parents and previous siblings. This also rules out cycles, and
+            // shared nodes are ruled out below.
+            if [next, first_child, last_child]
+                .iter()
+                .any(|&link| link != 0 && (link as usize <= i || link as usize >= num_nodes))
+            {
+                return Err(CaptureError::Malformed("invalid node link".into()));
+            }
+
+            let command = match r.u8()? {
+                0 if i == 0 => RenderGraphCommand::Root,
+                tag @ (1 | 2) if i != 0 => {
+                    let first = index(r)? as usize;
+                    let count = index(r)? as usize;
+                    let image = r.u32()?;
+
+                    let image = (image != Self::NO_IMAGE).then_some(image);
+                    if image.is_some_and(|image| image as usize >= num_images) {
+                        return Err(CaptureError::Malformed("image out of bounds".into()));
+                    }
+
+                    match (tag, image) {
+                        (1, image) if version >= 3 => {
+                            if first + count > imm_rect_instances.len() {
+                                return Err(CaptureError::Malformed(
+                                    "instance range out of bounds".into(),
+                                ));
+                            }
+
+                            RenderGraphCommand::DrawRect {
+                                first_instance: to_u32(first),
+                                num_instances: to_u32(count),
+                                image,
+                            }
+                        }
+                        (1, image) => {
+                            let instance =
+                                Self::read_quad(&imm_indices, &imm_rect_vertices, first, count)?;
+                            imm_rect_instances.push(instance);
+
+                            RenderGraphCommand::DrawRect {
+                                first_instance: to_u32(imm_rect_instances.len() - 1),
+                                num_instances: 1,
+                                image,
+                            }
+                        }
+                        (_, Some(image)) => {
+                            if first + count > imm_indices.len() {
+                                return Err(CaptureError::Malformed(
+                                    "index range out of bounds".into(),
+                                ));
+                            }
+
+                            RenderGraphCommand::DrawNineSlice {
+                                first_index: to_u32(first),
+                                num_indices: to_u32(count),
+                                image,
+                            }
+                        }
+                        (_, None) => {
+                            return Err(CaptureError::Malformed("nine-slice without image".into()))
+                        }
+                    }
+                }
+                _ => return Err(CaptureError::Malformed("invalid command".into())),
+            };
+
+            nodes.push(RenderGraphNode {
+                next,
+                first_child,
+                last_child,
+                bounds: None,
+                tag: None,
+                command,
+            });
+        }
+
+        Self::check_tree(&nodes)?;
+
+        let mut graph = Self {
+            imm_indices,
+            imm_rect_vertices,
+            imm_rect_instances,
+            display_lists: Vec::new(),
+            nodes,
+            spatial_index: OnceLock::new(),
+        };
+
+        // Bounds aren't saved, since they can be recomputed from the commands.
+        for i in 0..graph.nodes.len() {
+            graph.nodes[i].bounds = graph.command_bounds(&graph.nodes[i].command);
+        }
+
+        Ok(graph)
+    }
+
+    /// Checks that forward links between `nodes` form a tree under the root:
+    /// every other node is linked to exactly once, as a first child or next
+    /// sibling, and each node's last child ends its chain of children.
+    fn check_tree(nodes: &[RenderGraphNode<u32>]) -> Result<(), CaptureError> {
+        let mut linked = vec![false; nodes.len()];
+        for (i, node) in nodes.iter().enumerate() {
+            let links = if i == 0 {
+                // The root has no siblings.
+                if node.next != 0 {
+                    return Err(CaptureError::Malformed("invalid node link".into()));
+                }
+                [node.first_child, 0]
+            } else {
+                [node.first_child, node.next]
+            };
+
+            for link in links.into_iter().filter(|link| *link != 0) {
+                if std::mem::replace(&mut linked[link as usize], true) {
+                    return Err(CaptureError::Malformed("node linked more than once".into()));
+                }
+            }
+        }
+
+        if linked.iter().skip(1).any(|linked| !linked) {
+            return Err(CaptureError::Malformed("unreachable node".into()));
+        }
+
+        // Each node is in exactly one chain, so this visits every node once.
+        for node in nodes {
+            let mut last = 0;
+            let mut child = node.first_child;
+            while child != 0 {
+                last = child;
+                child = nodes[child as usize].next;
+            }
+
+            if last != node.last_child {
+                return Err(CaptureError::Malformed("invalid last child".into()));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Converts a rect drawn as a quad by older captures into an instance.
+    fn read_quad(
+        indices: &[u32],
+        vertices: &[RoundedRectVertex],
+        first_index: usize,
+        num_indices: usize,
+    ) -> Result<RectInstance, CaptureError> {
+        match indices.get(first_index..first_index + num_indices) {
+            Some(&[a, b, c, a2, c2, d]) if a == a2 && c == c2 => Ok(RectInstance::from_vertices(
+                &[a, b, c, d].map(|i| vertices[i as usize]),
+            )),
+            _ => Err(CaptureError::Malformed("invalid rect".into())),
+        }
+    }
+}
+
+// Sub-graphs are built on worker threads.
+const _: () = {
+    const fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<RenderGraph>();
+};
+
+/// Converts a vertex, index, or node count to the graph's 32-bit indices.
+pub(crate) fn to_u32(value: usize) -> u32 {
+    u32::try_from(value).expect("render graph exceeds 32-bit limits")
+}
+
+#[cfg(test)]
+mod tests {
+    use geometry::{Extent, Point, Rect};
+
+    use super::*;
+
+    #[test]
+    fn more_than_u16_nodes() {
+        let mut graph = RenderGraph::new();
+        let rect = DrawRect::new(Rect::new(Point::new(0.0, 0.0), Extent::new(1.0, 1.0)));
+
+        let mut parent = RenderGraphNodeId::root();
+        for _ in 0..70_000 {
+            graph.draw_rect(parent, &rect);
+            parent = RenderGraphNodeId {
+                index: to_u32(graph.num_nodes() - 1),
+            };
+        }
+
+        assert_eq!(graph.num_nodes(), 70_001);
+        assert_eq!(graph.paint_order().len(), 70_001);
+        assert!(matches!(
+            graph.get(parent),
+            RenderGraphCommand::DrawRect {
+                first_instance: 69_999,
+                num_instances: 1,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn more_than_u16_vertices() {
+        let mut graph = RenderGraph::new();
+        let quad = DrawRect::new(Rect::new(Point::new(0.0, 0.0), Extent::new(1.0, 1.0)))
+            .to_instance()
+            .to_vertices();
+
+        for _ in 0..20_000 {
+            graph.push_mesh(&quad, &[0, 1, 2, 0, 2, 3]);
+        }
+
+        assert_eq!(graph.push_mesh(&quad, &[0, 1, 2, 0, 2, 3]), (120_000, 6));
+        assert_eq!(
+            graph.imm_indices[120_000..],
+            [80_000, 80_001, 80_002, 80_000, 80_002, 80_003]
+        );
+    }
+
+    #[test]
+    fn splice_from_threads() {
+        let rect = |x: f32| DrawRect::new(Rect::new(Point::new(x, 0.0), Extent::new(1.0, 1.0)));
+        let quad = rect(0.0).to_instance().to_vertices();
+
+        let mut graph = RenderGraph::new();
+        graph.draw_rect(RenderGraphNodeId::root(), &rect(0.0));
+        graph.push_mesh(&quad, &[0, 1, 2]);
+        let parent = graph.draw_rect(RenderGraphNodeId::root(), &rect(1.0));
+
+        let parts = std::thread::scope(|scope| {
+            let workers = [2.0, 3.0].map(|x| {
+                scope.spawn(move || {
+                    let mut part = RenderGraph::new();
+                    let node = part.draw_rect(RenderGraphNodeId::root(), &rect(x));
+                    part.set_tag(node, x as u64);
+                    part.push_mesh(&quad, &[0, 2, 3]);
+                    part
+                })
+            });
+            workers.map(|worker| worker.join().unwrap())
+        });
+
+        for part in &parts {
+            graph.splice(parent, part);
+        }
+
+        let children = graph
+            .iter_children(parent)
+            .map(|node| match graph.get(node) {
+                RenderGraphCommand::DrawRect { first_instance, .. } => {
+                    (*first_instance, graph.tag(node))
+                }
+                _ => unreachable!(),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(children, [(2, Some(2)), (3, Some(3))]);
+
+        assert_eq!(graph.imm_indices, [0, 1, 2, 4, 6, 7, 8, 10, 11]);
     }
 }
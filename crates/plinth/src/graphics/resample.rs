@@ -0,0 +1,380 @@
+use geometry::{Extent, Px};
+
+use super::{AlphaMode, Color, ColorSpace, PixelBuffer, PixelBufferRef};
+
+/// Reconstruction filters for resizing pixel buffers, in increasing order of
+/// quality and cost.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Filter {
+    /// Picks the closest source pixel. Fast, but blocky when upscaling and
+    /// aliased when downscaling.
+    Nearest,
+    /// Linear interpolation between the two closest pixels along each axis (a
+    /// box-like average when downscaling).
+    Bilinear,
+    /// Catmull-Rom cubic interpolation. Sharper than bilinear, with slight
+    /// ringing around hard edges.
+    Bicubic,
+    /// Windowed sinc over three lobes. The sharpest of the filters, and the
+    /// most expensive.
+    Lanczos3,
+}
+
+impl Filter {
+    /// The radius of the filter kernel, in source pixels at a scale of 1.
+    fn support(self) -> f32 {
+        match self {
+            Self::Nearest => 0.5,
+            Self::Bilinear => 1.0,
+            Self::Bicubic => 2.0,
+            Self::Lanczos3 => 3.0,
+        }
+    }
+
+    fn weight(self, x: f32) -> f32 {
+        let x = x.abs();
+        match self {
+            Self::Nearest => {
+                if x <= 0.5 {
+                    1.0
+                } else {
+                    0.0
+                }
+            }
+            Self::Bilinear => (1.0 - x).max(0.0),
+            Self::Bicubic => {
+                // Catmull-Rom: B = 0, C = 0.5
+                if x < 1.0 {
+                    1.5 * x * x * x - 2.5 * x * x + 1.0
+                } else if x < 2.0 {
+                    -0.5 * x * x * x + 2.5 * x * x - 4.0 * x + 2.0
+                } else {
+                    0.0
+                }
+            }
+            Self::Lanczos3 => {
+                if x < 3.0 {
+                    sinc(x) * sinc(x / 3.0)
+                } else {
+                    0.0
+                }
+            }
+        }
+    }
+}
+
+fn sinc(x: f32) -> f32 {
+    if x == 0.0 {
+        1.0
+    } else {
+        let x = x * std::f32::consts::PI;
+        x.sin() / x
+    }
+}
+
+/// A sequence of progressively half-sized images, from the full-sized image at
+/// level 0 down to a single pixel.
+#[derive(Clone)]
+pub struct MipChain {
+    levels: Vec<PixelBuffer>,
+}
+
+impl MipChain {
+    #[must_use]
+    pub fn levels(&self) -> &[PixelBuffer] {
+        &self.levels
+    }
+
+    #[must_use]
+    pub fn num_levels(&self) -> u32 {
+        u32::try_from(self.levels.len()).expect("checked cast from usize to u32")
+    }
+}
+
+pub(crate) fn generate_mips(pixels: PixelBufferRef) -> MipChain {
+    let mut levels = vec![PixelBuffer::from(pixels)];
+
+    loop {
+        let prev = levels.last().unwrap();
+        if prev.width() <= 1 && prev.height() <= 1 {
+            break;
+        }
+
+        let extent = Extent::new((prev.width() / 2).max(1), (prev.height() / 2).max(1));
+        let next = resize(prev.as_ref(), extent, Filter::Bilinear);
+        levels.push(next);
+    }
+
+    MipChain { levels }
+}
+
+/// Resamples a pixel buffer to a new size.
+///
+/// Filtering happens on linear, premultiplied colors so that resizing neither
+/// darkens sRGB images nor bleeds the color of transparent pixels into their
+/// neighbors.
+pub(crate) fn resize(
+    pixels: PixelBufferRef,
+    extent: Extent<u32, Px>,
+    filter: Filter,
+) -> PixelBuffer {
+    let format = pixels.format();
+    let color_space = pixels.color_space();
+    let alpha_mode = pixels.alpha_mode();
+
+    let mut out = PixelBuffer::new(extent.width, extent.height, format, color_space)
+        .with_alpha_mode(alpha_mode);
+
+    if extent.width == 0 || extent.height == 0 || pixels.width() == 0 || pixels.height() == 0 {
+        return out;
+    }
+
+    if filter == Filter::Nearest {
+        // No blending takes place, so skip the conversions entirely.
+        let bpp = format.bytes_per_pixel();
+        let src_rows: Vec<&[u8]> = pixels.rows().collect();
+        let xs = nearest_indices(pixels.width(), extent.width);
+        let ys = nearest_indices(pixels.height(), extent.height);
+
+        for (dst_row, &y) in out.rows_mut().zip(&ys) {
+            for (dst, &x) in dst_row.chunks_exact_mut(bpp).zip(&xs) {
+                dst.copy_from_slice(&src_rows[y][x * bpp..(x + 1) * bpp]);
+            }
+        }
+
+        return out;
+    }
+
+    let src_width = pixels.width() as usize;
+    let src = pixels
+        .rows()
+        .flat_map(|row| row.chunks_exact(format.bytes_per_pixel()))
+        .map(|px| {
+            let color = alpha_mode.convert(format.read_color(px), AlphaMode::Straight);
+            to_linear_premultiplied(color, color_space)
+        })
+        .collect::<Vec<_>>();
+
+    let horizontal = Kernel::new(pixels.width(), extent.width, filter);
+    let vertical = Kernel::new(pixels.height(), extent.height, filter);
+
+    // Horizontal pass: src_width x src_height -> dst_width x src_height
+    let dst_width = extent.width as usize;
+    let mut tmp = vec![[0.0; 4]; dst_width * pixels.height() as usize];
+    for (src_row, tmp_row) in src
+        .chunks_exact(src_width)
+        .zip(tmp.chunks_exact_mut(dst_width))
+    {
+        for (x, dst) in tmp_row.iter_mut().enumerate() {
+            *dst = horizontal.apply(x, |i| src_row[i]);
+        }
+    }
+
+    // Vertical pass: dst_width x src_height -> dst_width x dst_height
+    let bpp = format.bytes_per_pixel();
+    for (y, dst_row) in out.rows_mut().enumerate() {
+        for (x, dst) in dst_row.chunks_exact_mut(bpp).enumerate() {
+            let color = vertical.apply(y, |i| tmp[i * dst_width + x]);
+            let color = from_linear_premultiplied(color, color_space);
+            let (count, bytes) = format.write_color(AlphaMode::Straight.convert(color, alpha_mode));
+            dst.copy_from_slice(&bytes[..count as usize]);
+        }
+    }
+
+    out
+}
+
+fn nearest_indices(src: u32, dst: u32) -> Vec<usize> {
+    let scale = src as f32 / dst as f32;
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    (0..dst)
+        .map(|i| (((i as f32 + 0.5) * scale) as usize).min(src as usize - 1))
+        .collect()
+}
+
+/// Precomputed filter weights for resampling along one axis.
+struct Kernel {
+    /// (first source index, range into `weights`) for each destination pixel.
+    spans: Vec<(usize, std::ops::Range<usize>)>,
+    weights: Vec<f32>,
+}
+
+impl Kernel {
+    fn new(src: u32, dst: u32, filter: Filter) -> Self {
+        let scale = src as f32 / dst as f32;
+        // Widen the filter when downscaling so that every source pixel
+        // contributes to the output.
+        let filter_scale = scale.max(1.0);
+        let support = filter.support() * filter_scale;
+
+        let mut spans = Vec::with_capacity(dst as usize);
+        let mut weights = Vec::new();
+
+        for i in 0..dst {
+            let center = (i as f32 + 0.5) * scale;
+            #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+            let first = (center - support).floor().max(0.0) as usize;
+            #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+            let last = ((center + support).ceil() as usize).min(src as usize);
+
+            let start = weights.len();
+            let mut total = 0.0;
+            for j in first..last {
+                let w = filter.weight((j as f32 + 0.5 - center) / filter_scale);
+                weights.push(w);
+                total += w;
+            }
+
+            if total != 0.0 {
+                for w in &mut weights[start..] {
+                    *w /= total;
+                }
+            }
+
+            spans.push((first, start..weights.len()));
+        }
+
+        Self { spans, weights }
+    }
+
+    fn apply(&self, i: usize, sample: impl Fn(usize) -> [f32; 4]) -> [f32; 4] {
+        let (first, range) = &self.spans[i];
+        let mut acc = [0.0; 4];
+        for (j, w) in self.weights[range.clone()].iter().enumerate() {
+            let s = sample(first + j);
+            for c in 0..4 {
+                acc[c] += s[c] * w;
+            }
+        }
+        acc
+    }
+}
+
+fn to_linear_premultiplied(color: Color, color_space: ColorSpace) -> [f32; 4] {
+    match color_space {
+        ColorSpace::Srgb => {
+            let a = color.a;
+            [
+                srgb_to_linear(color.r) * a,
+                srgb_to_linear(color.g) * a,
+                srgb_to_linear(color.b) * a,
+                a,
+            ]
+        }
+    }
+}
+
+fn from_linear_premultiplied([r, g, b, a]: [f32; 4], color_space: ColorSpace) -> Color {
+    // Filters with negative lobes can overshoot; clamp before un-doing the
+    // premultiplication so that colors stay in gamut.
+    let a = a.clamp(0.0, 1.0);
+    if a == 0.0 {
+        return Color::new(0.0, 0.0, 0.0, 0.0);
+    }
+
+    let unpremultiply = |c: f32| (c / a).clamp(0.0, 1.0);
+
+    match color_space {
+        ColorSpace::Srgb => Color::new(
+            linear_to_srgb(unpremultiply(r)),
+            linear_to_srgb(unpremultiply(g)),
+            linear_to_srgb(unpremultiply(b)),
+            a,
+        ),
+    }
+}
+
+pub(crate) fn srgb_to_linear(c: f32) -> f32 {
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+pub(crate) fn linear_to_srgb(c: f32) -> f32 {
+    if c <= 0.003_130_8 {
+        c * 12.92
+    } else {
+        1.055 * c.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::graphics::PixelFormat;
+
+    fn solid(color: Color, width: u32, height: u32) -> PixelBuffer {
+        let colors = vec![color; (width * height) as usize];
+        PixelBuffer::from_colors(&colors, width, PixelFormat::Rgba8, ColorSpace::Srgb)
+    }
+
+    #[test]
+    fn solid_colors_are_preserved() {
+        let color = Color::new(0.2, 0.4, 0.6, 1.0);
+        let pixels = solid(color, 7, 5);
+
+        for filter in [
+            Filter::Nearest,
+            Filter::Bilinear,
+            Filter::Bicubic,
+            Filter::Lanczos3,
+        ] {
+            for extent in [Extent::new(3, 2), Extent::new(16, 9)] {
+                let resized = pixels.resize(extent, filter);
+                assert_eq!(resized.width(), extent.width);
+                assert_eq!(resized.height(), extent.height);
+                for px in resized.rows().flat_map(|row| row.chunks_exact(4)) {
+                    assert_eq!(px, &pixels.bytes()[0..4], "{filter:?} {extent:?}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn downscaling_is_gamma_correct() {
+        // A black and white checkerboard should average to 50% linear
+        // intensity, which is ~188 in sRGB rather than the naive 128.
+        let colors = [Color::BLACK, Color::WHITE, Color::WHITE, Color::BLACK];
+        let pixels = PixelBuffer::from_colors(&colors, 2, PixelFormat::Rgba8, ColorSpace::Srgb);
+
+        let resized = pixels.resize(Extent::new(1, 1), Filter::Bilinear);
+        assert_eq!(resized.bytes(), [188, 188, 188, 255]);
+    }
+
+    #[test]
+    fn transparent_pixels_do_not_bleed() {
+        let colors = [Color::RED, Color::new(0.0, 1.0, 0.0, 0.0)];
+        let pixels = PixelBuffer::from_colors(&colors, 2, PixelFormat::Rgba8, ColorSpace::Srgb);
+
+        let resized = pixels.resize(Extent::new(1, 1), Filter::Bilinear);
+        assert_eq!(resized.bytes(), [255, 0, 0, 128]);
+    }
+
+    #[test]
+    fn alpha_mode_is_preserved() {
+        let colors = [Color::new(1.0, 0.0, 0.0, 0.5); 4];
+        let mut pixels = PixelBuffer::from_colors(&colors, 2, PixelFormat::Rgba8, ColorSpace::Srgb);
+        pixels.premultiply();
+
+        let resized = pixels.resize(Extent::new(1, 1), Filter::Lanczos3);
+        assert_eq!(resized.alpha_mode(), AlphaMode::Premultiplied);
+        assert_eq!(resized.bytes(), &pixels.bytes()[0..4]);
+    }
+
+    #[test]
+    fn mip_chain() {
+        let pixels = solid(Color::WHITE, 10, 3);
+        let mips = pixels.generate_mips();
+
+        let extents: Vec<_> = mips
+            .levels()
+            .iter()
+            .map(|level| (level.width(), level.height()))
+            .collect();
+        assert_eq!(extents, [(10, 3), (5, 1), (2, 1), (1, 1)]);
+        assert_eq!(mips.num_levels(), 4);
+        assert_eq!(mips.levels()[3].bytes(), [255, 255, 255, 255]);
+    }
+}
@@ -0,0 +1,163 @@
+use std::collections::BTreeMap;
+
+struct Entry {
+    size: u64,
+    last_drawn: u64,
+    reloadable: bool,
+}
+
+/// Tracks how much memory images use, and picks images to evict when a memory
+/// budget would be exceeded.
+///
+/// Only images marked as reloadable are evicted, least recently drawn first.
+/// Images drawn in the current or previous frame are never evicted, so the
+/// budget may be exceeded while they are all in use.
+pub(crate) struct Residency<K> {
+    budget: Option<u64>,
+    used: u64,
+    entries: BTreeMap<K, Entry>,
+    frame: u64,
+}
+
+impl<K: Copy + Ord> Residency<K> {
+    pub fn new(budget: Option<u64>) -> Self {
+        Self {
+            budget,
+            used: 0,
+            entries: BTreeMap::new(),
+            frame: 0,
+        }
+    }
+
+    /// The number of bytes used by every tracked image.
+    pub fn used(&self) -> u64 {
+        self.used
+    }
+
+    /// Starts tracking an image. New images count as drawn this frame.
+    pub fn insert(&mut self, key: K, size: u64) {
+        let previous = self.entries.insert(
+            key,
+            Entry {
+                size,
+                last_drawn: self.frame,
+                reloadable: false,
+            },
+        );
+
+        debug_assert!(previous.is_none(), "image tracked twice");
+        self.used += size;
+    }
+
+    pub fn remove(&mut self, key: K) {
+        if let Some(entry) = self.entries.remove(&key) {
+            self.used -= entry.size;
+        }
+    }
+
+    pub fn set_reloadable(&mut self, key: K, reloadable: bool) {
+        if let Some(entry) = self.entries.get_mut(&key) {
+            entry.reloadable = reloadable;
+        }
+    }
+
+    pub fn mark_drawn(&mut self, key: K) {
+        if let Some(entry) = self.entries.get_mut(&key) {
+            entry.last_drawn = self.frame;
+        }
+    }
+
+    pub fn end_frame(&mut self) {
+        self.frame += 1;
+    }
+
+    /// Stops tracking as many images as needed for `incoming` more bytes to
+    /// fit within the budget, and returns them so that they can be destroyed.
+    pub fn evict(&mut self, incoming: u64) -> Vec<K> {
+        let Some(budget) = self.budget else {
+            return Vec::new();
+        };
+
+        if self.used + incoming <= budget {
+            return Vec::new();
+        }
+
+        let mut candidates = self
+            .entries
+            .iter()
+            .filter(|(_, entry)| entry.reloadable && entry.last_drawn + 1 < self.frame)
+            .map(|(key, entry)| (entry.last_drawn, *key))
+            .collect::<Vec<_>>();
+        candidates.sort_unstable();
+
+        let mut evicted = Vec::new();
+        for (_, key) in candidates {
+            if self.used + incoming <= budget {
+                break;
+            }
+
+            self.remove(key);
+            evicted.push(key);
+        }
+
+        evicted
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unlimited_budget() {
+        let mut residency = Residency::new(None);
+        residency.insert(0, 1000);
+        residency.set_reloadable(0, true);
+        residency.end_frame();
+        residency.end_frame();
+
+        assert!(residency.evict(u64::MAX / 2).is_empty());
+        assert_eq!(residency.used(), 1000);
+    }
+
+    #[test]
+    fn least_recently_drawn_first() {
+        let mut residency = Residency::new(Some(300));
+
+        for key in 0..3 {
+            residency.insert(key, 100);
+            residency.set_reloadable(key, true);
+        }
+
+        residency.end_frame();
+        residency.mark_drawn(0);
+        residency.end_frame();
+        residency.end_frame();
+
+        // 1 and 2 were last drawn before 0.
+        assert_eq!(residency.evict(100), [1]);
+        assert_eq!(residency.used(), 200);
+        assert_eq!(residency.evict(250), [2, 0]);
+        assert_eq!(residency.used(), 0);
+    }
+
+    #[test]
+    fn pinned_images() {
+        let mut residency = Residency::new(Some(200));
+        residency.insert(0, 100);
+        residency.insert(1, 100);
+        residency.set_reloadable(1, true);
+
+        // Images drawn last frame are kept, even over budget.
+        residency.end_frame();
+        assert!(residency.evict(100).is_empty());
+
+        // Images that aren't reloadable are never evicted.
+        residency.end_frame();
+        assert_eq!(residency.evict(200), [1]);
+        assert_eq!(residency.used(), 100);
+
+        residency.remove(0);
+        assert_eq!(residency.used(), 0);
+    }
+}
@@ -0,0 +1,344 @@
+//! A CPU implementation of the renderer, for drawing captured frames without a
+//! GPU or a window.
+//!
+//! It follows the GPU path closely (the same rounded-rect coverage, bilinear
+//! sampling, and premultiplied blending) but is not bit-exact with it.
+
+use geometry::{Extent, Point, Px, Rect};
+
+use super::{
+    compile::{Batch, CompiledGraph},
+    AlphaMode, Color, ColorSpace, DebugView, PixelBuffer, PixelFormat, RenderGraph,
+    RoundedRectVertex,
+};
+
+/// Draws a render graph whose images are indices into `images`, on a white
+/// background like the GPU renderer, visualized as `view` describes.
+///
+/// Panics if the graph refers to an image that isn't in `images`.
+#[must_use]
+pub fn render(
+    graph: &RenderGraph<u32>,
+    images: &[PixelBuffer],
+    extent: Extent<u32, Px>,
+    view: DebugView,
+) -> PixelBuffer {
+    let textures = images.iter().map(Texture::new).collect::<Vec<_>>();
+
+    let mut target = Target {
+        width: extent.width as usize,
+        height: extent.height as usize,
+        pixels: vec![[1.0; 4]; extent.width as usize * extent.height as usize],
+    };
+
+    let compiled = graph.compile_debug(&Rect::new(Point::zero(), extent.to_f32()), view);
+    for batch in &compiled.batches {
+        draw_batch(&mut target, &compiled, &textures, batch);
+    }
+
+    let format = PixelFormat::Rgba8;
+    let mut bytes = Vec::with_capacity(target.pixels.len() * format.bytes_per_pixel());
+    for [r, g, b, a] in target.pixels {
+        let (count, pixel) = format.write_color(Color { r, g, b, a });
+        bytes.extend_from_slice(&pixel[..count as usize]);
+    }
+
+    PixelBuffer::from_bytes(&bytes, extent.width, format, ColorSpace::Srgb)
+        .with_alpha_mode(AlphaMode::Premultiplied)
+}
+
+/// Premultiplied colors, so that blending and sampling match the GPU.
+struct Target {
+    width: usize,
+    height: usize,
+    pixels: Vec<[f32; 4]>,
+}
+
+struct Texture {
+    width: usize,
+    height: usize,
+    texels: Vec<[f32; 4]>,
+}
+
+impl Texture {
+    fn new(pixels: &PixelBuffer) -> Self {
+        let mut premultiplied = PixelBuffer::new(
+            pixels.width(),
+            pixels.height(),
+            PixelFormat::Rgba8,
+            pixels.color_space(),
+        )
+        .with_alpha_mode(AlphaMode::Premultiplied);
+
+        premultiplied.as_mut().copy_from(pixels.as_ref());
+
+        let texels = premultiplied
+            .bytes()
+            .chunks_exact(PixelFormat::Rgba8.bytes_per_pixel())
+            .map(|texel| {
+                let color = PixelFormat::Rgba8.read_color(texel);
+                [color.r, color.g, color.b, color.a]
+            })
+            .collect();
+
+        Self {
+            width: pixels.width() as usize,
+            height: pixels.height() as usize,
+            texels,
+        }
+    }
+
+    /// Bilinear sampling with clamped edges.
+    fn sample(&self, uv: Point<f32, Px>) -> [f32; 4] {
+        #[allow(clippy::cast_precision_loss)]
+        let x = (uv.x * self.width as f32 - 0.5).clamp(0.0, (self.width - 1) as f32);
+        #[allow(clippy::cast_precision_loss)]
+        let y = (uv.y * self.height as f32 - 0.5).clamp(0.0, (self.height - 1) as f32);
+
+        #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+        let (x0, y0) = (x.floor() as usize, y.floor() as usize);
+        let (x1, y1) = ((x0 + 1).min(self.width - 1), (y0 + 1).min(self.height - 1));
+        let (fx, fy) = (x.fract(), y.fract());
+
+        let texel = |x: usize, y: usize| self.texels[y * self.width + x];
+        let top = lerp(texel(x0, y0), texel(x1, y0), fx);
+        let bottom = lerp(texel(x0, y1), texel(x1, y1), fx);
+        lerp(top, bottom, fy)
+    }
+}
+
+fn draw_batch(
+    target: &mut Target,
+    compiled: &CompiledGraph<u32>,
+    textures: &[Texture],
+    batch: &Batch<u32>,
+) {
+    let texture = batch.image().map(|image| &textures[image as usize]);
+
+    match *batch {
+        Batch::Rects {
+            first_instance,
+            num_instances,
+            ..
+        } => {
+            let first = first_instance as usize;
+
+            for rect in &compiled.instances[first..first + num_instances as usize] {
+                let [a, b, c, d] = rect.to_vertices();
+                draw_triangle(target, [&a, &b, &c], texture);
+                draw_triangle(target, [&a, &c, &d], texture);
+            }
+        }
+        Batch::Mesh {
+            first_index,
+            num_indices,
+            ..
+        } => {
+            let first = first_index as usize;
+
+            for triangle in compiled.indices[first..first + num_indices as usize].chunks_exact(3) {
+                let vertices = [0, 1, 2].map(|i| &compiled.vertices[triangle[i] as usize]);
+                draw_triangle(target, vertices, texture);
+            }
+        }
+        Batch::DisplayList { .. } => unreachable!("display lists are inlined when capturing"),
+    }
+}
+
+/// Twice the signed area of the triangle `a`, `b`, `p`. Positive when `p` is
+/// to the right of the edge from `a` to `b` (with y pointing down).
+fn edge(a: Point<f32, Px>, b: Point<f32, Px>, p: Point<f32, Px>) -> f32 {
+    (b.x - a.x) * (p.y - a.y) - (b.y - a.y) * (p.x - a.x)
+}
+
+/// Pixels on an edge are only drawn if it is a top or left edge, so that
+/// triangles that share an edge don't both draw it.
+fn is_top_left(a: Point<f32, Px>, b: Point<f32, Px>) -> bool {
+    (a.y == b.y && b.x > a.x) || b.y < a.y
+}
+
+#[allow(
+    clippy::cast_possible_truncation,
+    clippy::cast_sign_loss,
+    clippy::cast_precision_loss
+)]
+fn draw_triangle(
+    target: &mut Target,
+    vertices: [&RoundedRectVertex; 3],
+    texture: Option<&Texture>,
+) {
+    let [mut v0, mut v1, v2] = vertices;
+    let mut area = edge(v0.position, v1.position, v2.position);
+    if area == 0.0 {
+        return;
+    }
+
+    if area < 0.0 {
+        std::mem::swap(&mut v0, &mut v1);
+        area = -area;
+    }
+
+    let (p0, p1, p2) = (v0.position, v1.position, v2.position);
+
+    let min_x = p0.x.min(p1.x).min(p2.x).floor().max(0.0) as usize;
+    let min_y = p0.y.min(p1.y).min(p2.y).floor().max(0.0) as usize;
+    let max_x = (p0.x.max(p1.x).max(p2.x).ceil().max(0.0) as usize).min(target.width);
+    let max_y = (p0.y.max(p1.y).max(p2.y).ceil().max(0.0) as usize).min(target.height);
+
+    let edges = [(p1, p2), (p2, p0), (p0, p1)];
+    let top_left = edges.map(|(a, b)| is_top_left(a, b));
+
+    for y in min_y..max_y {
+        for x in min_x..max_x {
+            let p = Point::new(x as f32 + 0.5, y as f32 + 0.5);
+            let weights = edges.map(|(a, b)| edge(a, b, p));
+
+            let inside = weights
+                .iter()
+                .zip(top_left)
+                .all(|(&w, top_left)| w > 0.0 || (w == 0.0 && top_left));
+
+            if !inside {
+                continue;
+            }
+
+            let [w0, w1, w2] = weights.map(|w| w / area);
+            let color = [
+                v0.color.r * w0 + v1.color.r * w1 + v2.color.r * w2,
+                v0.color.g * w0 + v1.color.g * w1 + v2.color.g * w2,
+                v0.color.b * w0 + v1.color.b * w1 + v2.color.b * w2,
+                v0.color.a * w0 + v1.color.a * w1 + v2.color.a * w2,
+            ];
+            let uv = Point::new(
+                v0.uv.x * w0 + v1.uv.x * w1 + v2.uv.x * w2,
+                v0.uv.y * w0 + v1.uv.y * w1 + v2.uv.y * w2,
+            );
+
+            let sample = texture.map_or([1.0; 4], |texture| texture.sample(uv));
+            let coverage = coverage(v0, p);
+
+            let alpha = color[3];
+            let src = [
+                color[0] * alpha * sample[0] * coverage,
+                color[1] * alpha * sample[1] * coverage,
+                color[2] * alpha * sample[2] * coverage,
+                alpha * sample[3] * coverage,
+            ];
+
+            let dst = &mut target.pixels[y * target.width + x];
+            for channel in 0..4 {
+                dst[channel] = src[channel] + dst[channel] * (1.0 - src[3]);
+            }
+        }
+    }
+}
+
+/// How much of the pixel at `p` is covered by the vertex's rounded rect. See
+/// `pixel_main` in the rounded rect shader.
+fn coverage(vertex: &RoundedRectVertex, p: Point<f32, Px>) -> f32 {
+    let x = p.x - vertex.rect_center.x;
+    let y = p.y - vertex.rect_center.y;
+
+    let [r0, r1, r2, r3] = vertex.outer_radii;
+    let (a, b) = if x > 0.0 { (r0, r1) } else { (r2, r3) };
+    let radius = if y > 0.0 { a } else { b };
+
+    let half_width = vertex.rect_size.width / 2.0;
+    let half_height = vertex.rect_size.height / 2.0;
+    let radius = radius.clamp(0.0, half_width.min(half_height));
+
+    let dx = x.abs() - half_width + radius;
+    let dy = y.abs() - half_height + radius;
+    let outside = dx.max(0.0).hypot(dy.max(0.0));
+    let inside = dx.max(dy).min(0.0);
+    let distance = inside + outside - radius;
+
+    // The distance changes by about a pixel per pixel, which is what the
+    // shader gets from `fwidth`.
+    let w = 0.5 * 1.1;
+    smoothstep(w, -w, distance)
+}
+
+/// HLSL's `smoothstep`, which also works with `min > max`.
+fn smoothstep(min: f32, max: f32, x: f32) -> f32 {
+    let t = ((x - min) / (max - min)).clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
+
+fn lerp(a: [f32; 4], b: [f32; 4], t: f32) -> [f32; 4] {
+    [0, 1, 2, 3].map(|i| a[i] + (b[i] - a[i]) * t)
+}
+
+#[cfg(test)]
+mod tests {
+    use geometry::Rect;
+
+    use super::*;
+    use crate::graphics::{DrawRect, RenderGraphNodeId};
+
+    fn pixel(buffer: &PixelBuffer, x: usize, y: usize) -> [u8; 4] {
+        let offset = (y * buffer.width() as usize + x) * 4;
+        buffer.bytes()[offset..offset + 4].try_into().unwrap()
+    }
+
+    fn render_rects(rects: &[DrawRect]) -> PixelBuffer {
+        let mut graph = RenderGraph::new();
+        for rect in rects {
+            graph.draw_rect(RenderGraphNodeId::root(), rect);
+        }
+
+        render(
+            &graph.map_images(|_| unreachable!()),
+            &[],
+            Extent::new(8, 8),
+            DebugView::Off,
+        )
+    }
+
+    #[test]
+    fn solid_rects() {
+        let rect = Rect::new(Point::new(2.0, 2.0), Extent::new(4.0, 4.0));
+        let buffer = render_rects(&[DrawRect::new(rect).with_color(Color::RED)]);
+
+        assert_eq!(pixel(&buffer, 0, 0), [255, 255, 255, 255]);
+        assert_eq!(pixel(&buffer, 3, 3), [255, 0, 0, 255]);
+        assert_eq!(pixel(&buffer, 7, 7), [255, 255, 255, 255]);
+    }
+
+    #[test]
+    fn no_seams() {
+        // Translucent, so that pixels drawn by both triangles would be darker.
+        let rect = Rect::new(Point::new(0.0, 0.0), Extent::new(8.0, 8.0));
+        let color = Color {
+            r: 0.0,
+            g: 0.0,
+            b: 0.0,
+            a: 0.5,
+        };
+        let buffer = render_rects(&[DrawRect::new(rect).with_color(color)]);
+
+        let expected = pixel(&buffer, 3, 2);
+        for i in 1..7 {
+            assert_eq!(pixel(&buffer, i, i), expected);
+        }
+    }
+
+    #[test]
+    fn bilinear_sampling() {
+        let image = PixelBuffer::from_colors(
+            &[Color::RED, Color::BLUE],
+            2,
+            PixelFormat::Rgba8,
+            ColorSpace::Srgb,
+        );
+        let texture = Texture::new(&image);
+
+        // Texel centers, and clamped edges.
+        assert_eq!(texture.sample(Point::new(0.25, 0.5)), [1.0, 0.0, 0.0, 1.0]);
+        assert_eq!(texture.sample(Point::new(0.0, 0.0)), [1.0, 0.0, 0.0, 1.0]);
+        assert_eq!(texture.sample(Point::new(1.0, 1.0)), [0.0, 0.0, 1.0, 1.0]);
+
+        // Halfway between the texels.
+        assert_eq!(texture.sample(Point::new(0.5, 0.5)), [0.5, 0.0, 0.5, 1.0]);
+    }
+}
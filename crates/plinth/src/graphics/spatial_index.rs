@@ -0,0 +1,266 @@
+//! Finds the nodes of a render graph that are inside a region without visiting
+//! the rest of the graph.
+//!
+//! Every subtree has conservative bounds, so subtrees outside the region are
+//! skipped entirely. Nodes with many children, like the lines of a long
+//! document, also group their children into a hierarchy of bounds, so that
+//! finding the few children on screen doesn't visit every child. Groups are
+//! made of consecutive siblings, which keeps the result in painting order.
+
+use std::collections::HashMap;
+
+use geometry::{Point, Px, Rect};
+
+use super::{RenderGraph, RenderGraphNodeId};
+
+/// How many children or groups go into each group.
+const GROUP_SIZE: usize = 16;
+
+pub(crate) struct SpatialIndex {
+    /// The bounds of each node and all of its descendants, by node index.
+    subtrees: Vec<Option<Rect<f32, Px>>>,
+    /// Only nodes with more than `GROUP_SIZE` children are grouped.
+    groups: HashMap<RenderGraphNodeId, Groups>,
+}
+
+struct Groups {
+    children: Vec<RenderGraphNodeId>,
+    /// `levels[0][i]` bounds `children[i * GROUP_SIZE..][..GROUP_SIZE]`, and
+    /// each level after that groups the level before it. The last level has
+    /// at most `GROUP_SIZE` groups.
+    levels: Vec<Vec<Option<Rect<f32, Px>>>>,
+}
+
+enum Visit {
+    Node(RenderGraphNodeId),
+    Group {
+        parent: RenderGraphNodeId,
+        level: usize,
+        index: usize,
+    },
+}
+
+impl SpatialIndex {
+    pub fn new<I>(graph: &RenderGraph<I>) -> Self {
+        // Children are always added after their parents, so going backwards
+        // visits every child before its parent.
+        let mut subtrees: Vec<Option<Rect<f32, Px>>> = vec![None; graph.num_nodes()];
+        let mut groups = HashMap::new();
+
+        for index in (0..graph.num_nodes()).rev() {
+            let node = RenderGraphNodeId::from_index(index);
+
+            let mut bounds = graph.bounds(node);
+            let mut num_children = 0;
+            for child in graph.iter_children(node) {
+                bounds = union(bounds, subtrees[child.index()]);
+                num_children += 1;
+            }
+
+            subtrees[index] = bounds;
+
+            if num_children > GROUP_SIZE {
+                let children = graph.iter_children(node).collect::<Vec<_>>();
+                let mut levels = vec![group(children.iter().map(|c| subtrees[c.index()]))];
+
+                while levels[levels.len() - 1].len() > GROUP_SIZE {
+                    let next = group(levels[levels.len() - 1].iter().copied());
+                    levels.push(next);
+                }
+
+                groups.insert(node, Groups { children, levels });
+            }
+        }
+
+        Self { subtrees, groups }
+    }
+
+    /// The bounds of the node and all of its descendants.
+    pub fn bounds(&self, node: RenderGraphNodeId) -> Option<Rect<f32, Px>> {
+        self.subtrees[node.index()]
+    }
+
+    /// Every node whose own draw overlaps `region`, in painting order.
+    pub fn visible<I>(
+        &self,
+        graph: &RenderGraph<I>,
+        region: &Rect<f32, Px>,
+    ) -> Vec<RenderGraphNodeId> {
+        self.find(graph, |bounds| bounds.intersects(region))
+    }
+
+    /// Every node whose own draw covers `point`, including on its edges, in
+    /// painting order.
+    pub fn at_point<I>(
+        &self,
+        graph: &RenderGraph<I>,
+        point: Point<f32, Px>,
+    ) -> Vec<RenderGraphNodeId> {
+        self.find(graph, |bounds| {
+            (bounds.left()..=bounds.right()).contains(&point.x)
+                && (bounds.top()..=bounds.bottom()).contains(&point.y)
+        })
+    }
+
+    /// Every node whose own bounds pass `test`, in painting order. Subtrees
+    /// and groups are only visited if their bounds pass it too.
+    fn find<I>(
+        &self,
+        graph: &RenderGraph<I>,
+        test: impl Fn(&Rect<f32, Px>) -> bool,
+    ) -> Vec<RenderGraphNodeId> {
+        let overlaps = |bounds: Option<Rect<f32, Px>>| bounds.is_some_and(|b| test(&b));
+
+        let mut visible = Vec::new();
+
+        // A stack instead of recursion, since graphs can be very deep. Work is
+        // pushed in reverse so that it is popped in painting order.
+        let mut stack = vec![Visit::Node(RenderGraphNodeId::root())];
+
+        while let Some(visit) = stack.pop() {
+            let start = stack.len();
+
+            match visit {
+                Visit::Node(node) => {
+                    if !overlaps(self.subtrees[node.index()]) {
+                        continue;
+                    }
+
+                    if overlaps(graph.bounds(node)) {
+                        visible.push(node);
+                    }
+
+                    if let Some(groups) = self.groups.get(&node) {
+                        let level = groups.levels.len() - 1;
+                        stack.extend((0..groups.levels[level].len()).map(|index| Visit::Group {
+                            parent: node,
+                            level,
+                            index,
+                        }));
+                    } else {
+                        stack.extend(graph.iter_children(node).map(Visit::Node));
+                    }
+                }
+                Visit::Group {
+                    parent,
+                    level,
+                    index,
+                } => {
+                    let groups = &self.groups[&parent];
+                    if !overlaps(groups.levels[level][index]) {
+                        continue;
+                    }
+
+                    let first = index * GROUP_SIZE;
+                    if level == 0 {
+                        let end = (first + GROUP_SIZE).min(groups.children.len());
+                        stack.extend(groups.children[first..end].iter().copied().map(Visit::Node));
+                    } else {
+                        let end = (first + GROUP_SIZE).min(groups.levels[level - 1].len());
+                        stack.extend((first..end).map(|index| Visit::Group {
+                            parent,
+                            level: level - 1,
+                            index,
+                        }));
+                    }
+                }
+            }
+
+            stack[start..].reverse();
+        }
+
+        visible
+    }
+}
+
+fn group(bounds: impl Iterator<Item = Option<Rect<f32, Px>>>) -> Vec<Option<Rect<f32, Px>>> {
+    let bounds = bounds.collect::<Vec<_>>();
+    bounds
+        .chunks(GROUP_SIZE)
+        .map(|chunk| chunk.iter().copied().reduce(union).flatten())
+        .collect()
+}
+
+fn union(a: Option<Rect<f32, Px>>, b: Option<Rect<f32, Px>>) -> Option<Rect<f32, Px>> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(a.union(&b)),
+        (a, b) => a.or(b),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use geometry::Extent;
+
+    use crate::graphics::DrawRect;
+
+    use super::*;
+
+    fn rect(x: f32, y: f32, width: f32, height: f32) -> Rect<f32, Px> {
+        Rect::new(Point::new(x, y), Extent::new(width, height))
+    }
+
+    /// The first rect drawn by each node.
+    fn lefts_and_tops(graph: &RenderGraph, nodes: &[RenderGraphNodeId]) -> Vec<(f32, f32)> {
+        nodes
+            .iter()
+            .map(|node| {
+                let bounds = graph.bounds(*node).unwrap();
+                (bounds.left(), bounds.top())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn skips_subtrees_outside_region() {
+        let mut graph = RenderGraph::new();
+        graph.draw_rect(
+            RenderGraphNodeId::root(),
+            &DrawRect::new(rect(0.0, 0.0, 10.0, 10.0)),
+        );
+        graph.draw_rect(
+            RenderGraphNodeId::root(),
+            &DrawRect::new(rect(100.0, 0.0, 10.0, 10.0)),
+        );
+
+        // The child is inside the region even though its parent isn't.
+        let parent = graph
+            .iter_children(RenderGraphNodeId::root())
+            .nth(1)
+            .unwrap();
+        graph.draw_rect(parent, &DrawRect::new(rect(5.0, 5.0, 10.0, 10.0)));
+
+        let index = SpatialIndex::new(&graph);
+        let visible = index.visible(&graph, &rect(0.0, 0.0, 50.0, 50.0));
+        assert_eq!(lefts_and_tops(&graph, &visible), [(0.0, 0.0), (5.0, 5.0)]);
+
+        assert!(index
+            .visible(&graph, &rect(0.0, 20.0, 50.0, 50.0))
+            .is_empty());
+    }
+
+    #[test]
+    fn long_document() {
+        // Enough lines for three levels of groups.
+        let mut graph = RenderGraph::new();
+        for i in 0..5000 {
+            #[allow(clippy::cast_precision_loss)]
+            let line = rect(0.0, i as f32 * 20.0, 500.0, 16.0);
+            graph.draw_rect(RenderGraphNodeId::root(), &DrawRect::new(line));
+        }
+
+        let index = SpatialIndex::new(&graph);
+        assert_eq!(index.groups[&RenderGraphNodeId::root()].levels.len(), 3);
+
+        let visible = index.visible(&graph, &rect(0.0, 30_010.0, 500.0, 60.0));
+        assert_eq!(
+            lefts_and_tops(&graph, &visible),
+            [
+                (0.0, 30_000.0),
+                (0.0, 30_020.0),
+                (0.0, 30_040.0),
+                (0.0, 30_060.0)
+            ]
+        );
+    }
+}
@@ -62,6 +62,7 @@ impl Plinth {
             platform: platform::Platform::new(&GraphicsConfig {
                 debug_mode: config.debug_mode,
                 power_preference: config.power_preference,
+                ..Default::default()
             }),
         }
     }
@@ -139,6 +139,13 @@ pub struct FrameMarker {
     start: u64,
 }
 
+impl FrameMarker {
+    /// The number of bytes allocated during the frame, including padding.
+    pub fn size(&self) -> u64 {
+        self.end - self.start
+    }
+}
+
 impl PartialEq for FrameMarker {
     fn eq(&self, other: &Self) -> bool {
         self.start == other.start
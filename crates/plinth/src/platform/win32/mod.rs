@@ -1,6 +1,6 @@
-use std::{cell::Cell, ptr::NonNull};
+use std::{cell::Cell, collections::HashMap, ptr::NonNull, sync::Weak, time::Instant};
 
-use geometry::{Extent, ScreenPx};
+use geometry::{Extent, Offset, Point, Px, Rect, ScreenPx};
 use raw_window_handle::RawWindowHandle;
 
 use windows::{core::Interface, w, Win32::Graphics::Direct3D::D3D_PRIMITIVE_TOPOLOGY};
@@ -16,8 +16,11 @@ use windows::{
 
 use crate::{
     graphics::{
-        Color, ColorSpace, GraphicsConfig, PixelBuffer, PixelBufferRef, PixelFormat, RenderGraph,
-        RenderGraphCommand, RenderGraphNodeId, RoundedRectVertex,
+        compile::{Batch, CompiledGraph},
+        display_list::DisplayListData,
+        AlphaMode, Color, ColorSpace, DisplayList, FrameStats, GraphicsConfig,
+        Image as GraphicsImage, Images, PixelBuffer, PixelBufferMut, PixelBufferRef, PixelFormat,
+        RectInstance, RoundedRectVertex,
     },
     memory::{
         block_allocator::BlockAllocator,
@@ -41,11 +44,21 @@ pub struct Platform {
     white_pixel: Image,
 
     round_rect_shader: Shader<ShaderConstants>,
+    rect_instance_shader: Shader<ShaderConstants>,
 
     upload_buffer: ID3D12Resource,
     upload_allocator: temp_allocator::Allocator,
 
     descriptor_heap: DescriptorHeap,
+
+    /// Destroyed images that may still be in use by the GPU.
+    retired_images: Vec<Image>,
+
+    /// The vertex data of display lists, by list id.
+    retained_lists: HashMap<u64, RetainedList>,
+
+    /// Whether surfaces keep their contents between frames.
+    partial_redraws: bool,
 }
 
 impl Platform {
@@ -58,6 +71,7 @@ impl Platform {
         let mut graphics_queue = queue::Graphics::new(&dx);
 
         let round_rect_shader = create_rounded_rect_shader(&dx);
+        let rect_instance_shader = create_rect_instance_shader(&dx);
 
         let upload_buffer = create_buffer(
             &dx,
@@ -102,7 +116,7 @@ impl Platform {
                 &upload_buffer,
                 &mut mem,
                 &mut descriptor_heap,
-                pixels.as_ref(),
+                &[pixels.as_ref()],
             );
 
             let submit = graphics_queue.submit(rec, mem.finish());
@@ -126,6 +140,14 @@ impl Platform {
                     .root_signature
                     .SetName(w!("Round Rect Root Signature"))
                     .unwrap();
+                rect_instance_shader
+                    .pipeline_state
+                    .SetName(w!("Rect Instance Shader"))
+                    .unwrap();
+                rect_instance_shader
+                    .root_signature
+                    .SetName(w!("Rect Instance Root Signature"))
+                    .unwrap();
                 white_pixel.resource.SetName(w!("White Pixel")).unwrap();
             }
         }
@@ -135,17 +157,24 @@ impl Platform {
             graphics_queue,
             white_pixel,
             round_rect_shader,
+            rect_instance_shader,
             upload_buffer,
             upload_allocator,
             descriptor_heap,
+            retired_images: Vec::new(),
+            retained_lists: HashMap::new(),
+            partial_redraws: config.partial_redraws,
         }
     }
 
     pub fn create_surface(&self, window: RawWindowHandle) -> Surface {
         match window {
-            RawWindowHandle::Win32(handle) => {
-                Surface::new(&self.dx, &self.graphics_queue.queue, HWND(handle.hwnd as _))
-            }
+            RawWindowHandle::Win32(handle) => Surface::new(
+                &self.dx,
+                &self.graphics_queue.queue,
+                HWND(handle.hwnd as _),
+                self.partial_redraws,
+            ),
             _ => unimplemented!(),
         }
     }
@@ -156,8 +185,8 @@ impl Platform {
     }
 
     pub fn get_next_image<'a>(&self, surface: &'a mut Surface) -> RenderTarget<'a> {
-        let image = surface.get_next_image();
-        RenderTarget { image }
+        let (image, damage) = surface.get_next_image();
+        RenderTarget { image, damage }
     }
 
     pub fn present(&self, surface: &mut Surface) {
@@ -169,38 +198,96 @@ impl Platform {
         surface.resize(&self.dx);
     }
 
-    pub fn draw(&mut self, target: &RenderTarget, content: &RenderGraph) {
-        let target = target.image;
+    /// Finds the region of the target to redraw, which is the part inside the
+    /// target that changed since the last frame (or all of it if `damage` is
+    /// `None`), plus anything the target missed in earlier frames.
+    ///
+    /// This must be called exactly once before drawing to the target.
+    pub fn redraw_region(
+        &self,
+        target: &RenderTarget,
+        damage: Option<Rect<f32, Px>>,
+    ) -> Rect<f32, Px> {
+        let extent = target.image.extent();
+
+        // Round outward so that partially covered pixels are redrawn too.
+        let damage = damage.map(|rect| RECT {
+            left: rect.left().floor() as i32,
+            top: rect.top().floor() as i32,
+            right: rect.right().ceil() as i32,
+            bottom: rect.bottom().ceil() as i32,
+        });
+
+        let region = target.damage.redraw(damage, extent.width, extent.height);
+
+        Rect::from_points(
+            Point::new(region.left as f32, region.top as f32),
+            Point::new(region.right as f32, region.bottom as f32),
+        )
+    }
+
+    /// Draws the graph to the target, only touching pixels in `region` (from
+    /// [`Self::redraw_region`]).
+    pub fn draw(
+        &mut self,
+        target: &RenderTarget,
+        content: &CompiledGraph<GraphicsImage>,
+        images: &Images,
+        region: Rect<f32, Px>,
+    ) -> FrameStats {
+        let RenderTarget { image: target, .. } = target;
 
         let (rec, old_marker) = self.graphics_queue.record(&self.dx);
         if let Some(old_marker) = old_marker {
             self.upload_allocator.free_frame(old_marker);
         }
 
+        // Started once the command list is ready, so that any time spent
+        // waiting for the GPU to give it back isn't counted.
+        let start = Instant::now();
+
+        self.free_completed();
+        self.retain_display_lists(&content.display_lists);
+
         let mut frame_alloc = self.upload_allocator.begin_frame();
 
-        let (imm_index_view, imm_rect_view) = {
+        let (imm_index_view, imm_rect_view, imm_instance_view) = {
             let upload_address = unsafe { self.upload_buffer.GetGPUVirtualAddress() };
 
-            let index_memory = frame_alloc.upload(&content.imm_indices).unwrap();
+            let index_memory = frame_alloc.upload(&content.indices).unwrap();
             let index_view = D3D12_INDEX_BUFFER_VIEW {
                 BufferLocation: upload_address + index_memory.heap_offset,
                 SizeInBytes: index_memory.size as u32,
-                Format: DXGI_FORMAT_R16_UINT,
+                Format: DXGI_FORMAT_R32_UINT,
             };
 
-            let rect_memory = frame_alloc.upload(&content.imm_rect_vertices).unwrap();
+            let rect_memory = frame_alloc.upload(&content.vertices).unwrap();
             let rect_view = D3D12_VERTEX_BUFFER_VIEW {
                 BufferLocation: upload_address + rect_memory.heap_offset,
                 SizeInBytes: rect_memory.size as u32,
                 StrideInBytes: std::mem::size_of::<RoundedRectVertex>() as u32,
             };
 
-            (index_view, rect_view)
+            let instance_memory = frame_alloc.upload(&content.instances).unwrap();
+            let instance_view = D3D12_VERTEX_BUFFER_VIEW {
+                BufferLocation: upload_address + instance_memory.heap_offset,
+                SizeInBytes: instance_memory.size as u32,
+                StrideInBytes: std::mem::size_of::<RectInstance>() as u32,
+            };
+
+            (index_view, rect_view, instance_view)
         };
 
         let frame_marker = frame_alloc.finish();
 
+        let mut stats = FrameStats {
+            vertices_uploaded: content.vertices.len(),
+            indices_uploaded: content.indices.len(),
+            instances_uploaded: content.instances.len(),
+            upload_bytes: frame_marker.size(),
+            ..Default::default()
+        };
+
         unsafe {
             rec.commands.ResourceBarrier(&[transition_barrier(
                 &target.resource,
@@ -217,15 +304,27 @@ impl Platform {
             rec.commands
                 .OMSetRenderTargets(1, Some(&target.rtv.cpu), false, None);
 
-            rec.commands
-                .ClearRenderTargetView(target.rtv.cpu, [1.0, 1.0, 1.0, 1.0].as_ptr(), &[]);
-
             let target_desc = target.resource.GetDesc();
 
             let constants = ShaderConstants {
                 viewport: Extent::new(target_desc.Width as u32, target_desc.Height),
+                offset: Offset::zero(),
+            };
+
+            // The region is already in whole pixels.
+            let scissor = RECT {
+                left: region.left() as i32,
+                top: region.top() as i32,
+                right: region.right() as i32,
+                bottom: region.bottom() as i32,
             };
 
+            rec.commands.ClearRenderTargetView(
+                target.rtv.cpu,
+                [1.0, 1.0, 1.0, 1.0].as_ptr(),
+                &[scissor],
+            );
+
             rec.commands.RSSetViewports(&[D3D12_VIEWPORT {
                 TopLeftX: 0.0,
                 TopLeftY: 0.0,
@@ -235,27 +334,21 @@ impl Platform {
                 MaxDepth: 1.0,
             }]);
 
-            rec.commands.RSSetScissorRects(&[RECT {
-                left: 0,
-                top: 0,
-                right: constants.viewport.width.try_into().unwrap(),
-                bottom: constants.viewport.height.try_into().unwrap(),
-            }]);
+            rec.commands.RSSetScissorRects(&[scissor]);
+
+            rec.commands
+                .SetDescriptorHeaps(std::slice::from_ref(&self.descriptor_heap.heap));
 
             let render_data = RenderData {
                 constants,
                 white_pixel: &self.white_pixel,
-                descriptor_heap: &self.descriptor_heap,
+                images,
                 index_buffer: imm_index_view,
                 rect_vertex_buffer: imm_rect_view,
+                rect_instance_buffer: imm_instance_view,
             };
 
-            self.record_render_graph(
-                &rec.commands,
-                content,
-                RenderGraphNodeId::root(),
-                &render_data,
-            );
+            self.record_batches(&rec.commands, content, &render_data, &mut stats);
 
             rec.commands.ResourceBarrier(&[transition_barrier(
                 &target.resource,
@@ -271,9 +364,92 @@ impl Platform {
         let fence_value = self.graphics_queue.submit(rec, frame_marker);
 
         target.last_use.set(fence_value);
+
+        for image in content.batches.iter().filter_map(Batch::image) {
+            let (texture, _) = images.get(image).expect("image validated before drawing");
+            texture.last_use.set(fence_value);
+        }
+
+        self.mark_lists_used(&content.display_lists, images, fence_value);
+
+        stats.record_time = start.elapsed();
+        stats
+    }
+
+    /// Uploads the vertex data of display lists (and the lists inside them)
+    /// that haven't been drawn before.
+    fn retain_display_lists(&mut self, lists: &[(DisplayList, Offset<f32, Px>)]) {
+        for (list, _) in lists {
+            let data = list.data();
+            if !self.retained_lists.contains_key(&data.id) {
+                self.retained_lists
+                    .insert(data.id, RetainedList::new(&self.dx, list));
+                self.retain_display_lists(&data.compiled.display_lists);
+            }
+        }
     }
 
-    pub fn upload_image(&mut self, pixels: PixelBufferRef) -> Image {
+    fn mark_lists_used(
+        &self,
+        lists: &[(DisplayList, Offset<f32, Px>)],
+        images: &Images,
+        fence_value: SubmissionId,
+    ) {
+        for (list, _) in lists {
+            let data = list.data();
+            self.retained_lists[&data.id].last_use.set(fence_value);
+
+            // This includes the images of the lists inside it.
+            for image in &data.images {
+                let (texture, _) = images.get(*image).expect("image validated before drawing");
+                texture.last_use.set(fence_value);
+            }
+
+            self.mark_lists_used(&data.compiled.display_lists, images, fence_value);
+        }
+    }
+
+    /// Frees destroyed images and dropped display lists that the GPU has
+    /// finished using. Called from every entry point that uses the queue, so
+    /// that memory is freed even if nothing is being drawn.
+    fn free_completed(&mut self) {
+        self.free_retired_images();
+        self.free_dropped_lists();
+    }
+
+    /// Frees the vertex data of dropped display lists once the GPU has
+    /// finished using it.
+    fn free_dropped_lists(&mut self) {
+        let queue = &self.graphics_queue;
+        self.retained_lists.retain(|_, retained| {
+            retained.list.strong_count() > 0 || !queue.is_complete(retained.last_use.get())
+        });
+    }
+
+    /// Destroys an image once the GPU has finished using it.
+    pub fn destroy_image(&mut self, image: Image) {
+        self.retired_images.push(image);
+        self.free_completed();
+    }
+
+    fn free_retired_images(&mut self) {
+        let mut i = 0;
+        while i < self.retired_images.len() {
+            if self
+                .graphics_queue
+                .is_complete(self.retired_images[i].last_use.get())
+            {
+                let image = self.retired_images.swap_remove(i);
+                self.descriptor_heap.free(image.srv);
+            } else {
+                i += 1;
+            }
+        }
+    }
+
+    pub fn upload_image(&mut self, levels: &[PixelBufferRef]) -> Image {
+        self.free_completed();
+
         let (rec, old_marker) = self.graphics_queue.record(&self.dx);
 
         // Make sure to free old memory before we try to allocate more.
@@ -289,7 +465,7 @@ impl Platform {
             &self.upload_buffer,
             &mut alloc,
             &mut self.descriptor_heap,
-            pixels,
+            levels,
         );
 
         let submission_id = self.graphics_queue.submit(rec, alloc.finish());
@@ -299,46 +475,301 @@ impl Platform {
         image
     }
 
-    fn record_render_graph(
+    /// Creates an image with undefined contents, to be filled in with
+    /// [`Self::copy_to_image`].
+    pub fn create_image(&mut self, width: u32, height: u32, format: PixelFormat) -> Image {
+        self.free_completed();
+
+        create_texture(
+            &self.dx,
+            &mut self.descriptor_heap,
+            Extent::new(width, height),
+            1,
+            dxgi_format(format),
+            D3D12_RESOURCE_STATE_PIXEL_SHADER_RESOURCE,
+            AlphaMode::Premultiplied,
+        )
+    }
+
+    /// Copies pixels into a region of an image's most detailed mip level, with
+    /// the top-left corner of the pixels at `at`. The pixels are converted to
+    /// the image's format and alpha mode if necessary.
+    pub fn copy_to_image(&mut self, image: &Image, at: Point<u32, Px>, pixels: PixelBufferRef) {
+        self.free_completed();
+
+        let format = pixel_format(unsafe { image.resource.GetDesc() }.Format)
+            .expect("image does not have a pixel buffer format");
+
+        let (rec, old_marker) = self.graphics_queue.record(&self.dx);
+
+        if let Some(old_marker) = old_marker {
+            self.upload_allocator.free_frame(old_marker);
+        }
+
+        let mut alloc = self.upload_allocator.begin_frame();
+
+        unsafe {
+            rec.commands.ResourceBarrier(&[transition_barrier(
+                &image.resource,
+                D3D12_RESOURCE_STATE_PIXEL_SHADER_RESOURCE,
+                D3D12_RESOURCE_STATE_COPY_DEST,
+            )]);
+        }
+
+        copy_pixels_to_texture(
+            &rec.commands,
+            &self.upload_buffer,
+            &mut alloc,
+            &image.resource,
+            0,
+            (at.x, at.y),
+            format,
+            image.alpha_mode,
+            pixels,
+        );
+
+        unsafe {
+            rec.commands.ResourceBarrier(&[transition_barrier(
+                &image.resource,
+                D3D12_RESOURCE_STATE_COPY_DEST,
+                D3D12_RESOURCE_STATE_PIXEL_SHADER_RESOURCE,
+            )]);
+        }
+
+        let submission_id = self.graphics_queue.submit(rec, alloc.finish());
+
+        image.last_use.set(submission_id);
+    }
+
+    /// Copies the most detailed mip level of an image back into memory,
+    /// blocking until the GPU has finished the copy.
+    pub fn read_image(&mut self, image: &Image) -> PixelBuffer {
+        self.free_completed();
+
+        let desc = unsafe { image.resource.GetDesc() };
+        let format = pixel_format(desc.Format).expect("image does not have a pixel buffer format");
+        let (width, height) = (desc.Width as u32, desc.Height);
+
+        let footprint = D3D12_SUBRESOURCE_FOOTPRINT {
+            Format: desc.Format,
+            Width: width,
+            Height: height,
+            Depth: 1,
+            RowPitch: next_multiple_of_u32(
+                width * (format.bytes_per_pixel() as u32),
+                D3D12_TEXTURE_DATA_PITCH_ALIGNMENT,
+            ),
+        };
+
+        let size = u64::from(footprint.RowPitch) * u64::from(height);
+        let readback = create_buffer(
+            &self.dx,
+            D3D12_HEAP_TYPE_READBACK,
+            size,
+            D3D12_RESOURCE_STATE_COPY_DEST,
+        );
+
+        let (rec, old_marker) = self.graphics_queue.record(&self.dx);
+
+        if let Some(old_marker) = old_marker {
+            self.upload_allocator.free_frame(old_marker);
+        }
+
+        unsafe {
+            rec.commands.ResourceBarrier(&[transition_barrier(
+                &image.resource,
+                D3D12_RESOURCE_STATE_PIXEL_SHADER_RESOURCE,
+                D3D12_RESOURCE_STATE_COPY_SOURCE,
+            )]);
+
+            rec.commands.CopyTextureRegion(
+                &D3D12_TEXTURE_COPY_LOCATION {
+                    pResource: windows::core::ManuallyDrop::new(&readback),
+                    Type: D3D12_TEXTURE_COPY_TYPE_PLACED_FOOTPRINT,
+                    Anonymous: D3D12_TEXTURE_COPY_LOCATION_0 {
+                        PlacedFootprint: D3D12_PLACED_SUBRESOURCE_FOOTPRINT {
+                            Offset: 0,
+                            Footprint: footprint,
+                        },
+                    },
+                },
+                0,
+                0,
+                0,
+                &D3D12_TEXTURE_COPY_LOCATION {
+                    pResource: windows::core::ManuallyDrop::new(&image.resource),
+                    Type: D3D12_TEXTURE_COPY_TYPE_SUBRESOURCE_INDEX,
+                    Anonymous: D3D12_TEXTURE_COPY_LOCATION_0 {
+                        SubresourceIndex: 0,
+                    },
+                },
+                None,
+            );
+
+            rec.commands.ResourceBarrier(&[transition_barrier(
+                &image.resource,
+                D3D12_RESOURCE_STATE_COPY_SOURCE,
+                D3D12_RESOURCE_STATE_PIXEL_SHADER_RESOURCE,
+            )]);
+        }
+
+        let alloc = self.upload_allocator.begin_frame();
+        let submission_id = self.graphics_queue.submit(rec, alloc.finish());
+        image.last_use.set(submission_id);
+        self.graphics_queue.wait_until(submission_id);
+
+        let mut pixels = PixelBuffer::new(width, height, format, ColorSpace::Srgb)
+            .with_alpha_mode(image.alpha_mode);
+
+        unsafe {
+            let mut ptr = std::ptr::null_mut();
+            readback
+                .Map(
+                    0,
+                    Some(&D3D12_RANGE {
+                        Begin: 0,
+                        End: size as usize,
+                    }),
+                    Some(&mut ptr),
+                )
+                .unwrap();
+
+            let bytes = std::slice::from_raw_parts(ptr.cast::<u8>(), size as usize);
+            pixels.as_mut().copy_from(
+                PixelBufferRef::from_bytes_with_pitch(
+                    bytes,
+                    width,
+                    height,
+                    footprint.RowPitch as usize,
+                    format,
+                    ColorSpace::Srgb,
+                )
+                .with_alpha_mode(image.alpha_mode),
+            );
+
+            readback.Unmap(0, Some(&D3D12_RANGE { Begin: 0, End: 0 }));
+        }
+
+        pixels
+    }
+
+    fn record_batches(
         &self,
         command_list: &ID3D12GraphicsCommandList,
-        content: &RenderGraph,
-        node_id: RenderGraphNodeId,
+        content: &CompiledGraph<GraphicsImage>,
         data: &RenderData,
+        stats: &mut FrameStats,
     ) {
-        'draw: {
-            let (first_index, num_indices) = match content.get(node_id) {
-                RenderGraphCommand::Root => {
-                    assert_eq!(node_id, RenderGraphNodeId::root());
-                    break 'draw;
+        let mut bound = None;
+        // Binding a pipeline resets the bound texture, along with the rest of
+        // the root arguments.
+        let mut bound_texture = None;
+
+        for batch in &content.batches {
+            let (draw, image) = match *batch {
+                Batch::DisplayList { index } => {
+                    let (list, offset) = &content.display_lists[index as usize];
+                    let retained = &self.retained_lists[&list.data().id];
+
+                    let (index_buffer, rect_vertex_buffer, rect_instance_buffer) = retained.views();
+
+                    let list_data = RenderData {
+                        constants: ShaderConstants {
+                            offset: data.constants.offset + *offset,
+                            ..data.constants
+                        },
+                        index_buffer,
+                        rect_vertex_buffer,
+                        rect_instance_buffer,
+                        ..*data
+                    };
+
+                    self.record_batches(command_list, &list.data().compiled, &list_data, stats);
+
+                    // The list bound its own buffers and constants.
+                    bound = None;
+                    bound_texture = None;
+                    continue;
                 }
-                RenderGraphCommand::DrawRect {
+                Batch::Rects {
+                    first_instance,
+                    num_instances,
+                    image,
+                } => (Draw::Rects(first_instance, num_instances), image),
+                Batch::Mesh {
                     first_index,
                     num_indices,
-                } => {
+                    image,
+                } => (Draw::Mesh(first_index, num_indices), image),
+            };
+
+            let (texture, uv_rect) = image.map_or(
+                (
+                    data.white_pixel,
+                    Rect::new(Point::zero(), Extent::new(1.0, 1.0)),
+                ),
+                |image| {
+                    data.images
+                        .get(image)
+                        .expect("image validated before drawing")
+                },
+            );
+
+            // Batches alternate between images more often than pipelines, so
+            // only switch pipelines when needed.
+            let is_rects = matches!(draw, Draw::Rects(..));
+            if bound != Some(is_rects) {
+                if is_rects {
+                    self.rect_instance_shader.bind(
+                        command_list,
+                        &data.constants,
+                        &data.rect_instance_buffer,
+                        None,
+                    );
+                } else {
                     self.round_rect_shader.bind(
                         command_list,
                         &data.constants,
                         &data.rect_vertex_buffer,
-                        &data.index_buffer,
+                        Some(&data.index_buffer),
                     );
-                    (*first_index, *num_indices)
                 }
-            };
+                bound = Some(is_rects);
+                bound_texture = None;
+            }
+
+            // Atlas images share a texture, so consecutive batches often do.
+            if bound_texture != Some(texture.srv.gpu.ptr) {
+                unsafe { command_list.SetGraphicsRootDescriptorTable(1, texture.srv.gpu) };
+                bound_texture = Some(texture.srv.gpu.ptr);
+                stats.textures_bound += 1;
+            }
 
             unsafe {
-                command_list.DrawIndexedInstanced(
-                    u32::from(num_indices),
-                    1,
-                    u32::from(first_index),
-                    0,
-                    0,
-                );
+                // Maps the rect's texture coordinates to the image's region of
+                // the texture.
+                let uv_transform = [
+                    uv_rect.left(),
+                    uv_rect.top(),
+                    uv_rect.extent().width,
+                    uv_rect.extent().height,
+                ];
+                command_list.SetGraphicsRoot32BitConstants(2, 4, uv_transform.as_ptr().cast(), 0);
             }
-        }
 
-        for child in content.iter_children(node_id) {
-            self.record_render_graph(command_list, content, child, data);
+            stats.draw_calls += 1;
+
+            unsafe {
+                match draw {
+                    // Six vertices (two triangles) per rect.
+                    Draw::Rects(first_instance, num_instances) => {
+                        command_list.DrawInstanced(6, num_instances, 0, first_instance);
+                    }
+                    Draw::Mesh(first_index, num_indices) => {
+                        command_list.DrawIndexedInstanced(num_indices, 1, first_index, 0, 0);
+                    }
+                }
+            }
         }
     }
 }
@@ -351,15 +782,28 @@ impl Drop for Platform {
 
 pub struct RenderTarget<'a> {
     image: &'a Image,
+    damage: &'a surface::DamageHistory,
 }
 
 pub struct Image {
     resource: ID3D12Resource,
+    alpha_mode: AlphaMode,
     last_use: Cell<SubmissionId>,
     rtv: Descriptor,
     srv: Descriptor,
 }
 
+impl Image {
+    pub fn alpha_mode(&self) -> AlphaMode {
+        self.alpha_mode
+    }
+
+    pub fn extent(&self) -> Extent<u32, Px> {
+        let desc = unsafe { self.resource.GetDesc() };
+        Extent::new(desc.Width as u32, desc.Height)
+    }
+}
+
 fn transition_barrier(
     resource: &ID3D12Resource,
     state_before: D3D12_RESOURCE_STATES,
@@ -379,16 +823,26 @@ fn transition_barrier(
     }
 }
 
+#[derive(Clone, Copy)]
 struct ShaderConstants {
     viewport: Extent<u32, ScreenPx>,
+    /// Moves everything drawn, for drawing display lists.
+    offset: Offset<f32, Px>,
 }
 
 impl PushConstants for ShaderConstants {
     unsafe fn write(&self, command_list: &ID3D12GraphicsCommandList) {
         command_list.SetGraphicsRoot32BitConstants(
             0,
-            2,
-            [self.viewport.width, self.viewport.height].as_ptr().cast(),
+            4,
+            [
+                self.viewport.width,
+                self.viewport.height,
+                self.offset.x.to_bits(),
+                self.offset.y.to_bits(),
+            ]
+            .as_ptr()
+            .cast(),
             0,
         );
     }
@@ -407,7 +861,33 @@ fn create_rounded_rect_shader(dx: &dx::Interfaces) -> Shader<ShaderConstants> {
             vertex_input(s!("RECT_CENTER"), 0, DXGI_FORMAT_R32G32_FLOAT, 0),
             vertex_input(s!("OUTER_RADIUS"), 0, DXGI_FORMAT_R32G32B32A32_FLOAT, 0),
             vertex_input(s!("INNER_RADIUS"), 0, DXGI_FORMAT_R32G32B32A32_FLOAT, 0),
-            vertex_input(s!("COLOR\0"), 0, DXGI_FORMAT_R32G32B32A32_FLOAT, 0),
+            vertex_input(s!("COLOR"), 0, DXGI_FORMAT_R32G32B32A32_FLOAT, 0),
+            vertex_input(s!("TEXCOORD"), 0, DXGI_FORMAT_R32G32_FLOAT, 0),
+        ],
+    )
+}
+
+/// Draws [`RectInstance`]s, expanding each one into a quad in the vertex
+/// shader. The pixel shader is the same as for rounded rects.
+fn create_rect_instance_shader(dx: &dx::Interfaces) -> Shader<ShaderConstants> {
+    Shader::new(
+        dx,
+        include_bytes!(concat!(env!("OUT_DIR"), "/rect_instance_vs.cso")),
+        include_bytes!(concat!(env!("OUT_DIR"), "/rect_ps.cso")),
+        DXGI_FORMAT_R16G16B16A16_FLOAT,
+        D3D_PRIMITIVE_TOPOLOGY_TRIANGLELIST,
+        &[
+            instance_input(s!("RECT"), 0, DXGI_FORMAT_R32G32B32A32_FLOAT, 0),
+            instance_input(s!("OUTER_RADIUS"), 0, DXGI_FORMAT_R32G32B32A32_FLOAT, 0),
+            instance_input(s!("INNER_RADIUS"), 0, DXGI_FORMAT_R32G32B32A32_FLOAT, 0),
+            instance_input(s!("COLOR"), 0, DXGI_FORMAT_R16G16B16A16_FLOAT, 0),
+            instance_input(s!("COLOR"), 1, DXGI_FORMAT_R16G16B16A16_FLOAT, 0),
+            instance_input(s!("COLOR"), 2, DXGI_FORMAT_R16G16B16A16_FLOAT, 0),
+            instance_input(s!("COLOR"), 3, DXGI_FORMAT_R16G16B16A16_FLOAT, 0),
+            instance_input(s!("TEXCOORD"), 0, DXGI_FORMAT_R16G16_UNORM, 0),
+            instance_input(s!("TEXCOORD"), 1, DXGI_FORMAT_R16G16_UNORM, 0),
+            instance_input(s!("TEXCOORD"), 2, DXGI_FORMAT_R16G16_UNORM, 0),
+            instance_input(s!("TEXCOORD"), 3, DXGI_FORMAT_R16G16_UNORM, 0),
         ],
     )
 }
@@ -436,7 +916,8 @@ impl<Constants: PushConstants> Shader<Constants> {
 
         let mut blend_targets = [D3D12_RENDER_TARGET_BLEND_DESC::default(); 8];
 
-        // Blend with premultiplied alpha
+        // Blend with premultiplied alpha. Shaders must output premultiplied
+        // colors, and images are premultiplied when they are uploaded.
         blend_targets[0] = D3D12_RENDER_TARGET_BLEND_DESC {
             BlendEnable: true.into(),
             LogicOpEnable: false.into(),
@@ -444,7 +925,7 @@ impl<Constants: PushConstants> Shader<Constants> {
             DestBlend: D3D12_BLEND_INV_SRC_ALPHA,
             BlendOp: D3D12_BLEND_OP_ADD,
             SrcBlendAlpha: D3D12_BLEND_ONE,
-            DestBlendAlpha: D3D12_BLEND_ONE,
+            DestBlendAlpha: D3D12_BLEND_INV_SRC_ALPHA,
             BlendOpAlpha: D3D12_BLEND_OP_ADD,
             LogicOp: D3D12_LOGIC_OP_NOOP,
             RenderTargetWriteMask: D3D12_COLOR_WRITE_ENABLE_ALL.0 as u8,
@@ -534,25 +1015,152 @@ impl<Constants: PushConstants> Shader<Constants> {
         command_list: &ID3D12GraphicsCommandList,
         constants: &Constants,
         vertices: &D3D12_VERTEX_BUFFER_VIEW,
-        indices: &D3D12_INDEX_BUFFER_VIEW,
+        indices: Option<&D3D12_INDEX_BUFFER_VIEW>,
     ) {
         unsafe {
             command_list.SetPipelineState(&self.pipeline_state);
             command_list.SetGraphicsRootSignature(&self.root_signature);
             command_list.IASetPrimitiveTopology(self.primitive_topology);
             command_list.IASetVertexBuffers(0, Some(&[*vertices]));
-            command_list.IASetIndexBuffer(Some(indices));
+            command_list.IASetIndexBuffer(indices.map(std::ptr::from_ref));
             constants.write(command_list);
         }
     }
 }
 
+/// The draw call for a render graph command.
+#[derive(Clone, Copy)]
+enum Draw {
+    /// The first instance and number of instances.
+    Rects(u32, u32),
+    /// The first index and number of indices.
+    Mesh(u32, u32),
+}
+
+/// The vertex data of a display list, which stays on the GPU for as long as
+/// the list exists.
+struct RetainedList {
+    list: Weak<DisplayListData>,
+    buffer: ID3D12Resource,
+    /// The number of indices, vertices, and instances.
+    counts: [u64; 3],
+    last_use: Cell<SubmissionId>,
+}
+
+impl RetainedList {
+    /// Vertex buffers have to be aligned to 4 bytes, but a little more doesn't
+    /// hurt.
+    const ALIGNMENT: u64 = 16;
+
+    fn new(dx: &dx::Interfaces, list: &DisplayList) -> Self {
+        let compiled = &list.data().compiled;
+
+        let counts = [
+            compiled.indices.len(),
+            compiled.vertices.len(),
+            compiled.instances.len(),
+        ]
+        .map(|count| count as u64);
+
+        let (vertex_offset, instance_offset, size) = Self::layout(counts);
+        let buffer = create_buffer(
+            dx,
+            D3D12_HEAP_TYPE_UPLOAD,
+            size,
+            D3D12_RESOURCE_STATE_GENERIC_READ,
+        );
+
+        unsafe {
+            let mut ptr = std::ptr::null_mut();
+            buffer
+                .Map(0, Some(&D3D12_RANGE { Begin: 0, End: 0 }), Some(&mut ptr))
+                .unwrap();
+
+            let ptr = ptr.cast::<u8>();
+            std::ptr::copy_nonoverlapping(
+                compiled.indices.as_ptr(),
+                ptr.cast(),
+                compiled.indices.len(),
+            );
+            std::ptr::copy_nonoverlapping(
+                compiled.vertices.as_ptr(),
+                ptr.add(vertex_offset as usize).cast(),
+                compiled.vertices.len(),
+            );
+            std::ptr::copy_nonoverlapping(
+                compiled.instances.as_ptr(),
+                ptr.add(instance_offset as usize).cast(),
+                compiled.instances.len(),
+            );
+
+            buffer.Unmap(0, None);
+        }
+
+        if dx.is_debug {
+            unsafe { buffer.SetName(w!("Display List")).unwrap() };
+        }
+
+        Self {
+            list: list.downgrade(),
+            buffer,
+            counts,
+            last_use: Cell::new(SubmissionId::default()),
+        }
+    }
+
+    /// The offsets of the vertices and instances in the buffer, and the size
+    /// of the buffer, given the number of indices, vertices, and instances.
+    /// Indices come first.
+    fn layout([num_indices, num_vertices, num_instances]: [u64; 3]) -> (u64, u64, u64) {
+        let align = |offset: u64| offset.next_multiple_of(Self::ALIGNMENT);
+
+        let vertex_offset = align(num_indices * std::mem::size_of::<u32>() as u64);
+        let instance_offset =
+            align(vertex_offset + num_vertices * std::mem::size_of::<RoundedRectVertex>() as u64);
+        let size = instance_offset + num_instances * std::mem::size_of::<RectInstance>() as u64;
+
+        // Buffers can't be empty.
+        (vertex_offset, instance_offset, size.max(Self::ALIGNMENT))
+    }
+
+    fn views(
+        &self,
+    ) -> (
+        D3D12_INDEX_BUFFER_VIEW,
+        D3D12_VERTEX_BUFFER_VIEW,
+        D3D12_VERTEX_BUFFER_VIEW,
+    ) {
+        let address = unsafe { self.buffer.GetGPUVirtualAddress() };
+        let [num_indices, _, num_instances] = self.counts;
+        let (vertex_offset, instance_offset, _) = Self::layout(self.counts);
+
+        (
+            D3D12_INDEX_BUFFER_VIEW {
+                BufferLocation: address,
+                SizeInBytes: (num_indices * std::mem::size_of::<u32>() as u64) as u32,
+                Format: DXGI_FORMAT_R32_UINT,
+            },
+            D3D12_VERTEX_BUFFER_VIEW {
+                BufferLocation: address + vertex_offset,
+                SizeInBytes: (instance_offset - vertex_offset) as u32,
+                StrideInBytes: std::mem::size_of::<RoundedRectVertex>() as u32,
+            },
+            D3D12_VERTEX_BUFFER_VIEW {
+                BufferLocation: address + instance_offset,
+                SizeInBytes: (num_instances * std::mem::size_of::<RectInstance>() as u64) as u32,
+                StrideInBytes: std::mem::size_of::<RectInstance>() as u32,
+            },
+        )
+    }
+}
+
 struct RenderData<'a> {
     constants: ShaderConstants,
     white_pixel: &'a Image,
-    descriptor_heap: &'a DescriptorHeap,
+    images: &'a Images,
     index_buffer: D3D12_INDEX_BUFFER_VIEW,
     rect_vertex_buffer: D3D12_VERTEX_BUFFER_VIEW,
+    rect_instance_buffer: D3D12_VERTEX_BUFFER_VIEW,
 }
 
 fn create_buffer(
@@ -613,29 +1221,83 @@ fn vertex_input(
     }
 }
 
+fn instance_input(
+    name: PCSTR,
+    index: u32,
+    format: DXGI_FORMAT,
+    slot: u32,
+) -> D3D12_INPUT_ELEMENT_DESC {
+    D3D12_INPUT_ELEMENT_DESC {
+        InputSlotClass: D3D12_INPUT_CLASSIFICATION_PER_INSTANCE_DATA,
+        InstanceDataStepRate: 1,
+        ..vertex_input(name, index, format, slot)
+    }
+}
+
 fn upload_image(
     dx: &dx::Interfaces,
     command_list: &ID3D12GraphicsCommandList,
     upload_heap: &ID3D12Resource,
     allocator: &mut temp_allocator::FrameAllocator,
     descriptor_heap: &mut DescriptorHeap,
-    pixels: PixelBufferRef,
+    levels: &[PixelBufferRef],
 ) -> Image {
-    // To avoid recalculating
-    let pixels_height = pixels.height();
+    let pixels = levels[0];
+    let num_levels = u16::try_from(levels.len()).expect("too many mip levels");
 
-    let format = match pixels.format() {
-        PixelFormat::Rgba8 => DXGI_FORMAT_R8G8B8A8_UNORM,
-    };
+    let image = create_texture(
+        dx,
+        descriptor_heap,
+        Extent::new(pixels.width(), pixels.height()),
+        num_levels,
+        dxgi_format(pixels.format()),
+        D3D12_RESOURCE_STATE_COPY_DEST,
+        uploaded_alpha_mode(pixels.alpha_mode()),
+    );
+
+    for (subresource, level) in (0..).zip(levels) {
+        debug_assert_eq!(level.format(), pixels.format());
+        copy_pixels_to_texture(
+            command_list,
+            upload_heap,
+            allocator,
+            &image.resource,
+            subresource,
+            (0, 0),
+            pixels.format(),
+            image.alpha_mode,
+            *level,
+        );
+    }
 
+    unsafe {
+        command_list.ResourceBarrier(&[transition_barrier(
+            &image.resource,
+            D3D12_RESOURCE_STATE_COPY_DEST,
+            D3D12_RESOURCE_STATE_PIXEL_SHADER_RESOURCE,
+        )]);
+    }
+
+    image
+}
+
+fn create_texture(
+    dx: &dx::Interfaces,
+    descriptor_heap: &mut DescriptorHeap,
+    extent: Extent<u32, Px>,
+    num_levels: u16,
+    format: DXGI_FORMAT,
+    initial_state: D3D12_RESOURCE_STATES,
+    alpha_mode: AlphaMode,
+) -> Image {
     let image = {
         let desc = D3D12_RESOURCE_DESC {
             Dimension: D3D12_RESOURCE_DIMENSION_TEXTURE2D,
             Alignment: 0,
-            Width: pixels.width().into(),
-            Height: pixels_height,
+            Width: extent.width.into(),
+            Height: extent.height,
             DepthOrArraySize: 1,
-            MipLevels: 1,
+            MipLevels: num_levels,
             Format: format,
             SampleDesc: DXGI_SAMPLE_DESC {
                 Count: 1,
@@ -658,7 +1320,7 @@ fn upload_image(
                     },
                     D3D12_HEAP_FLAG_NONE,
                     &desc,
-                    D3D12_RESOURCE_STATE_COPY_DEST,
+                    initial_state,
                     None,
                     &mut image,
                 )
@@ -668,13 +1330,83 @@ fn upload_image(
         image.unwrap()
     };
 
+    let srv = {
+        let desc = D3D12_SHADER_RESOURCE_VIEW_DESC {
+            Format: format,
+            ViewDimension: D3D12_SRV_DIMENSION_TEXTURE2D,
+            Shader4ComponentMapping: D3D12_DEFAULT_SHADER_4_COMPONENT_MAPPING,
+            Anonymous: D3D12_SHADER_RESOURCE_VIEW_DESC_0 {
+                Texture2D: D3D12_TEX2D_SRV {
+                    MostDetailedMip: 0,
+                    MipLevels: u32::from(num_levels),
+                    PlaneSlice: 0,
+                    ResourceMinLODClamp: 0.0,
+                },
+            },
+        };
+
+        descriptor_heap.create_shader_resource_view(dx, &image, &desc)
+    };
+
+    Image {
+        resource: image,
+        alpha_mode,
+        last_use: Cell::new(SubmissionId::default()),
+        rtv: Descriptor::default(),
+        srv,
+    }
+}
+
+/// The renderer blends with premultiplied alpha, so images with straight alpha
+/// are converted when they are uploaded.
+fn uploaded_alpha_mode(alpha_mode: AlphaMode) -> AlphaMode {
+    match alpha_mode {
+        AlphaMode::Straight | AlphaMode::Premultiplied => AlphaMode::Premultiplied,
+        AlphaMode::Opaque => AlphaMode::Opaque,
+    }
+}
+
+fn dxgi_format(format: PixelFormat) -> DXGI_FORMAT {
+    match format {
+        PixelFormat::Rgba8 => DXGI_FORMAT_R8G8B8A8_UNORM,
+        PixelFormat::Bgra8 => DXGI_FORMAT_B8G8R8A8_UNORM,
+    }
+}
+
+fn pixel_format(format: DXGI_FORMAT) -> Option<PixelFormat> {
+    match format {
+        DXGI_FORMAT_R8G8B8A8_UNORM => Some(PixelFormat::Rgba8),
+        DXGI_FORMAT_B8G8R8A8_UNORM => Some(PixelFormat::Bgra8),
+        _ => None,
+    }
+}
+
+/// Records a copy of `pixels` into one subresource of a texture through the
+/// upload heap, with the top-left corner of the pixels at `dst_offset`. The
+/// pixels are converted to `format`, which must be the texture's format. The
+/// texture must be in the `COPY_DEST` state.
+#[allow(clippy::too_many_arguments)]
+fn copy_pixels_to_texture(
+    command_list: &ID3D12GraphicsCommandList,
+    upload_heap: &ID3D12Resource,
+    allocator: &mut temp_allocator::FrameAllocator,
+    texture: &ID3D12Resource,
+    subresource: u32,
+    dst_offset: (u32, u32),
+    format: PixelFormat,
+    alpha_mode: AlphaMode,
+    pixels: PixelBufferRef,
+) {
+    // To avoid recalculating
+    let pixels_height = pixels.height();
+
     let footprint = D3D12_SUBRESOURCE_FOOTPRINT {
-        Format: format,
+        Format: dxgi_format(format),
         Width: pixels.width(),
         Height: pixels_height,
         Depth: 1,
         RowPitch: next_multiple_of_u32(
-            pixels.width() * (pixels.format().bytes_per_pixel() as u32),
+            pixels.width() * (format.bytes_per_pixel() as u32),
             D3D12_TEXTURE_DATA_PITCH_ALIGNMENT,
         ),
     };
@@ -688,12 +1420,18 @@ fn upload_image(
 
     let bytes = bytes.expect("upload allocator with no host memory?");
 
-    for (i, row) in pixels.rows().enumerate() {
-        let offset = footprint.RowPitch as usize * i;
-        unsafe {
-            std::ptr::copy_nonoverlapping(row.as_ptr(), bytes.as_mut_ptr().add(offset), row.len());
-        }
-    }
+    PixelBufferMut::from_bytes_with_pitch(
+        bytes,
+        pixels.width(),
+        pixels_height,
+        footprint.RowPitch as usize,
+        format,
+        pixels.color_space(),
+    )
+    // Converted to the alpha mode the texture is stored in, which may differ
+    // from the pixels' when updating part of a texture.
+    .with_alpha_mode(alpha_mode)
+    .copy_from(pixels);
 
     let placed_desc = D3D12_PLACED_SUBRESOURCE_FOOTPRINT {
         Offset: mem.heap_offset,
@@ -703,14 +1441,14 @@ fn upload_image(
     unsafe {
         command_list.CopyTextureRegion(
             &D3D12_TEXTURE_COPY_LOCATION {
-                pResource: windows::core::ManuallyDrop::new(&image),
+                pResource: windows::core::ManuallyDrop::new(texture),
                 Type: D3D12_TEXTURE_COPY_TYPE_SUBRESOURCE_INDEX,
                 Anonymous: D3D12_TEXTURE_COPY_LOCATION_0 {
-                    SubresourceIndex: 0,
+                    SubresourceIndex: subresource,
                 },
             },
-            0,
-            0,
+            dst_offset.0,
+            dst_offset.1,
             0,
             &D3D12_TEXTURE_COPY_LOCATION {
                 pResource: windows::core::ManuallyDrop::new(upload_heap),
@@ -721,37 +1459,6 @@ fn upload_image(
             },
             None,
         );
-
-        command_list.ResourceBarrier(&[transition_barrier(
-            &image,
-            D3D12_RESOURCE_STATE_COPY_DEST,
-            D3D12_RESOURCE_STATE_PIXEL_SHADER_RESOURCE,
-        )]);
-    }
-
-    let srv = {
-        let desc = D3D12_SHADER_RESOURCE_VIEW_DESC {
-            Format: format,
-            ViewDimension: D3D12_SRV_DIMENSION_TEXTURE2D,
-            Shader4ComponentMapping: D3D12_DEFAULT_SHADER_4_COMPONENT_MAPPING,
-            Anonymous: D3D12_SHADER_RESOURCE_VIEW_DESC_0 {
-                Texture2D: D3D12_TEX2D_SRV {
-                    MostDetailedMip: 0,
-                    MipLevels: 1,
-                    PlaneSlice: 0,
-                    ResourceMinLODClamp: 0.0,
-                },
-            },
-        };
-
-        descriptor_heap.create_shader_resource_view(dx, &image, &desc)
-    };
-
-    Image {
-        resource: image,
-        last_use: Cell::new(SubmissionId::default()),
-        rtv: Descriptor::default(),
-        srv,
     }
 }
 
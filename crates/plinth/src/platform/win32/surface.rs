@@ -5,7 +5,7 @@ use windows::{
     core::Interface,
     w,
     Win32::{
-        Foundation::{CloseHandle, HANDLE, HWND},
+        Foundation::{CloseHandle, HANDLE, HWND, RECT},
         Graphics::{
             Direct3D12::*,
             Dxgi::{Common::*, *},
@@ -14,6 +14,8 @@ use windows::{
     },
 };
 
+use crate::graphics::AlphaMode;
+
 use super::{dx, queue::SubmissionId, Descriptor, DescriptorHeap, Image};
 
 /// A `Surface` controls the acquisition and presentation of images to its
@@ -26,6 +28,7 @@ pub struct Surface {
     render_targets: [Option<Image>; Self::BUFFER_COUNT as usize],
     waitable_object: HANDLE,
     rtv_heap: DescriptorHeap,
+    damage: DamageHistory,
 }
 
 impl Surface {
@@ -35,7 +38,15 @@ impl Surface {
     // on SDR displays.
     const FORMAT: DXGI_FORMAT = DXGI_FORMAT_R16G16B16A16_FLOAT;
 
-    pub fn new(dx: &dx::Interfaces, queue: &ID3D12CommandQueue, window: HWND) -> Self {
+    /// Creates a swapchain for the window. If `partial_redraws` is set, each
+    /// image keeps its contents between frames, so that only damaged regions
+    /// have to be redrawn.
+    pub fn new(
+        dx: &dx::Interfaces,
+        queue: &ID3D12CommandQueue,
+        window: HWND,
+        partial_redraws: bool,
+    ) -> Self {
         // Setting this flag lets us limit the number of frames in the present
         // queue. If the application renders faster than the display can present
         // them, the application will block until the display catches up.
@@ -64,9 +75,13 @@ impl Surface {
                     // Note: DISCARD has higher performance than SEQUENTIAL,
                     // since the DWM can overwrite parts of the image with
                     // overlapped windows instead of copying it into its own
-                    // memory. However, it may make sense to use SEQUENTIAL if
-                    // partial swapchain updates are needed.
-                    SwapEffect: DXGI_SWAP_EFFECT_FLIP_DISCARD,
+                    // memory. However, partial redraws need the contents of
+                    // each buffer to be kept between frames.
+                    SwapEffect: if partial_redraws {
+                        DXGI_SWAP_EFFECT_FLIP_SEQUENTIAL
+                    } else {
+                        DXGI_SWAP_EFFECT_FLIP_DISCARD
+                    },
                     // We don't care about transparent windows at the moment.
                     AlphaMode: DXGI_ALPHA_MODE_IGNORE,
                     Flags: flags.0 as u32,
@@ -99,6 +114,7 @@ impl Surface {
             render_targets: [Some(a), Some(b)],
             waitable_object,
             rtv_heap,
+            damage: DamageHistory::new(partial_redraws),
         }
     }
 
@@ -125,12 +141,13 @@ impl Surface {
 
         let [a, b] = Self::get_render_targets(dx, &self.swapchain, &mut self.rtv_heap);
         self.render_targets = [Some(a), Some(b)];
+        self.damage = DamageHistory::new(self.damage.keeps_contents);
     }
 
     /// Retrieves the next image in the swap chain.
     ///
     /// This function will block until the next image is available.
-    pub fn get_next_image(&mut self) -> &Image {
+    pub fn get_next_image(&mut self) -> (&Image, &DamageHistory) {
         // block until the next image is available
         //
         // NOTE: should this instead be done just before presenting???
@@ -139,10 +156,13 @@ impl Surface {
             .unwrap();
 
         self.image_index = unsafe { self.swapchain.GetCurrentBackBufferIndex() };
+        self.damage.current.set(self.image_index as usize);
 
-        self.render_targets[self.image_index as usize]
+        let image = self.render_targets[self.image_index as usize]
             .as_ref()
-            .unwrap()
+            .unwrap();
+
+        (image, &self.damage)
     }
 
     /// Presents the image to the surface.
@@ -151,7 +171,23 @@ impl Surface {
 
         // We assume that the window is not typically in borderless fullscreen,
         // and so use a presentation interval of 1 (VSync).
-        unsafe { self.swapchain.Present(1, 0) }.unwrap();
+        match self.damage.dirty.get() {
+            // Telling the compositor what changed lets it skip the rest.
+            Some(mut dirty) if !is_empty(&dirty) => unsafe {
+                self.swapchain.Present1(
+                    1,
+                    0,
+                    &DXGI_PRESENT_PARAMETERS {
+                        DirtyRectsCount: 1,
+                        pDirtyRects: &mut dirty,
+                        pScrollRect: std::ptr::null_mut(),
+                        pScrollOffset: std::ptr::null_mut(),
+                    },
+                )
+            }
+            .unwrap(),
+            _ => unsafe { self.swapchain.Present(1, 0) }.unwrap(),
+        }
     }
 
     fn get_render_targets(
@@ -175,12 +211,14 @@ impl Surface {
             [
                 Image {
                     resource: buffer0,
+                    alpha_mode: AlphaMode::Opaque,
                     last_use: Cell::new(SubmissionId::default()),
                     rtv: rtv0,
                     srv: Descriptor::default(),
                 },
                 Image {
                     resource: buffer1,
+                    alpha_mode: AlphaMode::Opaque,
                     last_use: Cell::new(SubmissionId::default()),
                     rtv: rtv1,
                     srv: Descriptor::default(),
@@ -190,6 +228,84 @@ impl Surface {
     }
 }
 
+/// Tracks which parts of each swapchain image are older than the last frame,
+/// so that a partial redraw can bring an image up to date.
+pub struct DamageHistory {
+    /// The out-of-date region of each image, or `None` if it is up to date.
+    stale: [Cell<Option<RECT>>; Surface::BUFFER_COUNT as usize],
+    current: Cell<usize>,
+    /// The region that changed in the last frame, or `None` if nothing has
+    /// been drawn yet.
+    dirty: Cell<Option<RECT>>,
+    /// Whether images keep their contents between frames. If they don't,
+    /// every frame is redrawn in full.
+    keeps_contents: bool,
+}
+
+impl DamageHistory {
+    /// Stands in for the whole image until it is clipped to the image's size.
+    const EVERYTHING: RECT = RECT {
+        left: 0,
+        top: 0,
+        right: i32::MAX,
+        bottom: i32::MAX,
+    };
+
+    fn new(keeps_contents: bool) -> Self {
+        Self {
+            stale: std::array::from_fn(|_| Cell::new(Some(Self::EVERYTHING))),
+            current: Cell::new(0),
+            dirty: Cell::new(None),
+            keeps_contents,
+        }
+    }
+
+    /// Records that the current image is being redrawn where `damage` changed
+    /// (or everywhere if `None`), returning the region of the image that has
+    /// to be redrawn. This also includes changes from earlier frames that the
+    /// image missed.
+    pub fn redraw(&self, damage: Option<RECT>, width: u32, height: u32) -> RECT {
+        let damage = damage
+            .filter(|_| self.keeps_contents)
+            .unwrap_or(Self::EVERYTHING);
+
+        let region = match self.stale[self.current.get()].take() {
+            Some(stale) => union(&damage, &stale),
+            None => damage,
+        };
+
+        for (i, stale) in self.stale.iter().enumerate() {
+            if i != self.current.get() {
+                stale.set(Some(stale.get().map_or(damage, |s| union(&s, &damage))));
+            }
+        }
+
+        let clip = |rect: RECT| RECT {
+            left: rect.left.max(0),
+            top: rect.top.max(0),
+            right: rect.right.min(width.try_into().unwrap()),
+            bottom: rect.bottom.min(height.try_into().unwrap()),
+        };
+
+        // Images that don't keep their contents are always presented whole.
+        self.dirty.set(self.keeps_contents.then(|| clip(damage)));
+        clip(region)
+    }
+}
+
+fn union(a: &RECT, b: &RECT) -> RECT {
+    RECT {
+        left: a.left.min(b.left),
+        top: a.top.min(b.top),
+        right: a.right.max(b.right),
+        bottom: a.bottom.max(b.bottom),
+    }
+}
+
+fn is_empty(rect: &RECT) -> bool {
+    rect.right <= rect.left || rect.bottom <= rect.top
+}
+
 impl Drop for Surface {
     fn drop(&mut self) {
         assert_eq!(
@@ -1,4 +1,9 @@
-use std::{cell::RefCell, collections::HashMap, rc::Rc};
+use std::{
+    cell::{Cell, RefCell},
+    collections::HashMap,
+    rc::Rc,
+    time::{Duration, Instant},
+};
 
 use geometry::{Extent, Offset, Point, ScreenPx};
 use raw_window_handle::{
@@ -67,18 +72,59 @@ pub trait WindowHandler {
         new_inner_size: Extent<u32, ScreenPx>,
     );
 
+    /// Called once the event loop has handled all pending events, before it
+    /// waits for more as set by [`WindowSpawner::set_control_flow`].
     fn on_idle(&mut self, spawner: &mut dyn WindowSpawner<Self>);
 
     /// Called when the OS requests that the window be redrawn.
     fn on_redraw(&mut self, spawner: &mut dyn WindowSpawner<Self>);
+
+    /// Called once per display refresh after the window requested it with
+    /// [`Window::request_animation_frame`]. Every window animating in the same
+    /// frame gets the same `timestamp`, and `frame_interval` is the time until
+    /// the next frame.
+    fn on_animation_frame(
+        &mut self,
+        spawner: &mut dyn WindowSpawner<Self>,
+        timestamp: Instant,
+        frame_interval: Duration,
+    );
 }
 
-/// Event loop interface for spawing new windows.
+/// Event loop interface for spawing new windows and controlling how the event
+/// loop waits for events.
 ///
 /// Only accessible from within a window handler (and event loop).
 pub trait WindowSpawner<Handler: WindowHandler> {
     /// Creates a new window bound to the event loop.
     fn spawn(&mut self, desc: WindowDesc<Handler>);
+
+    /// The way the event loop waits for events, shared by all windows.
+    fn control_flow(&self) -> ControlFlow;
+
+    /// Changes the way the event loop waits for events once it has handled the
+    /// ones it has. This lasts until it is changed again.
+    fn set_control_flow(&mut self, control_flow: ControlFlow);
+}
+
+/// How the event loop waits for new events once it has handled the ones it
+/// has.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ControlFlow {
+    /// Sleeps until there are new events. Suits apps that only change in
+    /// response to input, and saves the most power.
+    #[default]
+    Wait,
+    /// Runs again immediately, so `on_idle` is called continuously. Suits apps
+    /// that draw every frame, at the cost of keeping a CPU core busy.
+    Poll,
+    /// Sleeps until there are new events or the instant is reached, whichever
+    /// comes first. Suits timers and animations with long gaps between frames.
+    ///
+    /// Once the instant has passed, the control flow goes back to
+    /// [`ControlFlow::Wait`], so set it again (from `on_idle`, for example) to
+    /// keep waking up.
+    WaitUntil(Instant),
 }
 
 bitflags::bitflags! {
@@ -116,6 +162,7 @@ impl<'a, Handler: WindowHandler> WindowDesc<'a, Handler> {
         self,
         target: &winit::event_loop::EventLoopWindowTarget<()>,
         deferred_destroy: DeferredDestroy,
+        animation_requests: AnimationRequests,
     ) -> WindowState<Handler> {
         let mut builder = winit::window::WindowBuilder::new()
             .with_title(self.title)
@@ -144,16 +191,20 @@ impl<'a, Handler: WindowHandler> WindowDesc<'a, Handler> {
         let id = window.id();
 
         let extent = as_extent(window.inner_size());
+        let frame_interval = FrameInterval::default();
 
         let handler = (self.handler)(Window {
             inner: window,
             deferred_destroy,
+            animation_requests,
+            frame_interval: frame_interval.clone(),
         });
 
         WindowState {
             id,
             handler,
             extent,
+            frame_interval,
             cursor_position: Point::zero(),
             repeated_key: None,
         }
@@ -165,6 +216,8 @@ impl<'a, Handler: WindowHandler> WindowDesc<'a, Handler> {
 pub struct Window {
     inner: winit::window::Window,
     deferred_destroy: DeferredDestroy,
+    animation_requests: AnimationRequests,
+    frame_interval: FrameInterval,
 }
 
 unsafe impl HasRawWindowHandle for Window {
@@ -201,6 +254,34 @@ impl Window {
     pub fn request_redraw(&self) {
         self.inner.request_redraw();
     }
+
+    /// Schedules a call to [`WindowHandler::on_animation_frame`] at the next
+    /// display refresh. Request another frame from the callback to keep
+    /// animating; the event loop stops waking up for frames once no window
+    /// requests them.
+    pub fn request_animation_frame(&self) {
+        let interval = self.frame_interval.get().unwrap_or_else(|| {
+            // Windows reports 0 or 1 Hz when the monitor uses its default
+            // refresh rate.
+            let interval = self
+                .inner
+                .current_monitor()
+                .and_then(|monitor| monitor.refresh_rate_millihertz())
+                .filter(|millihertz| *millihertz > 1000)
+                .map_or(DEFAULT_FRAME_INTERVAL, |millihertz| {
+                    Duration::from_secs(1000) / millihertz
+                });
+            self.frame_interval.set(Some(interval));
+            interval
+        });
+
+        let mut requests = self.animation_requests.borrow_mut();
+        if let Some(request) = requests.iter_mut().find(|(id, _)| *id == self.inner.id()) {
+            request.1 = interval;
+        } else {
+            requests.push((self.inner.id(), interval));
+        }
+    }
 }
 
 #[must_use]
@@ -208,6 +289,7 @@ struct WindowState<Handler: WindowHandler> {
     id: winit::window::WindowId,
     handler: Handler,
     extent: Extent<u32, ScreenPx>,
+    frame_interval: FrameInterval,
     cursor_position: Point<i32, ScreenPx>,
     repeated_key: Option<(winit::event::KeyboardInput, u16)>,
 }
@@ -217,6 +299,8 @@ struct Control<'a, Handler: WindowHandler> {
     event_loop: &'a winit::event_loop::EventLoopWindowTarget<()>,
     buffered_creates: &'a mut Vec<WindowState<Handler>>,
     buffered_destroys: &'a DeferredDestroy,
+    animation_requests: &'a AnimationRequests,
+    control_flow: &'a mut ControlFlow,
 }
 
 impl<'a, Handler: WindowHandler> Control<'a, Handler> {
@@ -224,20 +308,36 @@ impl<'a, Handler: WindowHandler> Control<'a, Handler> {
         event_loop: &'a winit::event_loop::EventLoopWindowTarget<()>,
         buffered_creates: &'a mut Vec<WindowState<Handler>>,
         buffered_destroys: &'a DeferredDestroy,
+        animation_requests: &'a AnimationRequests,
+        control_flow: &'a mut ControlFlow,
     ) -> Self {
         Self {
             event_loop,
             buffered_creates,
             buffered_destroys,
+            animation_requests,
+            control_flow,
         }
     }
 }
 
 impl<'a, Handler: WindowHandler> WindowSpawner<Handler> for Control<'a, Handler> {
     fn spawn(&mut self, desc: WindowDesc<Handler>) {
-        let window = desc.build(self.event_loop, self.buffered_destroys.clone());
+        let window = desc.build(
+            self.event_loop,
+            self.buffered_destroys.clone(),
+            self.animation_requests.clone(),
+        );
         self.buffered_creates.push(window);
     }
+
+    fn control_flow(&self) -> ControlFlow {
+        *self.control_flow
+    }
+
+    fn set_control_flow(&mut self, control_flow: ControlFlow) {
+        *self.control_flow = control_flow;
+    }
 }
 
 /// Holds the ids of windows that are scheduled to be destroyed. They are kept
@@ -245,8 +345,72 @@ impl<'a, Handler: WindowHandler> WindowSpawner<Handler> for Control<'a, Handler>
 /// for `Window::destroy` to schedule the window for destruction.
 type DeferredDestroy = Rc<RefCell<Vec<winit::window::WindowId>>>;
 
+/// Holds the windows that requested an animation frame since the last one,
+/// with the refresh interval of the monitor each is on. Shared with `Window`
+/// for the same reason as `DeferredDestroy`.
+type AnimationRequests = Rc<RefCell<Vec<(winit::window::WindowId, Duration)>>>;
+
+/// The refresh interval of the monitor a window is on, or `None` if it has to
+/// be looked up again because the window may have changed monitors. Shared
+/// with `Window` for the same reason as `DeferredDestroy`.
+type FrameInterval = Rc<Cell<Option<Duration>>>;
+
+/// Used when the refresh rate of a window's monitor is unknown.
+const DEFAULT_FRAME_INTERVAL: Duration = Duration::from_micros(16_667);
+
+/// Schedules animation frames so that every window animating at the same time
+/// shares each frame, one display refresh apart.
+#[derive(Default)]
+struct AnimationClock {
+    last_frame: Option<Instant>,
+    /// When the next frame is due and the interval it was scheduled with, if
+    /// any window requested one.
+    next_frame: Option<(Instant, Duration)>,
+}
+
+impl AnimationClock {
+    /// Schedules a frame one interval after the last frame, or right away if
+    /// that has passed, unless a frame is already scheduled. Returns when the
+    /// frame is due.
+    fn schedule(&mut self, now: Instant, interval: Duration) -> Instant {
+        let last_frame = self.last_frame;
+        let (due, _) = *self.next_frame.get_or_insert_with(|| {
+            let due = last_frame.map_or(now, |last| (last + interval).max(now));
+            (due, interval)
+        });
+        due
+    }
+
+    /// Schedules a frame for the windows that requested one, with the
+    /// refresh interval of each one's monitor. Frames go at the rate of the
+    /// fastest monitor being animated.
+    fn schedule_requests(
+        &mut self,
+        now: Instant,
+        intervals: impl IntoIterator<Item = Duration>,
+    ) -> Option<Instant> {
+        let interval = intervals.into_iter().min()?;
+        Some(self.schedule(now, interval))
+    }
+
+    /// Takes the scheduled frame's timestamp and interval if it is due.
+    fn take_due(&mut self, now: Instant) -> Option<(Instant, Duration)> {
+        let (due, interval) = self.next_frame.filter(|(due, _)| *due <= now)?;
+        self.next_frame = None;
+        self.last_frame = Some(due);
+        Some((due, interval))
+    }
+
+    /// When the event loop must wake up for the next frame, if one is
+    /// scheduled.
+    fn next_frame(&self) -> Option<Instant> {
+        self.next_frame.map(|(due, _)| due)
+    }
+}
+
 /// Creates the described windows and runs the OS event loop until all windows
-/// are destroyed.
+/// are destroyed. The event loop waits for events (see [`ControlFlow::Wait`])
+/// until a handler sets a different control flow.
 #[allow(clippy::too_many_lines)]
 pub fn enter_event_loop<'a, Handler, I>(window_descs: I)
 where
@@ -262,9 +426,17 @@ where
     // added to the map at the end of every event loop invocation.
     let mut buffered_window_creates: Vec<WindowState<Handler>> = Vec::new();
     let buffered_window_destroys: DeferredDestroy = Rc::new(RefCell::new(Vec::new()));
+    let mut requested_control_flow = ControlFlow::default();
+
+    let animation_requests: AnimationRequests = Rc::new(RefCell::new(Vec::new()));
+    let mut animation_clock = AnimationClock::default();
 
     for desc in window_descs {
-        let window = desc.build(&event_loop, buffered_window_destroys.clone());
+        let window = desc.build(
+            &event_loop,
+            buffered_window_destroys.clone(),
+            animation_requests.clone(),
+        );
         windows.insert(window.id, window);
     }
 
@@ -280,13 +452,12 @@ where
     }
 
     event_loop.run(move |event, event_loop, control_flow| {
-        // control_flow.set_wait();
-        control_flow.set_poll();
-
         let mut control = Control::new(
             event_loop,
             &mut buffered_window_creates,
             &buffered_window_destroys,
+            &animation_requests,
+            &mut requested_control_flow,
         );
 
         match event {
@@ -379,10 +550,15 @@ where
                             }
                         }
                     }
+                    WindowEvent::Moved(_) => {
+                        // The window may be on another monitor now.
+                        window_state.frame_interval.set(None);
+                    }
                     WindowEvent::ScaleFactorChanged {
                         scale_factor,
                         new_inner_size,
                     } => {
+                        window_state.frame_interval.set(None);
                         window_state.handler.on_rescale(
                             &mut control,
                             scale_factor,
@@ -393,6 +569,21 @@ where
                 }
             }
             Event::MainEventsCleared => {
+                let now = Instant::now();
+
+                if let Some((timestamp, interval)) = animation_clock.take_due(now) {
+                    // Taken first, so that windows requesting another frame
+                    // get the next one.
+                    let requests = std::mem::take(&mut *animation_requests.borrow_mut());
+                    for (window_id, _) in requests {
+                        if let Some(window) = windows.get_mut(&window_id) {
+                            window
+                                .handler
+                                .on_animation_frame(&mut control, timestamp, interval);
+                        }
+                    }
+                }
+
                 for window in windows.values_mut() {
                     window.handler.on_idle(&mut control);
                 }
@@ -420,6 +611,36 @@ where
                 .expect("cannot destroy a window twice");
             state.handler.on_destroy();
         }
+
+        // Scheduled after every event, since frames can be requested from
+        // any handler, including `on_redraw`, which runs after `on_idle`.
+        animation_clock.schedule_requests(
+            Instant::now(),
+            animation_requests
+                .borrow()
+                .iter()
+                .map(|(_, interval)| *interval),
+        );
+
+        // A deadline that has passed would wake the loop up immediately, over
+        // and over.
+        if let ControlFlow::WaitUntil(instant) = requested_control_flow {
+            if instant <= Instant::now() {
+                requested_control_flow = ControlFlow::Wait;
+            }
+        }
+
+        // Events that return early don't reach handlers, so they can't have
+        // changed the control flow. Wake up for animation frames whatever the
+        // handlers asked for.
+        match (requested_control_flow, animation_clock.next_frame()) {
+            (ControlFlow::Poll, _) => control_flow.set_poll(),
+            (ControlFlow::Wait, None) => control_flow.set_wait(),
+            (ControlFlow::Wait, Some(frame)) => control_flow.set_wait_until(frame),
+            (ControlFlow::WaitUntil(instant), frame) => {
+                control_flow.set_wait_until(frame.map_or(instant, |frame| frame.min(instant)));
+            }
+        }
     });
 }
 
@@ -442,3 +663,71 @@ fn as_extent(size: PhysicalSize<u32>) -> Extent<u32, ScreenPx> {
 fn as_point(position: PhysicalPosition<i32>) -> Point<i32, ScreenPx> {
     Point::new(position.x, position.y)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const INTERVAL: Duration = Duration::from_millis(16);
+
+    #[test]
+    fn frames_are_one_interval_apart() {
+        let start = Instant::now();
+        let mut clock = AnimationClock::default();
+
+        // The first frame is right away, and requesting it again doesn't
+        // delay it.
+        assert_eq!(clock.schedule(start, INTERVAL), start);
+        assert_eq!(clock.schedule(start + INTERVAL, INTERVAL), start);
+        assert_eq!(
+            clock.take_due(start + Duration::from_millis(1)),
+            Some((start, INTERVAL))
+        );
+        assert_eq!(clock.next_frame(), None);
+
+        // Events in between frames don't move the next frame earlier.
+        let now = start + Duration::from_millis(5);
+        assert_eq!(clock.schedule(now, INTERVAL), start + INTERVAL);
+        assert_eq!(clock.take_due(now), None);
+        assert_eq!(
+            clock.take_due(start + INTERVAL),
+            Some((start + INTERVAL, INTERVAL))
+        );
+    }
+
+    #[test]
+    fn restarts_after_a_pause() {
+        let start = Instant::now();
+        let mut clock = AnimationClock::default();
+
+        clock.schedule(start, INTERVAL);
+        clock.take_due(start);
+
+        // Nothing animated for a while, so there are no missed frames to
+        // catch up on.
+        let later = start + Duration::from_secs(1);
+        assert_eq!(clock.schedule(later, INTERVAL), later);
+        assert_eq!(clock.take_due(later), Some((later, INTERVAL)));
+    }
+
+    #[test]
+    fn requests_after_a_frame_are_scheduled() {
+        let start = Instant::now();
+        let mut clock = AnimationClock::default();
+
+        // A frame fires with no other requests waiting.
+        clock.schedule(start, INTERVAL);
+        assert!(clock.take_due(start).is_some());
+        assert_eq!(clock.schedule_requests(start, []), None);
+        assert_eq!(clock.next_frame(), None);
+
+        // Then a window requests another frame while redrawing.
+        let now = start + Duration::from_millis(1);
+        let slower = INTERVAL * 2;
+        assert_eq!(
+            clock.schedule_requests(now, [slower, INTERVAL]),
+            Some(start + INTERVAL)
+        );
+        assert_eq!(clock.next_frame(), Some(start + INTERVAL));
+    }
+}
@@ -0,0 +1,256 @@
+//! Replays frame captures (see `plinth::graphics::FrameCapture`) on the CPU,
+//! writing each frame to a PNG file.
+//!
+//! Given the output directory of a previous run with `--diff`, each frame is
+//! compared against the previous run's image and a highlighted diff image is
+//! written for frames that changed. The exit code is non-zero if any frame
+//! changed or failed to replay, for use with `git bisect run`.
+
+use std::{
+    collections::HashMap,
+    error::Error,
+    path::{Path, PathBuf},
+    process::ExitCode,
+    time::Instant,
+};
+
+use geometry::{Extent, Px};
+use plinth::graphics::{AlphaMode, ColorSpace, DebugView, FrameCapture, PixelBuffer, PixelFormat};
+
+const USAGE: &str = "\
+usage: replay [options] <capture>...
+
+options:
+    --out <dir>          where to write frames (default: .)
+    --size <w>x<h>       frame size (default: fit the frame's contents)
+    --diff <dir>         compare against the frames from a previous run
+    --tolerance <n>      ignore channel differences of up to n (default: 0)
+    --debug <view>       draw with a debug view: bounds, overdraw, or wireframe";
+
+struct Options {
+    out: PathBuf,
+    size: Option<Extent<u32, Px>>,
+    diff: Option<PathBuf>,
+    tolerance: u8,
+    debug_view: DebugView,
+    captures: Vec<PathBuf>,
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Options, String> {
+    let mut options = Options {
+        out: PathBuf::from("."),
+        size: None,
+        diff: None,
+        tolerance: 0,
+        debug_view: DebugView::Off,
+        captures: Vec::new(),
+    };
+
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or(format!("missing value for {arg}"));
+
+        match arg.as_str() {
+            "--out" => options.out = value()?.into(),
+            "--diff" => options.diff = Some(value()?.into()),
+            "--size" => {
+                let size = value()?;
+                let (width, height) = size
+                    .split_once('x')
+                    .and_then(|(w, h)| Some((w.parse().ok()?, h.parse().ok()?)))
+                    .ok_or(format!("invalid size {size}"))?;
+                options.size = Some(Extent::new(width, height));
+            }
+            "--tolerance" => {
+                let tolerance = value()?;
+                options.tolerance = tolerance
+                    .parse()
+                    .map_err(|_| format!("invalid tolerance {tolerance}"))?;
+            }
+            "--debug" => {
+                let view = value()?;
+                options.debug_view = match view.as_str() {
+                    "bounds" => DebugView::Bounds,
+                    "overdraw" => DebugView::Overdraw,
+                    "wireframe" => DebugView::Wireframe,
+                    _ => return Err(format!("invalid debug view {view}")),
+                };
+            }
+            "-h" | "--help" => return Err(String::new()),
+            _ if arg.starts_with("--") => return Err(format!("unknown option {arg}")),
+            _ => options.captures.push(arg.into()),
+        }
+    }
+
+    if options.captures.is_empty() {
+        return Err("no captures given".into());
+    }
+
+    // Frames are named after their captures.
+    let mut names = HashMap::new();
+    for capture in &options.captures {
+        let Some(name) = capture.file_stem() else {
+            continue;
+        };
+
+        if let Some(other) = names.insert(name, capture) {
+            return Err(format!(
+                "{} and {} would both be written to {}.png",
+                other.display(),
+                capture.display(),
+                name.to_string_lossy()
+            ));
+        }
+    }
+
+    Ok(options)
+}
+
+fn main() -> ExitCode {
+    let options = match parse_args(std::env::args().skip(1)) {
+        Ok(options) => options,
+        Err(error) => {
+            if !error.is_empty() {
+                eprintln!("error: {error}\n");
+            }
+            eprintln!("{USAGE}");
+            return ExitCode::from(2);
+        }
+    };
+
+    if let Err(error) = std::fs::create_dir_all(&options.out) {
+        eprintln!("error: failed to create {}: {error}", options.out.display());
+        return ExitCode::from(2);
+    }
+
+    let mut all_match = true;
+    for path in &options.captures {
+        match replay(&options, path) {
+            Ok(matches) => all_match &= matches,
+            Err(error) => {
+                eprintln!("{}: error: {error}", path.display());
+                all_match = false;
+            }
+        }
+    }
+
+    if all_match {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    }
+}
+
+/// Replays one capture, returning `false` if it differs from the previous run.
+fn replay(options: &Options, path: &Path) -> Result<bool, Box<dyn Error>> {
+    let capture = FrameCapture::load(path)?;
+
+    let extent = options.size.unwrap_or_else(|| capture.content_extent());
+    if extent.width == 0 || extent.height == 0 {
+        return Err("the frame is empty; set its size with --size".into());
+    }
+
+    let start = Instant::now();
+    let frame = capture.render_debug(extent, options.debug_view);
+    let elapsed = start.elapsed();
+
+    let name = path.file_stem().ok_or("capture has no file name")?;
+
+    // Read before the frame is saved, in case it would overwrite this one.
+    let previous = match &options.diff {
+        Some(diff_dir) => {
+            let previous_path = diff_dir.join(name).with_extension("png");
+            let previous = PixelBuffer::decode(&std::fs::read(&previous_path)?)?;
+            Some((previous_path, previous))
+        }
+        None => None,
+    };
+
+    let frame_path = options.out.join(name).with_extension("png");
+    frame.save(&frame_path)?;
+
+    let stats = capture.stats();
+    println!(
+        "{}: {}x{}, {} draws, {} triangles, {} images ({} KiB), rendered in {:.2?}",
+        path.display(),
+        extent.width,
+        extent.height,
+        stats.draws,
+        stats.triangles,
+        stats.images,
+        stats.image_bytes / 1024,
+        elapsed,
+    );
+
+    let Some((previous_path, previous)) = previous else {
+        return Ok(true);
+    };
+
+    if (previous.width(), previous.height()) != (frame.width(), frame.height()) {
+        println!(
+            "    size changed from {}x{}",
+            previous.width(),
+            previous.height()
+        );
+        return Ok(false);
+    }
+
+    let (num_different, diff) = compare(&frame, &previous, options.tolerance);
+    if num_different == 0 {
+        println!("    matches {}", previous_path.display());
+        return Ok(true);
+    }
+
+    let mut diff_name = name.to_owned();
+    diff_name.push(".diff.png");
+    let diff_path = options.out.join(diff_name);
+    diff.save(&diff_path)?;
+
+    println!(
+        "    {num_different} pixels differ from {}, see {}",
+        previous_path.display(),
+        diff_path.display()
+    );
+
+    Ok(false)
+}
+
+/// Counts the pixels that differ by more than `tolerance` in any channel, and
+/// draws them in red over a faded copy of `frame`.
+fn compare(frame: &PixelBuffer, previous: &PixelBuffer, tolerance: u8) -> (usize, PixelBuffer) {
+    let frame = straight_rgba8(frame);
+    let previous = straight_rgba8(previous);
+
+    let mut num_different = 0;
+    let mut diff = Vec::with_capacity(frame.bytes().len());
+
+    for (a, b) in frame
+        .bytes()
+        .chunks_exact(4)
+        .zip(previous.bytes().chunks_exact(4))
+    {
+        if a.iter().zip(b).any(|(a, b)| a.abs_diff(*b) > tolerance) {
+            num_different += 1;
+            diff.extend_from_slice(&[255, 0, 0, 255]);
+        } else {
+            diff.extend(a[..3].iter().map(|c| c / 4 + 191));
+            diff.push(255);
+        }
+    }
+
+    let diff = PixelBuffer::from_bytes(&diff, frame.width(), PixelFormat::Rgba8, ColorSpace::Srgb)
+        .with_alpha_mode(AlphaMode::Opaque);
+    (num_different, diff)
+}
+
+fn straight_rgba8(pixels: &PixelBuffer) -> PixelBuffer {
+    let mut converted = PixelBuffer::new(
+        pixels.width(),
+        pixels.height(),
+        PixelFormat::Rgba8,
+        pixels.color_space(),
+    )
+    .with_alpha_mode(AlphaMode::Straight);
+
+    converted.as_mut().copy_from(pixels.as_ref());
+    converted
+}
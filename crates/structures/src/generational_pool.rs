@@ -12,7 +12,10 @@ use self::inline_free_list::{InlineFreeList, Item, ItemRef};
 /// It is typed for a modicum of safety, but it is still possible to use the
 /// handle manipulate objects in a different pool of the same type, which is
 /// undefined behavior!
-pub struct Handle<T>(NonZeroU64, PhantomData<T>);
+///
+/// Handles don't own a `T`, so they can be sent between threads whatever `T`
+/// is.
+pub struct Handle<T>(NonZeroU64, PhantomData<fn() -> T>);
 
 impl<T> Clone for Handle<T> {
     fn clone(&self) -> Self {